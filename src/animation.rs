@@ -0,0 +1,321 @@
+use crate::{
+    camera::Camera,
+    hitable::World,
+    renderer::{render, RenderSettings},
+    vec3::Vec3,
+};
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// The camera parameters at one frame of an animation, as given to `Camera::new`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    pub frame: f32,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f32,
+    pub focus_dist: f32,
+    pub aperture: f32,
+}
+
+impl CameraKeyframe {
+    fn to_values(self) -> [f32; 9] {
+        let (from, at) = (self.lookfrom, self.lookat);
+        [
+            from.x,
+            from.y,
+            from.z,
+            at.x,
+            at.y,
+            at.z,
+            self.vfov,
+            self.focus_dist,
+            self.aperture,
+        ]
+    }
+
+    fn from_values(frame: f32, v: [f32; 9]) -> CameraKeyframe {
+        CameraKeyframe {
+            frame,
+            lookfrom: Vec3::new(v[0], v[1], v[2]),
+            lookat: Vec3::new(v[3], v[4], v[5]),
+            vfov: v[6],
+            focus_dist: v[7],
+            aperture: v[8],
+        }
+    }
+}
+
+/// Camera parameters keyed at some frames and interpolated in between by Catmull-Rom splines,
+/// which pass through every keyframe and change speed smoothly. Before the first and after the
+/// last keyframe the camera stands still.
+#[derive(Clone, Debug)]
+pub struct CameraAnimation {
+    keyframes: Vec<CameraKeyframe>,
+    vup: Vec3,
+}
+
+impl CameraAnimation {
+    /// Panics without keyframes, or with two keyframes at the same frame.
+    pub fn new(mut keyframes: Vec<CameraKeyframe>, vup: Vec3) -> CameraAnimation {
+        assert!(!keyframes.is_empty(), "no camera keyframes");
+        keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        assert!(
+            keyframes.windows(2).all(|k| k[0].frame < k[1].frame),
+            "two camera keyframes at the same frame"
+        );
+        CameraAnimation { keyframes, vup }
+    }
+
+    pub fn load(path: &Path) -> io::Result<CameraAnimation> {
+        CameraAnimation::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads keyframes from text, a line per keyframe with its frame number, lookfrom, lookat,
+    /// vfov, focus distance and aperture, separated by whitespace:
+    ///
+    /// ```text
+    /// # frame  lookfrom   lookat  vfov  focus  aperture
+    /// 0        16 2 4     0 0 0   15    16.6   0.2
+    /// 48       4 2 16     0 0 0   20    16.6   0.0
+    /// ```
+    ///
+    /// `#` starts a comment. An optional line `vup x y z` sets the up direction, which is +y by
+    /// default.
+    pub fn parse(text: &str) -> io::Result<CameraAnimation> {
+        let mut keyframes = Vec::new();
+        let mut vup = Vec3::new(0.0, 1.0, 0.0);
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (is_vup, numbers) = match line.strip_prefix("vup") {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let values = numbers
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid_data(&format!("invalid number in keyframe {line:?}")))?;
+            // parse accepts nan and inf, which no keyframe can use
+            if values.iter().any(|v| !v.is_finite()) {
+                return Err(invalid_data(&format!(
+                    "invalid number in keyframe {line:?}"
+                )));
+            }
+            match values[..] {
+                [x, y, z] if is_vup => vup = Vec3::new(x, y, z),
+                [frame, ref rest @ ..] if !is_vup && rest.len() == 9 => {
+                    let mut v = [0.0; 9];
+                    v.copy_from_slice(rest);
+                    keyframes.push(CameraKeyframe::from_values(frame, v));
+                }
+                _ => return Err(invalid_data(&format!("wrong number of values in {line:?}"))),
+            }
+        }
+        if keyframes.is_empty() {
+            return Err(invalid_data("no camera keyframes"));
+        }
+        let mut frames: Vec<f32> = keyframes.iter().map(|k| k.frame).collect();
+        frames.sort_by(f32::total_cmp);
+        if frames.windows(2).any(|f| f[0] == f[1]) {
+            return Err(invalid_data("two camera keyframes at the same frame"));
+        }
+        Ok(CameraAnimation::new(keyframes, vup))
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// The frames from the first to the last keyframe.
+    pub fn frames(&self) -> RangeInclusive<u32> {
+        let first = self.keyframes[0].frame.ceil().max(0.0) as u32;
+        let last = self.keyframes[self.keyframes.len() - 1]
+            .frame
+            .floor()
+            .max(0.0) as u32;
+        first..=last
+    }
+
+    // The rate of change at keyframe `i`, from its neighbours.
+    fn tangent(&self, i: usize) -> [f32; 9] {
+        let before = self.keyframes[i.saturating_sub(1)];
+        let after = self.keyframes[(i + 1).min(self.keyframes.len() - 1)];
+        let (a, b) = (before.to_values(), after.to_values());
+        let dt = after.frame - before.frame;
+        let mut tangent = [0.0; 9];
+        for (t, (a, b)) in tangent.iter_mut().zip(a.iter().zip(&b)) {
+            *t = (b - a) / dt;
+        }
+        tangent
+    }
+
+    /// The camera parameters at `frame`, which need not be a whole number.
+    pub fn keyframe_at(&self, frame: f32) -> CameraKeyframe {
+        let last = self.keyframes.len() - 1;
+        if self.keyframes.len() == 1 || frame <= self.keyframes[0].frame {
+            return CameraKeyframe {
+                frame,
+                ..self.keyframes[0]
+            };
+        }
+        if frame >= self.keyframes[last].frame {
+            return CameraKeyframe {
+                frame,
+                ..self.keyframes[last]
+            };
+        }
+        let i = self.keyframes.partition_point(|k| k.frame <= frame) - 1;
+        let (k0, k1) = (self.keyframes[i], self.keyframes[i + 1]);
+        let (m0, m1) = (self.tangent(i), self.tangent(i + 1));
+        let h = k1.frame - k0.frame;
+        let s = (frame - k0.frame) / h;
+        // cubic Hermite basis
+        let h00 = (2.0 * s - 3.0) * s * s + 1.0;
+        let h10 = ((s - 2.0) * s + 1.0) * s;
+        let h01 = (3.0 - 2.0 * s) * s * s;
+        let h11 = (s - 1.0) * s * s;
+        let (p0, p1) = (k0.to_values(), k1.to_values());
+        let mut values = [0.0; 9];
+        for (j, value) in values.iter_mut().enumerate() {
+            *value = h00 * p0[j] + h10 * h * m0[j] + h01 * p1[j] + h11 * h * m1[j];
+        }
+        CameraKeyframe::from_values(frame, values)
+    }
+
+    /// The camera at `frame`, for an image with the given aspect ratio.
+    pub fn camera_at(&self, frame: f32, aspect: f32) -> Camera {
+        let k = self.keyframe_at(frame);
+        Camera::new(
+            k.lookfrom,
+            k.lookat,
+            self.vup,
+            k.vfov,
+            aspect,
+            k.aperture.max(0.0),
+            k.focus_dist,
+        )
+    }
+}
+
+/// The file name of `frame`: the last run of `#` in `pattern` is replaced by the frame number,
+/// padded with zeros to the length of the run. Without `#`, four digits go before the
+/// extension.
+pub fn frame_path(pattern: &str, frame: u32) -> PathBuf {
+    let Some(end) = pattern.rfind('#') else {
+        let path = Path::new(pattern);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let name = match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!("{stem}{frame:04}.{extension}"),
+            None => format!("{stem}{frame:04}"),
+        };
+        return path.with_file_name(name);
+    };
+    let start = pattern[..end].trim_end_matches('#').len();
+    let width = end + 1 - start;
+    PathBuf::from(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &pattern[end + 1..],
+        width = width
+    ))
+}
+
+/// Renders `frames` of the animation to PPM files named after `pattern`, see `frame_path`.
+/// Splitting the frames of an animation into ranges lets several machines share the work.
+/// `on_frame` is called with each frame and its file once written.
+pub fn render_sequence<F: FnMut(u32, &Path)>(
+    settings: &RenderSettings,
+    animation: &CameraAnimation,
+    world: &World,
+    frames: RangeInclusive<u32>,
+    pattern: &str,
+    mut on_frame: F,
+) -> io::Result<()> {
+    for frame in frames {
+        let camera = animation.camera_at(frame as f32, settings.aspect());
+        let path = frame_path(pattern, frame);
+        render(settings, &camera, world).write_ppm(&path)?;
+        on_frame(frame, &path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame_path, CameraAnimation, CameraKeyframe};
+    use crate::vec3::Vec3;
+    use std::path::PathBuf;
+
+    const TEXT: &str = "
+# frame  lookfrom   lookat  vfov  focus  aperture
+vup 0 1 0
+0        0 0 10     0 0 0   40    10     0.1
+10       10 0 0     0 0 0   60    10     0.1
+20       0 0 -10    0 0 0   40    10     0.1
+";
+
+    #[test]
+    fn interpolates_keyframes() {
+        let animation = CameraAnimation::parse(TEXT).unwrap();
+        assert_eq!(animation.keyframes().len(), 3);
+        assert_eq!(animation.frames(), 0..=20);
+        // the spline passes through the keyframes, and holds still outside of them
+        for k in animation.keyframes() {
+            assert_eq!(animation.keyframe_at(k.frame), *k);
+        }
+        assert_eq!(
+            animation.keyframe_at(-5.0).lookfrom,
+            Vec3::new(0.0, 0.0, 10.0)
+        );
+        assert_eq!(animation.keyframe_at(25.0).vfov, 40.0);
+        // halfway between the first two, the path bulges out towards the circle they lie on
+        let k = animation.keyframe_at(5.0);
+        assert!(k.lookfrom.length() > 0.5 * 200f32.sqrt());
+        assert!(k.vfov > 50.0 && k.vfov < 60.0);
+        assert_eq!(k.focus_dist, 10.0);
+
+        // with two keyframes the motion is linear
+        let key = |frame: f32, x: f32| CameraKeyframe {
+            frame,
+            lookfrom: Vec3::new(x, 0.0, 0.0),
+            lookat: Vec3::zeros(),
+            vfov: 40.0,
+            focus_dist: 1.0,
+            aperture: 0.0,
+        };
+        let linear = CameraAnimation::new(vec![key(4.0, 2.0), key(0.0, 0.0)], Vec3::zeros());
+        assert!((linear.keyframe_at(1.0).lookfrom.x - 0.5).abs() < 1e-6);
+
+        assert!(CameraAnimation::parse("").is_err());
+        assert!(CameraAnimation::parse("0 1 2 3").is_err());
+        assert!(CameraAnimation::parse("0 0 0 1 0 0 0 40 1 0\n0 0 0 2 0 0 0 40 1 0").is_err());
+        assert!(CameraAnimation::parse("nan 0 0 1 0 0 0 40 1 0\nnan 0 0 2 0 0 0 40 1 0").is_err());
+        assert!(CameraAnimation::parse("0 0 0 1 0 0 0 40 1 0\ninf 0 0 2 0 0 0 40 1 0").is_err());
+    }
+
+    #[test]
+    fn names_frames() {
+        assert_eq!(
+            frame_path("out/frame_####.ppm", 42),
+            PathBuf::from("out/frame_0042.ppm")
+        );
+        assert_eq!(frame_path("f#.ppm", 123), PathBuf::from("f123.ppm"));
+        assert_eq!(
+            frame_path("out/shot.ppm", 7),
+            PathBuf::from("out/shot0007.ppm")
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    sampling::{Distribution2D, Pcg32},
+    vec3::random_in_unit_disk,
+    vec3::Vec3,
+};
+use rand::prelude::*;
+use std::{
+    f32::consts::PI,
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// The shape of the opening of a lens, which out of focus highlights take on.
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    /// A regular polygon formed by `blades` straight aperture blades, with its corners on the
+    /// circle of the lens. `rotation` turns it counterclockwise, in degrees.
+    Polygon {
+        blades: u32,
+        rotation: f32,
+    },
+    Image(Arc<ApertureImage>),
+}
+
+/// A grayscale image of the opening of a lens, spanning the square around the lens. Brighter
+/// pixels let more light through.
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    distribution: Distribution2D,
+}
+
+impl ApertureImage {
+    /// `values` are the transmission of the pixels, row by row from the top.
+    pub fn new(width: usize, height: usize, values: &[f32]) -> ApertureImage {
+        assert_eq!(values.len(), width * height, "wrong aperture image size");
+        assert!(
+            values.iter().any(|&v| v > 0.0),
+            "the aperture image is black"
+        );
+        ApertureImage {
+            width,
+            height,
+            distribution: Distribution2D::new(values, width, height),
+        }
+    }
+
+    pub fn load_pgm(path: &Path) -> io::Result<ApertureImage> {
+        let (width, height, values) = read_pgm(&fs::read(path)?)?;
+        if !values.iter().any(|&v| v > 0.0) {
+            return Err(invalid_data("the aperture image is black"));
+        }
+        Ok(ApertureImage::new(width, height, &values))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+/// Reads a plain (P2) or binary (P5) PGM graymap, returning its width, height and values
+/// scaled to [0, 1].
+pub fn read_pgm(data: &[u8]) -> io::Result<(usize, usize, Vec<f32>)> {
+    // the header is four whitespace separated tokens, with comments from # to the end of line
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while tokens.len() < 4 {
+        while pos < data.len() && (data[pos].is_ascii_whitespace() || data[pos] == b'#') {
+            if data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid_data("unexpected end of PGM header"));
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let number = |token: &str| {
+        token
+            .parse::<usize>()
+            .map_err(|_| invalid_data("bad number in PGM header"))
+    };
+    let (width, height, max) = (
+        number(&tokens[1])?,
+        number(&tokens[2])?,
+        number(&tokens[3])?,
+    );
+    if max == 0 || max > 65535 {
+        return Err(invalid_data("bad PGM maximum value"));
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid_data("PGM image is empty"));
+    }
+    let count = width
+        .checked_mul(height)
+        .ok_or_else(|| invalid_data("PGM image is too large"))?;
+    let raw: Vec<usize> = match tokens[0].as_str() {
+        "P2" => String::from_utf8_lossy(&data[pos..])
+            .split_whitespace()
+            .take(count)
+            .map(number)
+            .collect::<io::Result<_>>()?,
+        "P5" => {
+            // a single whitespace character separates the header from the pixels
+            let body = data.get(pos + 1..).unwrap_or(&[]);
+            if max < 256 {
+                body.iter().take(count).map(|&b| usize::from(b)).collect()
+            } else {
+                body.chunks_exact(2)
+                    .take(count)
+                    .map(|b| usize::from(u16::from_be_bytes([b[0], b[1]])))
+                    .collect()
+            }
+        }
+        _ => return Err(invalid_data("not a PGM file")),
+    };
+    if raw.len() < count {
+        return Err(invalid_data("PGM file is truncated"));
+    }
+    let values = raw.iter().map(|&v| v as f32 / max as f32).collect();
+    Ok((width, height, values))
+}
+
+impl Aperture {
+    /// A point on the aperture of a lens of radius one, distributed in proportion to the light
+    /// let through, so that no sample is wasted.
+    pub fn sample(&self, rng: &mut Pcg32) -> Vec3 {
+        match self {
+            Aperture::Circle => random_in_unit_disk(rng),
+            Aperture::Polygon { blades, rotation } => {
+                // each blade edge closes a triangle with the centre, all of the same area
+                let blades = (*blades).max(3);
+                let edge = (rng.gen::<f32>() * blades as f32) as u32 % blades;
+                let corner = |k: u32| {
+                    let angle = rotation.to_radians() + 2.0 * PI * k as f32 / blades as f32;
+                    Vec3::new(angle.cos(), angle.sin(), 0.0)
+                };
+                let s = rng.gen::<f32>().sqrt();
+                let t = rng.gen::<f32>();
+                s * ((1.0 - t) * corner(edge) + t * corner(edge + 1))
+            }
+            Aperture::Image(image) => {
+                let ((u, v), _) = image
+                    .distribution
+                    .sample_continuous(rng.gen::<f32>(), rng.gen::<f32>());
+                Vec3::new(2.0 * u - 1.0, 1.0 - 2.0 * v, 0.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_pgm, Aperture, ApertureImage};
+    use crate::sampling::Pcg32;
+    use std::sync::Arc;
+
+    #[test]
+    fn polygon_and_image_apertures() {
+        let mut rng = Pcg32::new(0, 0);
+        // a square standing on a corner
+        let square = Aperture::Polygon {
+            blades: 4,
+            rotation: 0.0,
+        };
+        let mut mean = (0.0, 0.0);
+        for _ in 0..4096 {
+            let p = square.sample(&mut rng);
+            assert!(p.x.abs() + p.y.abs() <= 1.0 + 1e-6);
+            mean = (mean.0 + p.x / 4096.0, mean.1 + p.y / 4096.0);
+        }
+        assert!(mean.0.abs() < 0.05 && mean.1.abs() < 0.05);
+
+        // only the top right quarter of the image is open
+        let image = ApertureImage::new(2, 2, &[0.0, 1.0, 0.0, 0.0]);
+        let image = Aperture::Image(Arc::new(image));
+        for _ in 0..256 {
+            let p = image.sample(&mut rng);
+            assert!(p.x >= 0.0 && p.y >= 0.0 && p.x <= 1.0 && p.y <= 1.0);
+        }
+    }
+
+    #[test]
+    fn reads_pgm() {
+        let plain = b"P2\n# a comment\n3 1\n4\n0 2 4\n";
+        assert_eq!(read_pgm(plain).unwrap(), (3, 1, vec![0.0, 0.5, 1.0]));
+        let binary = b"P5 2 1 255\n\x00\xff";
+        assert_eq!(read_pgm(binary).unwrap(), (2, 1, vec![0.0, 1.0]));
+        assert!(read_pgm(b"P5 2 2 255\n\x00").is_err());
+        assert!(read_pgm(b"P6 1 1 255\n\x00\x00\x00").is_err());
+        assert!(read_pgm(b"P2 0 1 255\n").is_err());
+        assert!(read_pgm(b"P5 1 0 255\n").is_err());
+        assert!(read_pgm(b"P2 4294967296 4294967297 255\n0").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{aabb::Aabb, ray::Ray};
+
+const MAX_LEAF_SIZE: usize = 4;
+
+enum BvhNode {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    // the first child directly follows its parent
+    Interior {
+        bounds: Aabb,
+        second_child: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a list of primitives, given by their bounding boxes. It only
+/// stores indices, so it can index any list. Unbounded primitives (None) are tested by every ray.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+fn centroid(bounds: &Aabb) -> crate::vec3::Vec3 {
+    0.5 * (bounds.min + bounds.max)
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Option<Aabb>]) -> Bvh {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, b) in bounds.iter().enumerate() {
+            match b {
+                Some(b) => bounded.push((index, *b)),
+                None => unbounded.push(index),
+            }
+        }
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(bounded.len()),
+            unbounded,
+        };
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded);
+        }
+        bvh
+    }
+
+    fn build(&mut self, primitives: &mut [(usize, Aabb)]) -> usize {
+        let node_index = self.nodes.len();
+        let bounds = primitives
+            .iter()
+            .skip(1)
+            .fold(primitives[0].1, |acc, (_, b)| acc.union(b));
+        if primitives.len() <= MAX_LEAF_SIZE {
+            self.nodes.push(BvhNode::Leaf {
+                bounds,
+                start: self.indices.len(),
+                count: primitives.len(),
+            });
+            self.indices
+                .extend(primitives.iter().map(|(index, _)| *index));
+            return node_index;
+        }
+
+        // split at the median centroid along the largest axis of the centroid bounds
+        let first = centroid(&primitives[0].1);
+        let centroid_bounds = primitives
+            .iter()
+            .fold(Aabb::new(first, first), |acc, (_, b)| {
+                let c = centroid(b);
+                acc.union(&Aabb::new(c, c))
+            });
+        let axis = centroid_bounds.size().largest_axis();
+        primitives.sort_by(|a, b| centroid(&a.1)[axis].total_cmp(&centroid(&b.1)[axis]));
+        let mid = primitives.len() / 2;
+
+        self.nodes.push(BvhNode::Interior {
+            bounds,
+            second_child: 0,
+        });
+        let (left, right) = primitives.split_at_mut(mid);
+        self.build(left);
+        let second = self.build(right);
+        if let BvhNode::Interior { second_child, .. } = &mut self.nodes[node_index] {
+            *second_child = second;
+        }
+        node_index
+    }
+
+    /// Calls `visit` with the index of each primitive whose bounds the ray may hit between `t_min`
+    /// and the current `t_max`. `visit` returns the parameter of a hit, if any, which then
+    /// becomes the new `t_max`.
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        mut t_max: f32,
+        mut visit: impl FnMut(usize, f32) -> Option<f32>,
+    ) {
+        for &index in &self.unbounded {
+            if let Some(t) = visit(index, t_max) {
+                t_max = t;
+            }
+        }
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds().hit(ray, t_min, t_max).is_none() {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { start, count, .. } => {
+                    for &index in &self.indices[*start..*start + *count] {
+                        if let Some(t) = visit(index, t_max) {
+                            t_max = t;
+                        }
+                    }
+                }
+                BvhNode::Interior { second_child, .. } => {
+                    stack.push(*second_child);
+                    stack.push(node_index + 1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bvh;
+    use crate::{aabb::Aabb, ray::Ray, sampling::Pcg32, vec3::Vec3};
+    use rand::prelude::*;
+
+    fn random_point(rng: &mut Pcg32, scale: f32) -> Vec3 {
+        scale
+            * Vec3::new(
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+            )
+    }
+
+    #[test]
+    fn traversal_finds_the_closest_hit() {
+        let mut rng = Pcg32::new(0, 0);
+        // boxes of various sizes as the primitives, and every tenth unbounded
+        let boxes: Vec<Option<Aabb>> = (0..200)
+            .map(|i| {
+                if i % 10 == 0 {
+                    return None;
+                }
+                let min = random_point(&mut rng, 20.0);
+                Some(Aabb::new(
+                    min,
+                    min + random_point(&mut rng, 2.0) + Vec3::ones(),
+                ))
+            })
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        for _ in 0..500 {
+            let ray = Ray::new(
+                random_point(&mut rng, 30.0),
+                random_point(&mut rng, 1.0),
+                0.0,
+            );
+            let brute_force = boxes
+                .iter()
+                .enumerate()
+                .filter_map(|(i, b)| Some((i, b.as_ref()?.hit(&ray, 0.0, f32::MAX)?.0)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let mut closest = None;
+            let mut unbounded_visits = 0;
+            bvh.traverse(&ray, 0.0, f32::MAX, |i, t_max| {
+                let Some(bounds) = &boxes[i] else {
+                    unbounded_visits += 1;
+                    return None;
+                };
+                let (t, _) = bounds.hit(&ray, 0.0, t_max)?;
+                closest = Some((i, t));
+                Some(t)
+            });
+            // boxes overlapping around the origin all hit at t = 0, so compare distances rather
+            // than which box was found
+            assert_eq!(closest.map(|(_, t)| t), brute_force.map(|(_, t)| t));
+            assert_eq!(unbounded_visits, 20);
+        }
+    }
+
+    #[test]
+    fn empty_hierarchy() {
+        let bvh = Bvh::new(&[]);
+        let ray = Ray::new(Vec3::zeros(), Vec3::ones(), 0.0);
+        bvh.traverse(&ray, 0.0, f32::MAX, |_, _| panic!("nothing to visit"));
+    }
+}
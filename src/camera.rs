@@ -1,7 +1,35 @@
-use crate::{ray::Ray, vec3::random_in_unit_disk, vec3::Vec3};
+use crate::{aperture::Aperture, ray::Ray, sampling::Pcg32, vec3::Vec3};
 use rand::prelude::*;
 
-#[derive(Copy, Clone)]
+/// Turns positions on the image into rays, so that the renderer works with any projection.
+pub trait CameraModel: Sync {
+    /// The ray through image position (`u`, `v`), with (0, 0) the bottom left and (1, 1) the
+    /// top right corner of the image. None where the image shows nothing, such as outside the
+    /// image circle of a fisheye lens.
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray>;
+
+    /// Like `make_ray`, along with the weight of the ray's contribution to the image, for
+    /// cameras which dim parts of the image, such as the vignetting of a real lens.
+    fn make_weighted_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<(Ray, f32)> {
+        self.make_ray(rng, u, v).map(|r| (r, 1.0))
+    }
+}
+
+/// The right, up and backward unit vectors of a view from `lookfrom` towards `lookat`.
+pub(crate) fn view_basis(lookfrom: Vec3, lookat: Vec3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (lookfrom - lookat).make_unit_vector();
+    let u = vup.cross(w).make_unit_vector();
+    let v = w.cross(u);
+    (u, v, w)
+}
+
+/// The time of a ray, uniform within the shutter interval.
+pub(crate) fn shutter_time(rng: &mut Pcg32, open: f32, close: f32) -> f32 {
+    open + rng.gen::<f32>() * (close - open)
+}
+
+/// A perspective camera with an optional thin lens for depth of field.
+#[derive(Clone)]
 pub struct Camera {
     eye: Vec3,
     lower_left_corner: Vec3,
@@ -10,6 +38,12 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     lens_radius: f32,
+    aperture: Aperture,
+    // the shift of the lens barrel's opening per unit of image position from the centre, in
+    // lens radii
+    cats_eye: (f32, f32),
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
@@ -29,9 +63,7 @@ impl Camera {
 
         let eye = lookfrom;
 
-        let w = (lookfrom - lookat).make_unit_vector();
-        let u = vup.cross(w).make_unit_vector();
-        let v = w.cross(u);
+        let (u, v, w) = view_basis(lookfrom, lookat, vup);
 
         let lower_left_corner =
             eye - half_width * focus_dist * u - half_height * focus_dist * v - focus_dist * w;
@@ -46,16 +78,192 @@ impl Camera {
             u,
             v,
             lens_radius,
+            aperture: Aperture::Circle,
+            cats_eye: (0.0, 0.0),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
-    pub fn make_ray(&self, rng: &mut ThreadRng, u: f32, v: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
-        let offset = rd.x * self.u + rd.y * self.v;
+    /// Keeps the shutter open from time `open` to `close`, blurring objects which move in
+    /// between. By default the shutter is instantaneous at time zero.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+
+    /// Gives the lens opening a shape other than a circle, which shows in out of focus
+    /// highlights.
+    pub fn with_aperture_shape(mut self, aperture: Aperture) -> Camera {
+        self.aperture = aperture;
+        self
+    }
+
+    /// Clips the lens opening by the barrel of the lens towards the edges of the image, which
+    /// turns out of focus highlights there into cat's eyes and darkens the corners. `amount`
+    /// is how far the barrel's opening is shifted at the corners, in lens radii; at two the
+    /// corners are black.
+    pub fn with_cats_eye(mut self, amount: f32) -> Camera {
+        let (width, height) = (self.horizontal.length(), self.vertical.length());
+        let diagonal = width.hypot(height);
+        self.cats_eye = (amount * width / diagonal, amount * height / diagonal);
+        self
+    }
+
+    /// One eye of a stereo pair, moved `offset` along the right vector (negative for the left
+    /// eye). The eyes keep looking in parallel, but their frustums are shifted so that both
+    /// frame the same window at `convergence` distance, where objects appear at screen depth.
+    pub fn with_eye_offset(mut self, offset: f32, convergence: f32) -> Camera {
+        let w = self.u.cross(self.v);
+        let centre = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+        let focus_dist = (self.eye - centre).dot(w);
+        self.eye += offset * self.u;
+        self.lower_left_corner += (offset * (1.0 - focus_dist / convergence)) * self.u;
+        self
+    }
+}
+
+impl CameraModel for Camera {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        let mut offset = Vec3::zeros();
+        if self.lens_radius > 0.0 {
+            let p = self.aperture.sample(rng);
+            // light which the barrel blocks is lost, rather than sampled elsewhere
+            let barrel_x = p.x - (2.0 * u - 1.0) * self.cats_eye.0;
+            let barrel_y = p.y - (2.0 * v - 1.0) * self.cats_eye.1;
+            if barrel_x * barrel_x + barrel_y * barrel_y > 1.0 {
+                return None;
+            }
+            offset = self.lens_radius * (p.x * self.u + p.y * self.v);
+        }
         let lens_pos = self.eye + offset;
-        Ray::new(
+        let time = shutter_time(rng, self.shutter_open, self.shutter_close);
+        Some(Ray::new(
             lens_pos,
             self.lower_left_corner + u * self.horizontal + v * self.vertical - lens_pos,
+            time,
+        ))
+    }
+}
+
+/// A camera with parallel rays, which keeps sizes independent of the distance to the camera.
+/// The view is `width` by `height` units across, centred on the line from `lookfrom` to
+/// `lookat`. Everything is in focus.
+#[derive(Copy, Clone)]
+pub struct OrthographicCamera {
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        width: f32,
+        height: f32,
+    ) -> OrthographicCamera {
+        let (u, v, w) = view_basis(lookfrom, lookat, vup);
+        OrthographicCamera {
+            lower_left_corner: lookfrom - 0.5 * width * u - 0.5 * height * v,
+            horizontal: width * u,
+            vertical: height * v,
+            direction: -w,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// See `Camera::with_shutter`.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> OrthographicCamera {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+}
+
+impl CameraModel for OrthographicCamera {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        let time = shutter_time(rng, self.shutter_open, self.shutter_close);
+        Some(Ray::new(
+            self.lower_left_corner + u * self.horizontal + v * self.vertical,
+            self.direction,
+            time,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Camera, CameraModel, OrthographicCamera};
+    use crate::{aperture::Aperture, sampling::Pcg32, vec3::Vec3};
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = OrthographicCamera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::zeros(),
+            Vec3::new(0.0, 1.0, 0.0),
+            4.0,
+            2.0,
+        );
+        let mut rng = Pcg32::new(0, 0);
+        let corner = camera.make_ray(&mut rng, 0.0, 0.0).unwrap();
+        let centre = camera.make_ray(&mut rng, 0.5, 0.5).unwrap();
+        let right = camera.make_ray(&mut rng, 1.0, 0.5).unwrap();
+        assert_eq!(corner.origin, Vec3::new(-2.0, -1.0, 5.0));
+        assert_eq!(centre.origin, Vec3::new(0.0, 0.0, 5.0));
+        assert_eq!(right.origin, Vec3::new(2.0, 0.0, 5.0));
+        assert_eq!(corner.direction, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(centre.direction, right.direction);
+
+        // the perspective camera, for comparison, spreads out from the eye
+        let camera = Camera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::zeros(),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            2.0,
+            0.0,
+            1.0,
+        );
+        let right = camera.make_ray(&mut rng, 1.0, 0.5).unwrap();
+        assert_eq!(right.origin, Vec3::new(0.0, 0.0, 5.0));
+        assert!(right.direction.x > 0.0);
+    }
+
+    #[test]
+    fn shaped_and_clipped_apertures() {
+        let camera = Camera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            2.0,
+            1.0,
         )
+        .with_aperture_shape(Aperture::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        })
+        .with_cats_eye(1.0);
+        let mut rng = Pcg32::new(0, 0);
+        // the flat top of the hexagon is at sin(60) of the lens radius
+        let centre: Vec<_> = (0..1024)
+            .map(|_| camera.make_ray(&mut rng, 0.5, 0.5).unwrap().origin)
+            .collect();
+        assert!(centre.iter().all(|p| p.y.abs() <= 0.75f32.sqrt() + 1e-5));
+        assert!(centre.iter().any(|p| p.x.abs() > 0.9));
+        // towards the corner, the barrel blocks part of the lens
+        let corner = (0..1024)
+            .filter_map(|_| camera.make_ray(&mut rng, 1.0, 1.0))
+            .count();
+        assert!(corner > 0 && corner < 900);
     }
 }
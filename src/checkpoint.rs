@@ -0,0 +1,197 @@
+use crate::{
+    renderer::{Framebuffer, RenderSettings},
+    sampling::Pcg32,
+    vec3::Vec3,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"RTCHECK1";
+// the sum, sample count and generator state of a pixel
+const PIXEL_BYTES: usize = 3 * 4 + 4 + 2 * 8;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// The progress of one pixel: the sum of its samples so far, and the generator which draws the
+// next ones.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct PixelState {
+    pub(crate) sum: Vec3,
+    pub(crate) samples: u32,
+    pub(crate) rng: Pcg32,
+}
+
+/// The accumulated samples of a render in progress. Every pixel draws its samples from its own
+/// generator, so a render resumed from a checkpoint continues exactly where it stopped, and
+/// ends up the same as one that was never interrupted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    width: usize,
+    height: usize,
+    seed: u64,
+    pub(crate) pixels: Vec<PixelState>,
+}
+
+impl Checkpoint {
+    /// The start of a render with `settings`, without any samples.
+    pub fn new(settings: &RenderSettings) -> Checkpoint {
+        let (width, height) = (settings.width, settings.height);
+        let pixels = (0..width * height)
+            .map(|i| PixelState {
+                sum: Vec3::zeros(),
+                samples: 0,
+                rng: Pcg32::new(settings.seed, i as u64),
+            })
+            .collect();
+        Checkpoint {
+            width,
+            height,
+            seed: settings.seed,
+            pixels,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Checkpoint> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0; 32];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(invalid_data("not a render checkpoint"));
+        }
+        let number = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[i..i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let (width, height) = (number(8) as usize, number(16) as usize);
+        let size = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(PIXEL_BYTES))
+            .ok_or_else(|| invalid_data("render checkpoint is too large"))?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        if data.len() != size {
+            return Err(invalid_data("render checkpoint has the wrong size"));
+        }
+        let pixels = data
+            .chunks_exact(PIXEL_BYTES)
+            .map(|bytes| {
+                let f = |i: usize| {
+                    f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+                };
+                let mut state = [0; 8];
+                let mut increment = [0; 8];
+                state.copy_from_slice(&bytes[16..24]);
+                increment.copy_from_slice(&bytes[24..32]);
+                PixelState {
+                    sum: Vec3::new(f(0), f(4), f(8)),
+                    samples: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+                    rng: Pcg32::from_state(
+                        u64::from_le_bytes(state),
+                        u64::from_le_bytes(increment),
+                    ),
+                }
+            })
+            .collect();
+        Ok(Checkpoint {
+            width,
+            height,
+            seed: number(24),
+            pixels,
+        })
+    }
+
+    /// Writes the checkpoint to `path`. The file is replaced only once the new one is complete,
+    /// so that a crash while saving leaves the previous checkpoint intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut file = BufWriter::new(File::create(&partial)?);
+        file.write_all(MAGIC)?;
+        for &number in &[self.width as u64, self.height as u64, self.seed] {
+            file.write_all(&number.to_le_bytes())?;
+        }
+        for pixel in &self.pixels {
+            for &x in &[pixel.sum.x, pixel.sum.y, pixel.sum.z] {
+                file.write_all(&x.to_le_bytes())?;
+            }
+            file.write_all(&pixel.samples.to_le_bytes())?;
+            let (state, increment) = pixel.rng.state();
+            file.write_all(&state.to_le_bytes())?;
+            file.write_all(&increment.to_le_bytes())?;
+        }
+        file.into_inner()?.sync_all()?;
+        fs::rename(&partial, path)
+    }
+
+    /// Whether the checkpoint belongs to a render with `settings`, apart from the number of
+    /// samples.
+    pub fn matches(&self, settings: &RenderSettings) -> bool {
+        (self.width, self.height, self.seed) == (settings.width, settings.height, settings.seed)
+    }
+
+    /// The number of samples taken in the pixel in column `x` and row `y` from the top.
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x].samples
+    }
+
+    /// The image so far, each pixel the average of its samples. Pixels without samples are
+    /// black.
+    pub fn framebuffer(&self) -> Framebuffer {
+        let mut image = Framebuffer::new(self.width, self.height);
+        for (c, pixel) in image.pixels.iter_mut().zip(&self.pixels) {
+            *c = pixel.average();
+        }
+        image
+    }
+}
+
+impl PixelState {
+    pub(crate) fn average(&self) -> Vec3 {
+        (1.0 / self.samples.max(1) as f32) * self.sum
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Checkpoint;
+    use crate::{renderer::RenderSettings, vec3::Vec3};
+    use rand::prelude::*;
+
+    #[test]
+    fn saves_and_loads() {
+        let settings = RenderSettings::new(3, 2, 4).with_seed(7);
+        let mut checkpoint = Checkpoint::new(&settings);
+        checkpoint.pixels[4].sum = Vec3::new(1.0, 2.5, -3.0);
+        checkpoint.pixels[4].samples = 2;
+        checkpoint.pixels[4].rng.gen::<f32>();
+        assert_eq!(checkpoint.samples(1, 1), 2);
+        assert_eq!(
+            checkpoint.framebuffer().pixel(1, 1),
+            Vec3::new(0.5, 1.25, -1.5)
+        );
+        assert!(checkpoint.matches(&settings.with_max_depth(2)));
+        assert!(!checkpoint.matches(&settings.with_seed(8)));
+
+        let path = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path);
+        std::fs::write(&path, b"RTCHECK1 but cut short").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        // a header whose size overflows
+        let mut header = b"RTCHECK1".to_vec();
+        for number in [u64::MAX, 2, 7] {
+            header.extend_from_slice(&number.to_le_bytes());
+        }
+        std::fs::write(&path, header).unwrap();
+        let overflow = Checkpoint::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), checkpoint);
+        assert!(overflow.unwrap_err().to_string().contains("too large"));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::vec3::Vec3;
+
+/// Converts CIE XYZ to linear sRGB.
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.240_6 * xyz.x - 1.537_2 * xyz.y - 0.498_6 * xyz.z,
+        -0.968_9 * xyz.x + 1.875_8 * xyz.y + 0.041_5 * xyz.z,
+        0.055_7 * xyz.x - 0.204_0 * xyz.y + 1.057_0 * xyz.z,
+    )
+}
+
+pub fn luminance(rgb: Vec3) -> f32 {
+    0.212_6 * rgb.x + 0.715_2 * rgb.y + 0.072_2 * rgb.z
+}
+
+// Piecewise gaussian used in the Wyman, Sloan & Shirley fit of the CIE 1931 colour matching
+// functions.
+fn piecewise_gaussian(x: f32, mu: f32, sigma_left: f32, sigma_right: f32) -> f32 {
+    let sigma = if x < mu { sigma_left } else { sigma_right };
+    let t = (x - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+fn cie_xyz(wavelength_nm: f32) -> Vec3 {
+    let l = wavelength_nm;
+    Vec3::new(
+        1.056 * piecewise_gaussian(l, 599.8, 37.9, 31.0)
+            + 0.362 * piecewise_gaussian(l, 442.0, 16.0, 26.7)
+            - 0.065 * piecewise_gaussian(l, 501.1, 20.4, 26.2),
+        0.821 * piecewise_gaussian(l, 568.8, 46.9, 40.5)
+            + 0.286 * piecewise_gaussian(l, 530.9, 16.3, 31.1),
+        1.217 * piecewise_gaussian(l, 437.0, 11.8, 36.0)
+            + 0.681 * piecewise_gaussian(l, 459.0, 26.0, 13.8),
+    )
+}
+
+// Spectral radiance of a black body, up to a constant factor.
+fn planck(wavelength_nm: f32, temperature: f32) -> f32 {
+    // second radiation constant hc/k in nm K
+    const C2: f32 = 1.438_777e7;
+    let l = wavelength_nm / 1000.0;
+    1.0 / (l.powi(5) * ((C2 / (wavelength_nm * temperature)).exp() - 1.0))
+}
+
+/// The linear sRGB colour of a black body at `temperature` Kelvin, normalized to unit
+/// luminance.
+pub fn blackbody(temperature: f32) -> Vec3 {
+    if temperature <= 0.0 {
+        return Vec3::zeros();
+    }
+    let mut xyz = Vec3::zeros();
+    let mut wavelength = 380.0;
+    while wavelength <= 780.0 {
+        xyz += planck(wavelength, temperature) * cie_xyz(wavelength);
+        wavelength += 5.0;
+    }
+    let rgb = xyz_to_rgb((1.0 / xyz.y) * xyz);
+    Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{blackbody, luminance};
+
+    #[test]
+    fn blackbody_hue() {
+        let candle = blackbody(1900.0);
+        let daylight = blackbody(6500.0);
+        let sky = blackbody(12000.0);
+        assert!(candle.x > candle.z);
+        assert!((daylight.x - daylight.z).abs() < 0.15);
+        assert!(sky.z > sky.x);
+        assert!((luminance(daylight) - 1.0).abs() < 0.05);
+    }
+}
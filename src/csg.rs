@@ -0,0 +1,183 @@
+use crate::{
+    aabb::Aabb,
+    hitable::{HitRecord, Hitable},
+    ray::Ray,
+    sampling::Pcg32,
+    vec3::Vec3,
+};
+
+// Upper bound on the number of boundary crossings followed along a single ray, per operand.
+const MAX_CROSSINGS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    // the left operand with the right one carved out of it
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOperation::Union => left || right,
+            CsgOperation::Intersection => left && right,
+            CsgOperation::Difference => left && !right,
+        }
+    }
+}
+
+/// A boolean combination of two closed solids with outward facing normals, which may themselves
+/// be combinations. Surfaces of the result keep the material of the operand they come from.
+#[derive(Clone)]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<Hitable>,
+    pub right: Box<Hitable>,
+}
+
+// Every boundary crossing of the solid along the ray up to the first one past `t_max`, in
+// order. Each is an entry if the ray runs against its normal, otherwise an exit.
+fn crossings<'obj>(
+    solid: &'obj Hitable,
+    ray: &Ray,
+    t_max: f32,
+    rng: &mut Pcg32,
+) -> Vec<HitRecord<'obj>> {
+    let mut crossings = Vec::new();
+    let mut t = -f32::MAX;
+    for _ in 0..MAX_CROSSINGS {
+        let Some(hit) = solid.hit(ray, t, f32::MAX, rng) else {
+            break;
+        };
+        crossings.push(hit);
+        if hit.t >= t_max {
+            break;
+        }
+        t = hit.t + 0.0001;
+    }
+    crossings
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Hitable, right: Hitable) -> Csg {
+        Csg {
+            operation,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Walks the entry and exit points of both operands in order, tracking whether the ray is
+    /// inside each of them, and returns the first point where that changes whether it is inside
+    /// the result.
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        let left = crossings(&self.left, ray, t_max, rng);
+        let right = crossings(&self.right, ray, t_max, rng);
+        let (mut i, mut j) = (0, 0);
+        let mut inside_left = false;
+        let mut inside_right = false;
+        loop {
+            let (hit, is_left) = match (left.get(i), right.get(j)) {
+                (Some(l), Some(r)) if l.t <= r.t => (*l, true),
+                (_, Some(r)) => (*r, false),
+                (Some(l), None) => (*l, true),
+                (None, None) => return None,
+            };
+            if hit.t >= t_max {
+                return None;
+            }
+            let was_inside = self.operation.inside(inside_left, inside_right);
+            let entering = ray.direction.dot(hit.n) < 0.0;
+            if is_left {
+                inside_left = entering;
+                i += 1;
+            } else {
+                inside_right = entering;
+                j += 1;
+            }
+            let is_inside = self.operation.inside(inside_left, inside_right);
+            if is_inside != was_inside && hit.t > t_min {
+                // surfaces of a carved out operand face the wrong way, so orient the normal by
+                // whether the ray enters or leaves the result
+                let n = if is_inside == entering { hit.n } else { -hit.n };
+                return Some(HitRecord { n, ..hit });
+            }
+        }
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.operation {
+            CsgOperation::Union => Some(left?.union(&right?)),
+            CsgOperation::Intersection => match (left, right) {
+                (Some(l), Some(r)) => Some(Aabb::new(
+                    Vec3::new(
+                        l.min.x.max(r.min.x),
+                        l.min.y.max(r.min.y),
+                        l.min.z.max(r.min.z),
+                    ),
+                    Vec3::new(
+                        l.max.x.min(r.max.x),
+                        l.max.y.min(r.max.y),
+                        l.max.z.min(r.max.z),
+                    ),
+                )),
+                (bounds, None) | (None, bounds) => bounds,
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Csg, CsgOperation};
+    use crate::{
+        hitable::{Hitable, Sphere},
+        material::Material,
+        ray::Ray,
+        sampling::Pcg32,
+        vec3::Vec3,
+    };
+
+    fn sphere(x: f32, material: &Material) -> Hitable {
+        Hitable::Sphere(Sphere::new(Vec3::new(x, 0.0, 0.0), 1.0, material.clone()))
+    }
+
+    #[test]
+    fn boolean_operations() {
+        let red = Material::lambertian(Vec3::new(1.0, 0.0, 0.0));
+        let blue = Material::lambertian(Vec3::new(0.0, 0.0, 1.0));
+        let mut rng = Pcg32::new(0, 0);
+        // along the x axis, the left sphere covers [-1, 1] and the right one [0.5, 2.5]
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let combine = |operation| Csg::new(operation, sphere(0.0, &red), sphere(1.5, &blue));
+
+        let union = combine(CsgOperation::Union);
+        let hit = union.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p.x + 1.0).abs() < 1e-4);
+        let exit = union.hit(&ray, hit.t + 0.01, f32::MAX, &mut rng).unwrap();
+        assert!((exit.p.x - 2.5).abs() < 1e-4 && exit.n.x > 0.0);
+
+        let intersection = combine(CsgOperation::Intersection);
+        let hit = intersection.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p.x - 0.5).abs() < 1e-4 && hit.n.x < 0.0);
+        // the entry surface belongs to the right operand
+        assert!(matches!(hit.material, Material::Lambertian(l) if l.albedo.z == 1.0));
+
+        let difference = combine(CsgOperation::Difference);
+        let hit = difference.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p.x + 1.0).abs() < 1e-4);
+        // leaving the result where the carved out sphere begins, facing out of the result
+        let exit = difference
+            .hit(&ray, hit.t + 0.01, f32::MAX, &mut rng)
+            .unwrap();
+        assert!((exit.p.x - 0.5).abs() < 1e-4 && exit.n.x > 0.0);
+        // a ray from the other side only hits the carved surface
+        let back = Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0), 0.0);
+        let hit = difference.hit(&back, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p.x - 0.5).abs() < 1e-4 && hit.n.x > 0.0);
+    }
+}
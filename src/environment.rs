@@ -0,0 +1,359 @@
+use crate::{
+    color::luminance,
+    light::LightSample,
+    sampling::{Distribution2D, Pcg32},
+    sky::Sky,
+    vec3::Vec3,
+};
+use rand::prelude::*;
+use std::{
+    f32::consts::PI,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+// The direction at the (u, v) position of an equirectangular image, rotated around the y axis
+// by `rotation` radians.
+fn equirectangular_direction(u: f32, v: f32, rotation: f32) -> Vec3 {
+    let theta = v * PI;
+    let phi = u * 2.0 * PI + rotation;
+    let sin_theta = theta.sin();
+    Vec3::new(sin_theta * phi.sin(), theta.cos(), -sin_theta * phi.cos())
+}
+
+/// An equirectangular (latitude-longitude) HDR image surrounding the scene. The top row of the
+/// image is straight up (+y), and the centre of the image looks down -z. The map is importance
+/// sampled according to the luminance of its pixels.
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    // rotation around the y axis, in radians
+    rotation: f32,
+    intensity: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// `rotation` turns the map around the vertical axis, in degrees. `intensity` scales the
+    /// radiance of every pixel.
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Vec3>,
+        rotation: f32,
+        intensity: f32,
+    ) -> EnvironmentMap {
+        assert_eq!(pixels.len(), width * height, "wrong environment map size");
+        // weight by sin(theta) to account for the stretching of the rows towards the poles
+        let func: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let theta = PI * ((i / width) as f32 + 0.5) / height as f32;
+                luminance(p) * theta.sin()
+            })
+            .collect();
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: rotation.to_radians(),
+            intensity,
+            distribution: Distribution2D::new(&func, width, height),
+        }
+    }
+
+    /// Tabulates a radiance function of the direction into a map.
+    pub fn from_fn<F: Fn(Vec3) -> Vec3>(
+        width: usize,
+        height: usize,
+        radiance: F,
+    ) -> EnvironmentMap {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let u = ((i % width) as f32 + 0.5) / width as f32;
+                let v = ((i / width) as f32 + 0.5) / height as f32;
+                radiance(equirectangular_direction(u, v, 0.0))
+            })
+            .collect();
+        EnvironmentMap::new(width, height, pixels, 0.0, 1.0)
+    }
+
+    /// Loads a Radiance RGBE (.hdr) image.
+    pub fn load_hdr(path: &Path, rotation: f32, intensity: f32) -> io::Result<EnvironmentMap> {
+        let (width, height, pixels) = read_hdr(&mut BufReader::new(File::open(path)?))?;
+        Ok(EnvironmentMap::new(
+            width, height, pixels, rotation, intensity,
+        ))
+    }
+
+    fn uv_of(&self, direction: Vec3) -> (f32, f32) {
+        let d = direction.make_unit_vector();
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+        let mut phi = d.x.atan2(-d.z) - self.rotation;
+        phi = phi.rem_euclid(2.0 * PI);
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn lookup(&self, u: f32, v: f32) -> Vec3 {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.intensity * self.pixels[y * self.width + x]
+    }
+
+    /// The radiance integrated over the sphere, with pixel luminance standing in for radiance.
+    pub fn power(&self) -> f32 {
+        self.intensity * 2.0 * PI * PI * self.distribution.integral()
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = self.uv_of(direction);
+        self.lookup(u, v)
+    }
+
+    pub fn sample(&self, rng: &mut Pcg32) -> Option<LightSample> {
+        let ((u, v), map_pdf) = self
+            .distribution
+            .sample_continuous(rng.gen::<f32>(), rng.gen::<f32>());
+        let sin_theta = (v * PI).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: equirectangular_direction(u, v, self.rotation),
+            distance: f32::MAX,
+            radiance: self.lookup(u, v),
+            // change of variables from the unit square to the sphere
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let (u, v) = self.uv_of(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// What a ray sees when it leaves the scene.
+#[derive(Clone)]
+pub enum Background {
+    // The classic white to blue gradient.
+    Gradient,
+    EnvironmentMap(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Background::Gradient => {
+                let unit_direction = direction.make_unit_vector();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0)
+            }
+            Background::EnvironmentMap(map) => map.radiance(direction),
+            Background::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Samples a direction towards the background for direct lighting. Returns None if the
+    /// background can only be hit by chance.
+    pub fn sample(&self, rng: &mut Pcg32) -> Option<LightSample> {
+        match self {
+            Background::Gradient => None,
+            Background::EnvironmentMap(map) => map.sample(rng),
+            Background::Sky(sky) => sky.sample(rng),
+        }
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        match self {
+            Background::Gradient => 0.0,
+            Background::EnvironmentMap(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn rgbe_to_rgb(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::zeros();
+    }
+    let scale = 2f32.powi(i32::from(rgbe[3]) - (128 + 8));
+    Vec3::new(
+        (f32::from(rgbe[0]) + 0.5) * scale,
+        (f32::from(rgbe[1]) + 0.5) * scale,
+        (f32::from(rgbe[2]) + 0.5) * scale,
+    )
+}
+
+// Reads one scanline, which is either run-length encoded per channel or stored flat.
+fn read_scanline<R: Read>(reader: &mut R, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let mut head = [0u8; 4];
+    reader.read_exact(&mut head)?;
+    let encoded_width = (usize::from(head[2]) << 8) | usize::from(head[3]);
+    if head[0] != 2 || head[1] != 2 || head[2] & 0x80 != 0 || !(8..0x8000).contains(&width) {
+        // flat scanlines grow as their pixels are read, so a bogus width can't allocate much
+        let mut scanline = vec![head];
+        for _ in 1..width {
+            let mut pixel = [0u8; 4];
+            reader.read_exact(&mut pixel)?;
+            scanline.push(pixel);
+        }
+        return Ok(scanline);
+    }
+    let mut scanline = vec![[0u8; 4]; width];
+    if encoded_width != width {
+        return Err(invalid_data("scanline width mismatch"));
+    }
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let count = usize::from(count[0]);
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return Err(invalid_data("bad scanline run"));
+                }
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + run] {
+                    pixel[channel] = value[0];
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad scanline run"));
+                }
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(scanline)
+}
+
+/// Reads a Radiance RGBE image with the standard -Y +X orientation, returning its width, height
+/// and linear pixels.
+pub fn read_hdr<R: BufRead>(reader: &mut R) -> io::Result<(usize, usize, Vec<Vec3>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of HDR header"));
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some(format) = trimmed.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_data("unsupported HDR pixel format"));
+            }
+        }
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match tokens.as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>()
+                .map_err(|_| invalid_data("bad HDR height"))?,
+            w.parse::<usize>()
+                .map_err(|_| invalid_data("bad HDR width"))?,
+        ),
+        _ => return Err(invalid_data("unsupported HDR orientation")),
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid_data("HDR image is empty"));
+    }
+    if width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(std::mem::size_of::<Vec3>()))
+        .is_none()
+    {
+        return Err(invalid_data("HDR image is too large"));
+    }
+    // the pixels grow by scanline rather than trusting the size up front
+    let mut pixels = Vec::new();
+    for _ in 0..height {
+        pixels.extend(read_scanline(reader, width)?.into_iter().map(rgbe_to_rgb));
+    }
+    Ok((width, height, pixels))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_hdr, EnvironmentMap};
+    use crate::sampling::Pcg32;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn read_flat_and_rle_hdr() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // a run-length encoded scanline: every pixel is (128, 64, 0, 129) = (1.0, 0.5, 0.0)
+        file.extend_from_slice(&[2, 2, 0, 8]);
+        file.extend_from_slice(&[136, 128, 136, 64, 136, 0, 136, 129]);
+        // a flat scanline of literal pixels
+        for _ in 0..8 {
+            file.extend_from_slice(&[0, 0, 128, 130]);
+        }
+        let (width, height, pixels) = read_hdr(&mut file.as_slice()).unwrap();
+        assert_eq!((width, height), (8, 2));
+        assert!((pixels[0] - Vec3::new(1.0, 0.5, 0.0)).length() < 0.01);
+        assert!((pixels[15] - Vec3::new(0.0, 0.0, 2.0)).length() < 0.02);
+    }
+
+    #[test]
+    fn rejects_bad_hdr_sizes() {
+        for size in &["-Y 1 +X 0", "-Y 0 +X 1", "-Y 18446744073709551615 +X 2"] {
+            let file = format!("#?RADIANCE\n\n{size}\n");
+            assert!(read_hdr(&mut file.as_bytes()).is_err());
+        }
+        // huge images without the pixels to back them up fail without allocating them
+        for size in &["-Y 1000000000 +X 1000000000", "-Y 1 +X 1000000000"] {
+            let file = format!("#?RADIANCE\n\n{size}\n\x01\x02\x03\x04");
+            assert!(read_hdr(&mut file.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn sampled_pdf_matches() {
+        let mut pixels = vec![Vec3::new(0.1, 0.1, 0.1); 16 * 8];
+        pixels[2 * 16 + 5] = Vec3::new(50.0, 40.0, 30.0);
+        let map = EnvironmentMap::new(16, 8, pixels, 30.0, 1.0);
+        let mut rng = Pcg32::new(0, 0);
+        for _ in 0..100 {
+            let sample = map.sample(&mut rng).unwrap();
+            if sample.direction.y.abs() > 0.9999 {
+                // right at the poles the pdf divides by a vanishing sin(theta), so rounding in
+                // the direction swamps the comparison
+                continue;
+            }
+            let pdf = map.pdf(sample.direction);
+            assert!((sample.pdf - pdf).abs() < 1e-3 * pdf);
+            assert!((sample.radiance - map.radiance(sample.direction)).length() < 1e-4);
+        }
+    }
+}
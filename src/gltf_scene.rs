@@ -0,0 +1,376 @@
+use crate::{
+    camera::Camera,
+    hitable::Hitable,
+    material::{Material, Pbr},
+    mesh::{Mesh, TriangleMesh},
+    texture::{srgb_to_linear, Texture},
+    transform::{Mat4, Transform},
+    vec3::Vec3,
+};
+use gltf::{buffer, camera::Projection, image, image::Format, mesh::Mode, Document, Node};
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+// Importer for glTF 2.0 scenes, from .gltf files with their .bin and image files or from .glb
+// files. Resources are only read from the local file system or from data URIs.
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// The parameters of `Camera::new` for a perspective camera in the file.
+#[derive(Copy, Clone, Debug)]
+pub struct SceneCamera {
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vup: Vec3,
+    /// vertical field of view, in degrees
+    pub vfov: f32,
+    /// None if the file leaves it to the viewport
+    pub aspect: Option<f32>,
+}
+
+impl SceneCamera {
+    /// A pinhole camera, with `aspect` used if the file doesn't specify the aspect ratio.
+    pub fn camera(&self, aspect: f32) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            self.aspect.unwrap_or(aspect),
+            0.0,
+            1.0,
+        )
+    }
+}
+
+/// The contents of the default scene, flattened into world space.
+pub struct GltfScene {
+    pub hitables: Vec<Hitable>,
+    pub cameras: Vec<SceneCamera>,
+}
+
+pub fn load(path: &Path) -> io::Result<GltfScene> {
+    let (document, buffers, images) =
+        gltf::import(path).map_err(|e| invalid_data(&e.to_string()))?;
+    Importer::new(&buffers, &images).scene(&document)
+}
+
+/// Reads a .glb file, or a .gltf file with all of its resources in data URIs.
+pub fn read(data: &[u8]) -> io::Result<GltfScene> {
+    let (document, buffers, images) =
+        gltf::import_slice(data).map_err(|e| invalid_data(&e.to_string()))?;
+    Importer::new(&buffers, &images).scene(&document)
+}
+
+// Converts a decoded image to linear texels, decoding sRGB for colour textures.
+fn texture(image: &image::Data, srgb: bool) -> io::Result<Texture> {
+    let (channels, bytes) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            return Err(invalid_data(
+                "floating point glTF textures are not supported",
+            ))
+        }
+    };
+    let channel = |texel: &[u8], c: usize| {
+        // single and dual channel images are grey, with alpha in the second channel
+        let c = if channels < 3 { 0 } else { c };
+        let value = if bytes == 1 {
+            f32::from(texel[c]) / 255.0
+        } else {
+            f32::from(u16::from_le_bytes([texel[2 * c], texel[2 * c + 1]])) / 65535.0
+        };
+        if srgb {
+            srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+    let texels = image
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|texel| Vec3::new(channel(texel, 0), channel(texel, 1), channel(texel, 2)))
+        .collect();
+    Ok(Texture::new(
+        image.width as usize,
+        image.height as usize,
+        texels,
+    ))
+}
+
+// None for a singular matrix, e.g. a zero scale, which glTF allows
+fn to_transform(columns: [[f32; 4]; 4]) -> Option<Transform> {
+    // glTF stores matrices by columns
+    let matrix = Mat4::new(columns).transpose();
+    matrix
+        .inverse()
+        .map(|inverse| Transform::from_matrices(matrix, inverse))
+}
+
+struct Importer<'a> {
+    buffers: &'a [buffer::Data],
+    images: &'a [image::Data],
+    // textures by image and whether they are sRGB encoded
+    textures: HashMap<(usize, bool), Arc<Texture>>,
+    // the primitives of every mesh, shared between the nodes using it
+    meshes: HashMap<usize, Vec<Arc<Hitable>>>,
+    hitables: Vec<Hitable>,
+    cameras: Vec<SceneCamera>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(buffers: &'a [buffer::Data], images: &'a [image::Data]) -> Importer<'a> {
+        Importer {
+            buffers,
+            images,
+            textures: HashMap::new(),
+            meshes: HashMap::new(),
+            hitables: Vec::new(),
+            cameras: Vec::new(),
+        }
+    }
+
+    fn scene(mut self, document: &Document) -> io::Result<GltfScene> {
+        let scene = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .ok_or_else(|| invalid_data("glTF file without a scene"))?;
+        for node in scene.nodes() {
+            self.node(&node, Transform::identity())?;
+        }
+        Ok(GltfScene {
+            hitables: self.hitables,
+            cameras: self.cameras,
+        })
+    }
+
+    fn node(&mut self, node: &Node<'_>, parent: Transform) -> io::Result<()> {
+        // a node scaled to nothing hides itself and its children
+        let transform = match to_transform(node.transform().matrix()) {
+            Some(local) => parent * local,
+            None => return Ok(()),
+        };
+        if let Some(mesh) = node.mesh() {
+            if !self.meshes.contains_key(&mesh.index()) {
+                let primitives = self.mesh(&mesh)?;
+                self.meshes.insert(mesh.index(), primitives);
+            }
+            for primitive in &self.meshes[&mesh.index()] {
+                self.hitables
+                    .push(Hitable::instance(primitive.clone(), transform));
+            }
+        }
+        // only perspective cameras are imported
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                // cameras look down their -z axis, with +y up
+                self.cameras.push(SceneCamera {
+                    lookfrom: transform.point(Vec3::zeros()),
+                    lookat: transform.point(Vec3::new(0.0, 0.0, -1.0)),
+                    vup: transform.vector(Vec3::new(0.0, 1.0, 0.0)),
+                    vfov: perspective.yfov().to_degrees(),
+                    aspect: perspective.aspect_ratio(),
+                });
+            }
+        }
+        for child in node.children() {
+            self.node(&child, transform)?;
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh<'_>) -> io::Result<Vec<Arc<Hitable>>> {
+        let mut primitives = Vec::new();
+        // points and lines have no surface, and strips and fans are rare enough to skip
+        for primitive in mesh.primitives().filter(|p| p.mode() == Mode::Triangles) {
+            let buffers = self.buffers;
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|d| &d.0[..]));
+            let positions: Vec<Vec3> = reader
+                .read_positions()
+                .ok_or_else(|| invalid_data("glTF primitive without positions"))?
+                .map(|[x, y, z]| Vec3::new(x, y, z))
+                .collect();
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let geometry = TriangleMesh {
+                normals: reader
+                    .read_normals()
+                    .map(|n| n.map(|[x, y, z]| Vec3::new(x, y, z)).collect()),
+                colors: reader.read_colors(0).map(|c| {
+                    c.into_rgb_f32()
+                        .map(|[r, g, b]| Vec3::new(r, g, b))
+                        .collect()
+                }),
+                uvs: reader
+                    .read_tex_coords(0)
+                    .map(|uv| uv.into_f32().map(|[u, v]| (u, v)).collect()),
+                triangles: indices
+                    .chunks_exact(3)
+                    .map(|t| [t[0], t[1], t[2]])
+                    .collect(),
+                positions,
+            };
+            geometry.validate()?;
+            let material = self.material(&primitive.material())?;
+            primitives.push(Arc::new(Hitable::Mesh(Mesh::new(
+                Arc::new(geometry),
+                material,
+            ))));
+        }
+        Ok(primitives)
+    }
+
+    fn texture(
+        &mut self,
+        info: Option<gltf::texture::Info<'_>>,
+        srgb: bool,
+    ) -> io::Result<Option<Arc<Texture>>> {
+        let Some(info) = info else {
+            return Ok(None);
+        };
+        let index = info.texture().source().index();
+        if let Some(texture) = self.textures.get(&(index, srgb)) {
+            return Ok(Some(texture.clone()));
+        }
+        let image = self
+            .images
+            .get(index)
+            .ok_or_else(|| invalid_data("glTF texture refers to a missing image"))?;
+        let texture = Arc::new(texture(image, srgb)?);
+        self.textures.insert((index, srgb), texture.clone());
+        Ok(Some(texture))
+    }
+
+    // Primitives without a material get the default material of the specification.
+    fn material(&mut self, material: &gltf::Material<'_>) -> io::Result<Material> {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let [er, eg, eb] = material.emissive_factor();
+        Ok(Material::Pbr(Pbr {
+            base_color: Vec3::new(r, g, b),
+            base_color_texture: self.texture(pbr.base_color_texture(), true)?,
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            metallic_roughness_texture: self.texture(pbr.metallic_roughness_texture(), false)?,
+            emissive: Vec3::new(er, eg, eb),
+            emissive_texture: self.texture(material.emissive_texture(), true)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use crate::{hitable::Hitable, ray::Ray, sampling::Pcg32, vec3::Vec3};
+
+    // Packs a document and its binary buffer into a .glb file.
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let total = 12 + 8 + json.len() + 8 + bin.len();
+        let mut data = Vec::new();
+        data.extend_from_slice(b"glTF");
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&(total as u32).to_le_bytes());
+        data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"JSON");
+        data.extend_from_slice(&json);
+        data.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"BIN\0");
+        data.extend_from_slice(bin);
+        data
+    }
+
+    #[test]
+    fn nodes_meshes_and_cameras() {
+        let mut bin = Vec::new();
+        for x in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend_from_slice(&x.to_le_bytes());
+        }
+        // one triangle used by two nodes, one of them below a translated parent, and a camera
+        // at z = 5 turned upside down
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "scene": 0,
+            "scenes": [{"nodes": [0, 2]}],
+            "nodes": [
+                {"translation": [10, 0, 0], "children": [1]},
+                {"mesh": 0},
+                {"mesh": 0, "camera": 0, "translation": [0, 0, 5], "rotation": [0, 0, 1, 0]}
+            ],
+            "cameras": [{"type": "perspective", "perspective": {"yfov": 1.0, "znear": 0.1}}],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+            "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1, 0.5, 0.25, 1],
+                "metallicFactor": 0.0}}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "buffers": [{"byteLength": 36}]
+        }"#;
+        let scene = read(&glb(json, &bin)).unwrap();
+        assert_eq!(scene.hitables.len(), 2);
+        let mut rng = Pcg32::new(0, 0);
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        let ray = Ray::new(Vec3::new(10.25, 0.25, 1.0), down, 0.0);
+        let hit = scene.hitables[0]
+            .hit(&ray, 0.001, f32::MAX, &mut rng)
+            .unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-5);
+        // the child inherits the translation of its parent
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 1.0), down, 0.0);
+        assert!(scene.hitables[0]
+            .hit(&ray, 0.001, f32::MAX, &mut rng)
+            .is_none());
+        match &scene.hitables[1] {
+            Hitable::Instance(instance) => assert!(matches!(&*instance.object, Hitable::Mesh(_))),
+            _ => panic!("expected a mesh instance"),
+        }
+
+        assert_eq!(scene.cameras.len(), 1);
+        let camera = scene.cameras[0];
+        assert!((camera.lookfrom - Vec3::new(0.0, 0.0, 5.0)).length() < 1e-5);
+        assert!((camera.lookat - Vec3::new(0.0, 0.0, 4.0)).length() < 1e-5);
+        assert!((camera.vup - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-5);
+        assert!((camera.vfov - 1.0f32.to_degrees()).abs() < 1e-4);
+        assert!(camera.aspect.is_none());
+    }
+
+    #[test]
+    fn zero_scale_hides_a_subtree() {
+        let mut bin = Vec::new();
+        for x in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            bin.extend_from_slice(&x.to_le_bytes());
+        }
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "scenes": [{"nodes": [0, 2]}],
+            "nodes": [
+                {"mesh": 0, "scale": [0, 0, 0], "children": [1]},
+                {"mesh": 0},
+                {"mesh": 0, "scale": [1, 0, 1]}
+            ],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "buffers": [{"byteLength": 36}]
+        }"#;
+        let scene = read(&glb(json, &bin)).unwrap();
+        assert!(scene.hitables.is_empty());
+    }
+}
@@ -1,14 +1,99 @@
-use crate::{material::Material, ray::Ray, vec3::Vec3};
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    csg::{Csg, CsgOperation},
+    environment::Background,
+    instance::{AnimatedInstance, Instance},
+    light::{Light, SphereLight},
+    light_sampler::LightSampler,
+    material::Material,
+    medium::{ConstantMedium, GridMedium, HomogeneousMedium},
+    mesh::Mesh,
+    primitives::{Cone, Cuboid, Cylinder, Disk, Plane, Quad, Torus},
+    ray::Ray,
+    sampling::Pcg32,
+    sdf::Sdf,
+    transform::{AnimatedTransform, Transform},
+    vec3::Vec3,
+};
+use std::{
+    f32::consts::PI,
+    sync::{Arc, OnceLock},
+};
 
+/// `n` is the outward facing unit normal, and `u`, `v` are the surface coordinates of the hit,
+/// usually in [0, 1]. `color` is the vertex colour interpolated across meshes which have one,
+/// tinting the material, and white elsewhere.
 #[derive(Copy, Clone)]
 pub struct HitRecord<'obj> {
     pub t: f32,
     pub p: Vec3,
     pub n: Vec3,
+    pub u: f32,
+    pub v: f32,
+    pub color: Vec3,
     pub material: &'obj Material,
 }
 
-#[derive(Copy, Clone)]
+/// Longitude and latitude of a point on the unit sphere. `u` runs around the y axis starting at
+/// -x, and `v` runs from the bottom to the top pole.
+pub fn sphere_uv(n: Vec3) -> (f32, f32) {
+    let theta = (-n.y).clamp(-1.0, 1.0).acos();
+    let phi = (-n.z).atan2(n.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+fn hit_sphere<'obj>(
+    center: Vec3,
+    radius: f32,
+    material: &'obj Material,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord<'obj>> {
+    let oc = ray.origin - center;
+    let a = ray.direction.dot(ray.direction);
+    let b = oc.dot(ray.direction);
+    let c = oc.dot(oc) - radius * radius;
+    let discriminant = b * b - a * c;
+
+    if discriminant > 0.0 {
+        let mut temp = (-b - (b * b - a * c).sqrt()) / a;
+        if temp < t_max && temp > t_min {
+            let hit_point = ray.point_at_parameter(temp);
+            let n = (1.0 / radius) * (hit_point - center);
+            let (u, v) = sphere_uv(n);
+            return Some(HitRecord {
+                t: temp,
+                p: hit_point,
+                n,
+                u,
+                v,
+                color: Vec3::ones(),
+                material,
+            });
+        }
+
+        temp = (-b + (b * b - a * c).sqrt()) / a;
+        if temp < t_max && temp > t_min {
+            let hit_point = ray.point_at_parameter(temp);
+            let n = (1.0 / radius) * (hit_point - center);
+            let (u, v) = sphere_uv(n);
+            return Some(HitRecord {
+                t: temp,
+                p: hit_point,
+                n,
+                u,
+                v,
+                color: Vec3::ones(),
+                material,
+            });
+        }
+    }
+    None
+}
+
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -25,61 +110,321 @@ impl Sphere {
     }
 
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
-        let oc = ray.origin - self.center;
-        let a = ray.direction.dot(ray.direction);
-        let b = oc.dot(ray.direction);
-        let c = oc.dot(oc) - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-
-        if discriminant > 0.0 {
-            let mut temp = (-b - (b * b - a * c).sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let hit_point = ray.point_at_parameter(temp);
-                return Some(HitRecord {
-                    t: temp,
-                    p: hit_point,
-                    n: (1.0 / self.radius) * (hit_point - self.center),
-                    material: &self.material,
-                });
-            }
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+/// A sphere whose centre moves linearly from `center0` at `time0` to `center1` at `time1`. It
+/// stays put outside of that interval.
+#[derive(Clone)]
+pub struct MovingSphere {
+    pub center0: Vec3,
+    pub center1: Vec3,
+    pub time0: f32,
+    pub time1: f32,
+    pub radius: f32,
+    pub material: Material,
+}
 
-            temp = (-b + (b * b - a * c).sqrt()) / a;
-            if temp < t_max && temp > t_min {
-                let hit_point = ray.point_at_parameter(temp);
-                return Some(HitRecord {
-                    t: temp,
-                    p: hit_point,
-                    n: (1.0 / self.radius) * (hit_point - self.center),
-                    material: &self.material,
-                });
+impl MovingSphere {
+    pub fn new(
+        center0: Vec3,
+        center1: Vec3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+        material: Material,
+    ) -> MovingSphere {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
+        }
+    }
+
+    pub fn center(&self, time: f32) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center0 + s * (self.center1 - self.center0)
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let center = self.center(ray.time);
+        hit_sphere(center, self.radius, &self.material, ray, t_min, t_max)
+    }
+
+    /// Bounds the sphere over its whole motion.
+    pub fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::new(self.center0 - r, self.center0 + r);
+        start.union(&Aabb::new(self.center1 - r, self.center1 + r))
+    }
+}
+
+#[derive(Clone)]
+pub enum Hitable {
+    Sphere(Sphere),
+    MovingSphere(MovingSphere),
+    Plane(Plane),
+    Disk(Disk),
+    Quad(Quad),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Csg(Csg),
+    Sdf(Sdf),
+    Mesh(Mesh),
+    ConstantMedium(ConstantMedium),
+    GridMedium(GridMedium),
+    Instance(Instance),
+    AnimatedInstance(AnimatedInstance),
+}
+
+impl Hitable {
+    /// Places a shared object in the world.
+    pub fn instance(object: Arc<Hitable>, transform: Transform) -> Hitable {
+        Hitable::Instance(Instance::new(object, transform))
+    }
+
+    /// Combines two closed solids, see `Csg`.
+    pub fn csg(operation: CsgOperation, left: Hitable, right: Hitable) -> Hitable {
+        Hitable::Csg(Csg::new(operation, left, right))
+    }
+
+    /// Moves a shared object along keyframed motion.
+    pub fn animated(object: Arc<Hitable>, motion: AnimatedTransform) -> Hitable {
+        Hitable::AnimatedInstance(AnimatedInstance::new(object, motion))
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        match self {
+            Hitable::Sphere(s) => s.hit(ray, t_min, t_max),
+            Hitable::MovingSphere(s) => s.hit(ray, t_min, t_max),
+            Hitable::Plane(s) => s.hit(ray, t_min, t_max),
+            Hitable::Disk(s) => s.hit(ray, t_min, t_max),
+            Hitable::Quad(s) => s.hit(ray, t_min, t_max),
+            Hitable::Cuboid(s) => s.hit(ray, t_min, t_max),
+            Hitable::Cylinder(s) => s.hit(ray, t_min, t_max),
+            Hitable::Cone(s) => s.hit(ray, t_min, t_max),
+            Hitable::Torus(s) => s.hit(ray, t_min, t_max),
+            Hitable::Csg(c) => c.hit(ray, t_min, t_max, rng),
+            Hitable::Sdf(s) => s.hit(ray, t_min, t_max),
+            Hitable::Mesh(m) => m.hit(ray, t_min, t_max),
+            Hitable::ConstantMedium(m) => m.hit(ray, t_min, t_max, rng),
+            Hitable::GridMedium(m) => m.hit(ray, t_min, t_max, rng),
+            Hitable::Instance(i) => i.hit(ray, t_min, t_max, rng),
+            Hitable::AnimatedInstance(i) => i.hit(ray, t_min, t_max, rng),
+        }
+    }
+
+    /// None for objects without finite extent.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        match self {
+            Hitable::Sphere(s) => Some(s.bounding_box()),
+            Hitable::MovingSphere(s) => Some(s.bounding_box()),
+            Hitable::Plane(_) => None,
+            Hitable::Disk(s) => Some(s.bounding_box()),
+            Hitable::Quad(s) => Some(s.bounding_box()),
+            Hitable::Cuboid(s) => Some(s.bounds),
+            Hitable::Cylinder(s) => Some(s.bounding_box()),
+            Hitable::Cone(s) => Some(s.bounding_box()),
+            Hitable::Torus(s) => Some(s.bounding_box()),
+            Hitable::Csg(c) => c.bounding_box(),
+            Hitable::Sdf(s) => Some(s.bounds),
+            Hitable::Mesh(m) => m.bounding_box(),
+            Hitable::ConstantMedium(m) => m.boundary.bounding_box(),
+            Hitable::GridMedium(m) => Some(m.grid.bounds),
+            Hitable::Instance(i) => i.bounding_box(),
+            Hitable::AnimatedInstance(i) => i.bounding_box(),
+        }
+    }
+
+    /// The fraction of light which makes it along the ray between `t_min` and `t_max`. Surfaces
+    /// are treated as opaque.
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        match self {
+            Hitable::ConstantMedium(m) => m.transmittance(ray, t_min, t_max, rng),
+            Hitable::GridMedium(m) => m.transmittance(ray, t_min, t_max, rng),
+            Hitable::Instance(i) => i.transmittance(ray, t_min, t_max, rng),
+            Hitable::AnimatedInstance(i) => i.transmittance(ray, t_min, t_max, rng),
+            _ => {
+                if self.hit(ray, t_min, t_max, rng).is_some() {
+                    0.0
+                } else {
+                    1.0
+                }
             }
         }
-        None
     }
 }
 
 pub struct World {
-    spheres: Vec<Sphere>,
+    hitables: Vec<Hitable>,
+    bvh: Bvh,
+    atmosphere: Option<ConstantMedium>,
+    pub background: Background,
+    lights: Vec<Light>,
+    // for each hitable, the index of the light it is sampled as
+    object_lights: Vec<Option<usize>>,
+    use_light_bvh: bool,
+    light_sampler: OnceLock<LightSampler>,
 }
 
 impl World {
-    pub fn new(spheres: Vec<Sphere>) -> World {
-        World { spheres }
+    /// Spheres with an emissive material become area lights.
+    pub fn new(hitables: Vec<Hitable>) -> World {
+        let mut lights = Vec::new();
+        let mut object_lights = vec![None; hitables.len()];
+        for (object, hitable) in hitables.iter().enumerate() {
+            if let Hitable::Sphere(sphere) = hitable {
+                if let Material::Emissive(emissive) = sphere.material {
+                    object_lights[object] = Some(lights.len());
+                    lights.push(Light::Sphere(SphereLight {
+                        center: sphere.center,
+                        radius: sphere.radius,
+                        radiance: emissive.radiance,
+                        object,
+                    }));
+                }
+            }
+        }
+        let bounds: Vec<Option<Aabb>> = hitables.iter().map(Hitable::bounding_box).collect();
+        World {
+            hitables,
+            bvh: Bvh::new(&bounds),
+            atmosphere: None,
+            background: Background::Gradient,
+            lights,
+            object_lights,
+            use_light_bvh: true,
+            light_sampler: OnceLock::new(),
+        }
     }
 
-    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
-        let mut closest_so_far = t_max;
-        let mut maybe_hit: Option<HitRecord<'_>> = None;
-        for sphere in self.spheres.iter() {
-            if let Some(hit) = sphere.hit(&ray, t_min, t_max) {
-                closest_so_far = if hit.t < closest_so_far {
-                    maybe_hit = Some(hit);
-                    hit.t
-                } else {
-                    closest_so_far
-                };
+    pub fn add_light(&mut self, light: Light) {
+        self.lights.push(light);
+        self.light_sampler = OnceLock::new();
+    }
+
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    /// Chooses between picking lights with a light BVH, which accounts for the distance and
+    /// orientation of the lights relative to the shading point (the default), or purely in
+    /// proportion to their power.
+    pub fn set_light_bvh(&mut self, use_light_bvh: bool) {
+        self.use_light_bvh = use_light_bvh;
+        self.light_sampler = OnceLock::new();
+    }
+
+    pub fn light_sampler(&self) -> &LightSampler {
+        self.light_sampler.get_or_init(|| {
+            if self.use_light_bvh {
+                LightSampler::bvh(&self.lights)
+            } else {
+                LightSampler::power(&self.lights)
+            }
+        })
+    }
+
+    /// The index of the light the hitable `object` is sampled as, if any.
+    pub fn object_light(&self, object: usize) -> Option<usize> {
+        self.object_lights.get(object).copied().flatten()
+    }
+
+    /// Fills the world up to `extent` units from the origin with a participating medium, e.g. a
+    /// thin haze. The extent must be finite, otherwise no light from the background would make
+    /// it into the scene.
+    pub fn set_atmosphere(&mut self, medium: HomogeneousMedium, extent: f32) {
+        let boundary = Sphere::new(Vec3::zeros(), extent, medium.material.clone());
+        self.atmosphere = Some(ConstantMedium::new(Hitable::Sphere(boundary), medium));
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        self.hit_object(ray, t_min, t_max, rng).map(|(_, hit)| hit)
+    }
+
+    /// Like `hit`, but also returns the index of the hitable which was hit. Collisions with the
+    /// atmosphere have no index.
+    pub fn hit_object(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        rng: &mut Pcg32,
+    ) -> Option<(Option<usize>, HitRecord<'_>)> {
+        let mut maybe_hit: Option<(Option<usize>, HitRecord<'_>)> = None;
+        self.bvh
+            .traverse(ray, t_min, t_max, |object, closest_so_far| {
+                let hit = self.hitables[object].hit(ray, t_min, closest_so_far, rng)?;
+                maybe_hit = Some((Some(object), hit));
+                Some(hit.t)
+            });
+        let closest_so_far = maybe_hit.map_or(t_max, |(_, hit)| hit.t);
+        if let Some(atmosphere) = &self.atmosphere {
+            if let Some(hit) = atmosphere.hit(ray, t_min, closest_so_far, rng) {
+                maybe_hit = Some((None, hit));
             }
         }
         maybe_hit
     }
+
+    /// The fraction of light arriving along a shadow ray, accounting for occluding surfaces and
+    /// absorption by participating media.
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        let mut transmittance = match &self.atmosphere {
+            Some(atmosphere) => atmosphere.transmittance(ray, t_min, t_max, rng),
+            None => 1.0,
+        };
+        self.bvh.traverse(ray, t_min, t_max, |object, _| {
+            if transmittance > 0.0 {
+                transmittance *= self.hitables[object].transmittance(ray, t_min, t_max, rng);
+            }
+            None
+        });
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MovingSphere;
+    use crate::{material::Material, ray::Ray, vec3::Vec3};
+
+    #[test]
+    fn moving_sphere() {
+        let sphere = MovingSphere::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 4.0, 0.0),
+            1.0,
+            3.0,
+            0.5,
+            Material::lambertian(Vec3::ones()),
+        );
+        // still outside of the time interval, and linear within it
+        assert_eq!(sphere.center(0.0), Vec3::zeros());
+        assert_eq!(sphere.center(2.0), Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(sphere.center(5.0), Vec3::new(0.0, 4.0, 0.0));
+        let ray = |time: f32| Ray::new(Vec3::new(0.0, 2.0, 5.0), Vec3::new(0.0, 0.0, -1.0), time);
+        let hit = sphere.hit(&ray(2.0), 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-5);
+        assert_eq!(hit.p, Vec3::new(0.0, 2.0, 0.5));
+        assert!(sphere.hit(&ray(1.0), 0.001, f32::MAX).is_none());
+        assert!(sphere.hit(&ray(3.0), 0.001, f32::MAX).is_none());
+        let bounds = sphere.bounding_box();
+        assert_eq!(bounds.min, Vec3::new(-0.5, -0.5, -0.5));
+        assert_eq!(bounds.max, Vec3::new(0.5, 4.5, 0.5));
+    }
 }
@@ -0,0 +1,287 @@
+use crate::vec3::{coordinate_system, Vec3};
+use std::{
+    f32::consts::PI,
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+/// The angular intensity distribution of a luminaire, read from an IES LM-63 photometric file.
+/// Only type C photometry, the kind used for architectural fixtures, is supported: vertical
+/// angles are measured from the nadir (straight down) and horizontal angles around it.
+#[derive(Clone, Debug)]
+pub struct IesProfile {
+    vertical_angles: Vec<f32>,
+    horizontal_angles: Vec<f32>,
+    // candela, indexed by [horizontal][vertical]
+    candela: Vec<Vec<f32>>,
+    max_candela: f32,
+    // the mean of the normalized intensity over the sphere of directions
+    mean: f32,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Finds the segment containing `x` in the sorted `values`, and the position within it.
+fn locate(values: &[f32], x: f32) -> Option<(usize, f32)> {
+    let last = values.len() - 1;
+    if x < values[0] || x > values[last] {
+        return None;
+    }
+    if last == 0 {
+        return Some((0, 0.0));
+    }
+    let i = values.partition_point(|&v| v <= x).clamp(1, last) - 1;
+    let width = values[i + 1] - values[i];
+    let t = if width > 0.0 {
+        (x - values[i]) / width
+    } else {
+        0.0
+    };
+    Some((i, t))
+}
+
+impl IesProfile {
+    pub fn load(path: &Path) -> io::Result<IesProfile> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> io::Result<IesProfile> {
+        let mut lines = text.lines();
+        // skip the format line and the [KEYWORD] header lines up to the tilt specification
+        let tilt = loop {
+            let line = lines
+                .next()
+                .ok_or_else(|| invalid_data("missing TILT line"))?
+                .trim();
+            if let Some(tilt) = line.strip_prefix("TILT=") {
+                break tilt.trim().to_string();
+            }
+        };
+        let mut numbers = lines
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token
+                    .parse::<f32>()
+                    .map_err(|_| invalid_data(&format!("bad number '{token}'")))
+            });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid_data("unexpected end of file")))
+        };
+
+        if tilt == "INCLUDE" {
+            // lamp-to-luminaire geometry followed by angle and multiplier pairs, which only
+            // matter for lamps whose output depends on their tilt
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0..2 * pairs {
+                next()?;
+            }
+        }
+
+        let _lamp_count = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        // units, luminous opening width, length and height
+        for _ in 0..4 {
+            next()?;
+        }
+        let ballast_factor = next()?;
+        // ballast-lamp photometric factor, or future use
+        next()?;
+        let _input_watts = next()?;
+
+        if photometric_type != 1.0 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("profile has no angles"));
+        }
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f32>>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<io::Result<Vec<f32>>>()?;
+        let scale = multiplier * ballast_factor;
+        let mut candela = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            let row = (0..vertical_count)
+                .map(|_| next().map(|c| scale * c))
+                .collect::<io::Result<Vec<f32>>>()?;
+            candela.push(row);
+        }
+        let max_candela = candela.iter().flatten().fold(0.0f32, |acc, &c| acc.max(c));
+
+        let mut profile = IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            max_candela,
+            mean: 0.0,
+        };
+        profile.mean = profile.integrate() / (4.0 * PI);
+        Ok(profile)
+    }
+
+    pub fn max_candela(&self) -> f32 {
+        self.max_candela
+    }
+
+    /// The mean of `normalized_intensity` over all directions.
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    // Integrates the normalized intensity over the sphere with the midpoint rule.
+    fn integrate(&self) -> f32 {
+        const STEPS: usize = 64;
+        let mut total = 0.0;
+        for i in 0..STEPS {
+            let theta = 180.0 * (i as f32 + 0.5) / STEPS as f32;
+            let mut ring = 0.0;
+            for j in 0..2 * STEPS {
+                let phi = 360.0 * (j as f32 + 0.5) / (2 * STEPS) as f32;
+                ring += self.normalized_intensity(theta, phi);
+            }
+            let d_theta = PI / STEPS as f32;
+            let d_phi = PI / STEPS as f32;
+            total += ring * theta.to_radians().sin() * d_theta * d_phi;
+        }
+        total
+    }
+
+    // Maps a horizontal angle onto the range covered by the file, according to the symmetry
+    // implied by the last horizontal angle.
+    fn fold_horizontal(&self, phi: f32) -> f32 {
+        let phi = phi.rem_euclid(360.0);
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if last <= 0.0 {
+            // rotationally symmetric
+            0.0
+        } else if last <= 90.0 {
+            let phi = if phi > 180.0 { 360.0 - phi } else { phi };
+            if phi > 90.0 {
+                180.0 - phi
+            } else {
+                phi
+            }
+        } else if last <= 180.0 && phi > 180.0 {
+            360.0 - phi
+        } else {
+            phi
+        }
+    }
+
+    /// Bilinearly interpolated candela at vertical angle `theta` from the nadir and horizontal
+    /// angle `phi`, both in degrees.
+    pub fn candela(&self, theta: f32, phi: f32) -> f32 {
+        let Some((v, tv)) = locate(&self.vertical_angles, theta) else {
+            return 0.0;
+        };
+        let (h, th) =
+            locate(&self.horizontal_angles, self.fold_horizontal(phi)).unwrap_or((0, 0.0));
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal_angles.len() - 1);
+            let v = v.min(self.vertical_angles.len() - 1);
+            self.candela[h][v]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        lerp(
+            lerp(at(h, v), at(h, v + 1), tv),
+            lerp(at(h + 1, v), at(h + 1, v + 1), tv),
+            th,
+        )
+    }
+
+    pub fn normalized_intensity(&self, theta: f32, phi: f32) -> f32 {
+        if self.max_candela > 0.0 {
+            self.candela(theta, phi) / self.max_candela
+        } else {
+            0.0
+        }
+    }
+}
+
+/// An IES profile oriented in the world, with the profile's nadir pointing along `nadir`.
+#[derive(Clone)]
+pub struct Photometry {
+    pub profile: Arc<IesProfile>,
+    nadir: Vec3,
+    u: Vec3,
+    v: Vec3,
+}
+
+impl Photometry {
+    pub fn new(profile: Arc<IesProfile>, nadir: Vec3) -> Photometry {
+        let nadir = nadir.make_unit_vector();
+        let (u, v) = coordinate_system(nadir);
+        Photometry {
+            profile,
+            nadir,
+            u,
+            v,
+        }
+    }
+
+    /// The normalized intensity emitted along the unit vector `direction`.
+    pub fn intensity(&self, direction: Vec3) -> f32 {
+        let theta = direction
+            .dot(self.nadir)
+            .clamp(-1.0, 1.0)
+            .acos()
+            .to_degrees();
+        let phi = direction
+            .dot(self.v)
+            .atan2(direction.dot(self.u))
+            .to_degrees();
+        self.profile.normalized_intensity(theta, phi)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::IesProfile;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] nobody
+TILT=NONE
+1 1000 2.0 3 2 1 1 0.1 0.1 0
+1.0 1.0 10
+0 45 90
+0 90
+100 50 0
+200 100 0
+";
+
+    #[test]
+    fn parse_and_interpolate() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(profile.max_candela(), 400.0);
+        assert_eq!(profile.candela(0.0, 0.0), 200.0);
+        assert_eq!(profile.candela(22.5, 0.0), 150.0);
+        assert_eq!(profile.candela(0.0, 45.0), 300.0);
+        // quadrant symmetry mirrors the 90 degree plane to 270 degrees
+        assert_eq!(profile.candela(0.0, 270.0), 400.0);
+        assert_eq!(profile.candela(0.0, 180.0), 200.0);
+        // nothing above the horizontal
+        assert_eq!(profile.candela(120.0, 0.0), 0.0);
+        assert!(profile.mean() > 0.0 && profile.mean() < 0.5);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 3 2").is_err());
+        assert!(IesProfile::parse("no tilt here").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{
+    aabb::Aabb,
+    hitable::{HitRecord, Hitable},
+    ray::Ray,
+    sampling::Pcg32,
+    transform::{AnimatedTransform, Transform},
+};
+use std::sync::Arc;
+
+// The direction is left unnormalized so that ray parameters agree in both spaces.
+fn object_ray(transform: &Transform, ray: &Ray) -> Ray {
+    Ray::new(
+        transform.inverse.transform_point(ray.origin),
+        transform.inverse.transform_vector(ray.direction),
+        ray.time,
+    )
+}
+
+fn world_hit<'obj>(transform: &Transform, ray: &Ray, hit: HitRecord<'obj>) -> HitRecord<'obj> {
+    HitRecord {
+        p: ray.point_at_parameter(hit.t),
+        n: transform.normal(hit.n).make_unit_vector(),
+        ..hit
+    }
+}
+
+/// A shared object placed in the world by a transform. Rays are brought into the object's space
+/// instead of transforming the object, so any number of instances can share one copy of the
+/// geometry. Media inside an instance keep their density per unit of object space length.
+#[derive(Clone)]
+pub struct Instance {
+    pub object: Arc<Hitable>,
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<Hitable>, transform: Transform) -> Instance {
+        Instance { object, transform }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        let hit = self
+            .object
+            .hit(&object_ray(&self.transform, ray), t_min, t_max, rng)?;
+        Some(world_hit(&self.transform, ray, hit))
+    }
+
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        self.object
+            .transmittance(&object_ray(&self.transform, ray), t_min, t_max, rng)
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.object
+            .bounding_box()
+            .map(|bounds| self.transform.bounds(&bounds))
+    }
+}
+
+/// A shared object moving along an animated transform, evaluated at each ray's time.
+#[derive(Clone)]
+pub struct AnimatedInstance {
+    pub object: Arc<Hitable>,
+    pub motion: AnimatedTransform,
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<Hitable>, motion: AnimatedTransform) -> AnimatedInstance {
+        AnimatedInstance { object, motion }
+    }
+
+    /// Misses at instants where the object is scaled to nothing.
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        let transform = self.motion.at(ray.time)?;
+        let hit = self
+            .object
+            .hit(&object_ray(&transform, ray), t_min, t_max, rng)?;
+        Some(world_hit(&transform, ray, hit))
+    }
+
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        let Some(transform) = self.motion.at(ray.time) else {
+            return 1.0;
+        };
+        self.object
+            .transmittance(&object_ray(&transform, ray), t_min, t_max, rng)
+    }
+
+    /// Bounds the object over its whole motion.
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.object
+            .bounding_box()
+            .map(|bounds| self.motion.bounds(&bounds))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AnimatedInstance, Instance};
+    use crate::{
+        hitable::{Hitable, Sphere},
+        material::Material,
+        ray::Ray,
+        sampling::Pcg32,
+        transform::{AnimatedTransform, Keyframe, Quaternion, Transform},
+        vec3::Vec3,
+    };
+    use rand::prelude::*;
+    use std::sync::Arc;
+
+    fn random_point(rng: &mut Pcg32, scale: f32) -> Vec3 {
+        scale
+            * Vec3::new(
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+                rng.gen::<f32>() - 0.5,
+            )
+    }
+
+    #[test]
+    fn instances_hit_like_transformed_geometry() {
+        let material = Material::lambertian(Vec3::ones());
+        let unit = Arc::new(Hitable::Sphere(Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            material.clone(),
+        )));
+        // rotating a sphere about its centre makes no difference, so the instance is the same
+        // as a sphere of radius 2 at (1, 2, 3)
+        let transform = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(Vec3::new(1.0, 1.0, 0.0), 50.0)
+            * Transform::uniform_scale(2.0);
+        let instance = Instance::new(unit, transform);
+        let sphere = Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, material);
+        let mut rng = Pcg32::new(0, 0);
+        let mut hits = 0;
+        for _ in 0..1000 {
+            // aim at points around the sphere so that most rays hit it and some miss
+            let origin = random_point(&mut rng, 12.0);
+            let target = Vec3::new(1.0, 2.0, 3.0) + random_point(&mut rng, 6.0);
+            let ray = Ray::new(origin, target - origin, 0.0);
+            let expected = sphere.hit(&ray, 0.001, f32::MAX);
+            let hit = instance.hit(&ray, 0.001, f32::MAX, &mut rng);
+            assert_eq!(hit.is_some(), expected.is_some());
+            if let (Some(hit), Some(expected)) = (hit, expected) {
+                assert!((hit.t - expected.t).abs() < 1e-4 * expected.t.max(1.0));
+                assert!((hit.p - expected.p).length() < 1e-3);
+                assert!((hit.n - expected.n).length() < 1e-3);
+                hits += 1;
+            }
+        }
+        assert!(hits > 300);
+        let bounds = instance.bounding_box().unwrap();
+        assert!(bounds.contains(Vec3::new(1.0, 2.0, 4.9)));
+    }
+
+    #[test]
+    fn normals_of_stretched_instances() {
+        // a unit sphere stretched into an ellipsoid four units long along x
+        let unit = Arc::new(Hitable::Sphere(Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            Material::lambertian(Vec3::ones()),
+        )));
+        let instance = Instance::new(unit, Transform::scale(Vec3::new(4.0, 1.0, 1.0)));
+        let mut rng = Pcg32::new(0, 0);
+        // at 45 degrees around the ellipse x^2/16 + y^2 = 1, the normal is along (x/16, y)
+        let p = Vec3::new(4.0 * 0.5f32.sqrt(), 0.5f32.sqrt(), 0.0);
+        let ray = Ray::new(p + Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let hit = instance.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p - p).length() < 1e-4);
+        let n = Vec3::new(p.x / 16.0, p.y, 0.0).make_unit_vector();
+        assert!((hit.n - n).length() < 1e-4);
+    }
+
+    #[test]
+    fn animated_instances_follow_their_motion() {
+        let unit = Arc::new(Hitable::Sphere(Sphere::new(
+            Vec3::zeros(),
+            1.0,
+            Material::lambertian(Vec3::ones()),
+        )));
+        // moving four units along x while shrinking away to nothing
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(0.0, Vec3::zeros(), Quaternion::identity(), Vec3::ones()),
+            Keyframe::new(
+                1.0,
+                Vec3::new(4.0, 0.0, 0.0),
+                Quaternion::identity(),
+                Vec3::zeros(),
+            ),
+        ]);
+        let instance = AnimatedInstance::new(unit, motion);
+        let mut rng = Pcg32::new(0, 0);
+        let ray =
+            |x: f32, time: f32| Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), time);
+        let hit = instance
+            .hit(&ray(0.0, 0.0), 0.001, f32::MAX, &mut rng)
+            .unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-5);
+        // halfway, at x = 2 with a radius of one half
+        assert!(instance
+            .hit(&ray(0.0, 0.5), 0.001, f32::MAX, &mut rng)
+            .is_none());
+        let hit = instance
+            .hit(&ray(2.0, 0.5), 0.001, f32::MAX, &mut rng)
+            .unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-4);
+        assert!((hit.n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+        // scaled to nothing, it is a miss rather than a singular transform
+        assert!(instance
+            .hit(&ray(4.0, 1.0), 0.001, f32::MAX, &mut rng)
+            .is_none());
+        assert_eq!(
+            instance.transmittance(&ray(4.0, 1.0), 0.001, f32::MAX, &mut rng),
+            1.0
+        );
+        let bounds = instance.bounding_box().unwrap();
+        assert!(
+            bounds.contains(Vec3::new(-1.0, 0.0, 0.0)) && bounds.contains(Vec3::new(2.5, 0.0, 0.0))
+        );
+    }
+}
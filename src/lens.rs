@@ -0,0 +1,556 @@
+use crate::{
+    camera::{shutter_time, view_basis, CameraModel},
+    material::refract,
+    ray::Ray,
+    sampling::Pcg32,
+    vec3::Vec3,
+};
+use rand::prelude::*;
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+// The number of film radius intervals with their own exit pupil bounds, and the number of rays
+// traced to bound each of them.
+const PUPIL_INTERVALS: usize = 64;
+const PUPIL_SAMPLES: usize = 16384;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// One spherical surface of a lens, in scene units.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LensElement {
+    /// positive when the centre of curvature is towards the film, zero for the aperture stop
+    pub curvature_radius: f32,
+    /// the distance along the axis to the next surface, or to the film after the last one
+    pub thickness: f32,
+    /// the index of refraction of the medium behind the surface, towards the film
+    pub ior: f32,
+    pub aperture_radius: f32,
+}
+
+/// A lens prescription: spherical surfaces listed from the front of the lens to the back.
+///
+/// Rays are traced in camera space, with the film at z = 0 and the lens towards +z.
+#[derive(Clone, Debug)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+}
+
+// Mirrors a ray between camera space and lens space, where the lens is towards -z.
+fn flip(r: Ray) -> Ray {
+    Ray::new(
+        Vec3::new(r.origin.x, r.origin.y, -r.origin.z),
+        Vec3::new(r.direction.x, r.direction.y, -r.direction.z),
+        r.time,
+    )
+}
+
+// Where a ray in lens space passes through the surface with its vertex at `z`, and the normal
+// there facing the ray. The aperture stop is flat and has no normal.
+fn hit_surface(element: LensElement, z: f32, r: Ray) -> Option<(Vec3, Vec3)> {
+    let radius = element.curvature_radius;
+    let (p, n) = if radius == 0.0 {
+        let t = (z - r.origin.z) / r.direction.z;
+        (r.point_at_parameter(t), Vec3::zeros())
+    } else {
+        let oc = r.origin - Vec3::new(0.0, 0.0, z + radius);
+        let a = r.direction.dot(r.direction);
+        let b = 2.0 * r.direction.dot(oc);
+        let c = oc.dot(oc) - radius * radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let t0 = (-b - root) / (2.0 * a);
+        let t1 = (-b + root) / (2.0 * a);
+        // the surface is the half of the sphere facing the vertex
+        let t = if (r.direction.z > 0.0) ^ (radius < 0.0) {
+            t0
+        } else {
+            t1
+        };
+        if t < 0.0 {
+            return None;
+        }
+        let mut n = (oc + t * r.direction).make_unit_vector();
+        if n.dot(r.direction) > 0.0 {
+            n = -n;
+        }
+        (r.point_at_parameter(t), n)
+    };
+    let aperture = element.aperture_radius;
+    if p.x * p.x + p.y * p.y > aperture * aperture {
+        return None;
+    }
+    Some((p, n))
+}
+
+impl LensSystem {
+    /// Fails if there are no elements.
+    pub fn new(elements: Vec<LensElement>) -> io::Result<LensSystem> {
+        if elements.is_empty() {
+            return Err(invalid_data("the lens has no surfaces"));
+        }
+        Ok(LensSystem { elements })
+    }
+
+    pub fn load(path: &Path) -> io::Result<LensSystem> {
+        LensSystem::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Reads a prescription in the format of pbrt's lens files: a line per surface with its
+    /// curvature radius, thickness, index of refraction and aperture diameter, in millimetres.
+    /// A radius of zero marks the aperture stop, and `#` starts a comment. Scene units are
+    /// metres.
+    pub fn parse(text: &str) -> io::Result<LensSystem> {
+        let mut elements = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid_data(&format!("invalid number in lens line {line:?}")))?;
+            let [radius, thickness, ior, aperture] = values[..] else {
+                return Err(invalid_data(&format!(
+                    "expected 4 values in lens line {line:?}"
+                )));
+            };
+            elements.push(LensElement {
+                curvature_radius: 0.001 * radius,
+                thickness: 0.001 * thickness,
+                // the aperture stop is usually given an index of zero
+                ior: if ior == 0.0 { 1.0 } else { ior },
+                aperture_radius: 0.0005 * aperture,
+            });
+        }
+        LensSystem::new(elements)
+    }
+
+    /// Stops the aperture down to `diameter` millimetres. Wider apertures than the lens allows
+    /// are ignored.
+    pub fn with_aperture(mut self, diameter: f32) -> LensSystem {
+        let radius = 0.0005 * diameter;
+        for element in &mut self.elements {
+            if element.curvature_radius == 0.0 {
+                element.aperture_radius = element.aperture_radius.min(radius);
+            }
+        }
+        self
+    }
+
+    pub fn elements(&self) -> &[LensElement] {
+        &self.elements
+    }
+
+    fn front_z(&self) -> f32 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear(&self) -> LensElement {
+        self.elements[self.elements.len() - 1]
+    }
+
+    /// Traces a ray from the film out through the lens, or None if the lens blocks it.
+    pub fn trace_from_film(&self, r: Ray) -> Option<Ray> {
+        let mut r = flip(r);
+        let mut z = 0.0;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            z -= element.thickness;
+            let (p, n) = hit_surface(*element, z, r)?;
+            r.origin = p;
+            if element.curvature_radius != 0.0 {
+                let eta_t = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+                r.direction = refract(r.direction, n, element.ior / eta_t)?;
+            }
+        }
+        Some(flip(r))
+    }
+
+    /// Traces a ray from the scene in through the lens towards the film.
+    pub fn trace_from_scene(&self, r: Ray) -> Option<Ray> {
+        let mut r = flip(r);
+        let mut z = -self.front_z();
+        for (i, element) in self.elements.iter().enumerate() {
+            let (p, n) = hit_surface(*element, z, r)?;
+            r.origin = p;
+            if element.curvature_radius != 0.0 {
+                let eta_i = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+                r.direction = refract(r.direction, n, eta_i / element.ior)?;
+            }
+            z += element.thickness;
+        }
+        Some(flip(r))
+    }
+
+    // The z of the principal plane and the focal point of a ray parallel to the axis and
+    // the ray it becomes on the other side of the lens.
+    fn cardinal_points(r_in: Ray, r_out: Ray) -> (f32, f32) {
+        let tf = -r_out.origin.x / r_out.direction.x;
+        let tp = (r_in.origin.x - r_out.origin.x) / r_out.direction.x;
+        (
+            r_out.point_at_parameter(tp).z,
+            r_out.point_at_parameter(tf).z,
+        )
+    }
+
+    /// The distance of the film behind the lens that focuses at `distance` in front of the
+    /// film, approximating the lens by a thick lens. None if the lens can't focus that close.
+    fn focus_thickness(&self, distance: f32) -> Option<f32> {
+        // rays close to the axis, parallel to it from either side
+        let x = 0.001;
+        let from_scene = Ray::new(
+            Vec3::new(x, 0.0, self.front_z() + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            0.0,
+        );
+        let (p_scene, f_scene) =
+            LensSystem::cardinal_points(from_scene, self.trace_from_scene(from_scene)?);
+        let from_film = Ray::new(
+            Vec3::new(x, 0.0, self.rear().thickness - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        let (p_film, _) = LensSystem::cardinal_points(from_film, self.trace_from_film(from_film)?);
+        // in the thick lens equation, 1/f = 1/(z' - p') - 1/(z - p), with z the focused
+        // distance and z' the film, both measured along -z as in pbrt
+        let (p_scene, f_scene, p_film, z) = (-p_scene, -f_scene, -p_film, -distance);
+        let f = f_scene - p_scene;
+        let c = (p_film - z - p_scene) * (p_film - z - 4.0 * f - p_scene);
+        if c <= 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (p_film - z + p_scene - c.sqrt());
+        // a lens without power, such as a lone aperture stop, has no focal point
+        Some(self.rear().thickness + delta).filter(|t| t.is_finite())
+    }
+}
+
+// An axis-aligned rectangle on the plane of the rear element.
+#[derive(Copy, Clone, Debug)]
+struct PupilBounds {
+    min: (f32, f32),
+    max: (f32, f32),
+}
+
+impl PupilBounds {
+    fn square(half_width: f32) -> PupilBounds {
+        PupilBounds {
+            min: (-half_width, -half_width),
+            max: (half_width, half_width),
+        }
+    }
+
+    fn area(&self) -> f32 {
+        (self.max.0 - self.min.0) * (self.max.1 - self.min.1)
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.min.0 && x <= self.max.0 && y >= self.min.1 && y <= self.max.1
+    }
+}
+
+// The radical inverse of `i` in base 2 and 3, a well spread point set on the unit square.
+fn halton(i: usize) -> (f32, f32) {
+    let radical_inverse = |base: usize| {
+        let (mut i, mut inverse, mut scale) = (i, 0.0, 1.0 / base as f32);
+        while i > 0 {
+            inverse += (i % base) as f32 * scale;
+            i /= base;
+            scale /= base as f32;
+        }
+        inverse
+    };
+    (radical_inverse(2), radical_inverse(3))
+}
+
+/// A camera which traces rays through the elements of a real lens. The lens system produces
+/// its own depth of field, distortion and vignetting.
+///
+/// To aim rays at the part of the rear element that light from the scene passes through, the
+/// exit pupil is bounded in advance for a number of distances from the centre of the film.
+#[derive(Clone)]
+pub struct RealisticCamera {
+    position: Vec3,
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    lens: LensSystem,
+    film_width: f32,
+    film_height: f32,
+    pupil_bounds: Vec<PupilBounds>,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl RealisticCamera {
+    /// A camera with its film at `lookfrom`, looking at `lookat`. The film has a diagonal of
+    /// `film_diagonal` millimetres, and the lens is moved so that it focuses `focus_dist` in
+    /// front of the film.
+    ///
+    /// Fails if the lens can't focus that close.
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        mut lens: LensSystem,
+        film_diagonal: f32,
+        aspect: f32,
+        focus_dist: f32,
+    ) -> io::Result<RealisticCamera> {
+        let back = lens.elements.len() - 1;
+        lens.elements[back].thickness = lens.focus_thickness(focus_dist).ok_or_else(|| {
+            invalid_data(&format!("the lens can't focus at distance {focus_dist}"))
+        })?;
+        let diagonal = 0.001 * film_diagonal;
+        let film_height = diagonal / (1.0 + aspect * aspect).sqrt();
+        let (right, up, back) = view_basis(lookfrom, lookat, vup);
+        let mut camera = RealisticCamera {
+            position: lookfrom,
+            right,
+            up,
+            forward: -back,
+            lens,
+            film_width: aspect * film_height,
+            film_height,
+            pupil_bounds: Vec::new(),
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        };
+        camera.pupil_bounds = (0..PUPIL_INTERVALS)
+            .map(|i| {
+                let x0 = i as f32 / PUPIL_INTERVALS as f32 * 0.5 * diagonal;
+                let x1 = (i + 1) as f32 / PUPIL_INTERVALS as f32 * 0.5 * diagonal;
+                camera.bound_exit_pupil(x0, x1)
+            })
+            .collect();
+        Ok(camera)
+    }
+
+    /// See `Camera::with_shutter`.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> RealisticCamera {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+
+    pub fn lens(&self) -> &LensSystem {
+        &self.lens
+    }
+
+    // Bounds the points on the rear element which rays from the film between x0 and x1 along
+    // the x axis pass through the lens from.
+    fn bound_exit_pupil(&self, x0: f32, x1: f32) -> PupilBounds {
+        let rear = self.lens.rear();
+        let search = PupilBounds::square(1.5 * rear.aperture_radius);
+        let mut bounds: Option<PupilBounds> = None;
+        for i in 0..PUPIL_SAMPLES {
+            let t = (i as f32 + 0.5) / PUPIL_SAMPLES as f32;
+            let film = Vec3::new(x0 + t * (x1 - x0), 0.0, 0.0);
+            let (s, t) = halton(i);
+            let x = search.min.0 + s * (search.max.0 - search.min.0);
+            let y = search.min.1 + t * (search.max.1 - search.min.1);
+            let inside = bounds.is_some_and(|b| b.contains(x, y));
+            let on_rear = Vec3::new(x, y, rear.thickness);
+            if inside
+                || self
+                    .lens
+                    .trace_from_film(Ray::new(film, on_rear - film, 0.0))
+                    .is_some()
+            {
+                bounds = Some(match bounds {
+                    Some(b) => PupilBounds {
+                        min: (b.min.0.min(x), b.min.1.min(y)),
+                        max: (b.max.0.max(x), b.max.1.max(y)),
+                    },
+                    None => PupilBounds {
+                        min: (x, y),
+                        max: (x, y),
+                    },
+                });
+            }
+        }
+        let Some(b) = bounds else {
+            return search;
+        };
+        // grow by the spacing of the samples so as not to miss the edge of the pupil
+        let margin =
+            2.0 * 2f32.sqrt() * (search.max.0 - search.min.0) / (PUPIL_SAMPLES as f32).sqrt();
+        PupilBounds {
+            min: (
+                (b.min.0 - margin).max(search.min.0),
+                (b.min.1 - margin).max(search.min.1),
+            ),
+            max: (
+                (b.max.0 + margin).min(search.max.0),
+                (b.max.1 + margin).min(search.max.1),
+            ),
+        }
+    }
+
+    // A point on the rear element within the exit pupil of the film point (x, y), and the
+    // area of the bounds it was chosen from.
+    fn sample_exit_pupil(&self, x: f32, y: f32, rng: &mut Pcg32) -> (Vec3, f32) {
+        let r = x.hypot(y);
+        let diagonal = self.film_width.hypot(self.film_height);
+        let index =
+            ((r / (0.5 * diagonal) * PUPIL_INTERVALS as f32) as usize).min(PUPIL_INTERVALS - 1);
+        let b = self.pupil_bounds[index];
+        let px = b.min.0 + rng.gen::<f32>() * (b.max.0 - b.min.0);
+        let py = b.min.1 + rng.gen::<f32>() * (b.max.1 - b.min.1);
+        // the bounds are along the x axis, so turn them towards the film point
+        let (sin, cos) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+        let rear = Vec3::new(
+            cos * px - sin * py,
+            sin * px + cos * py,
+            self.lens.rear().thickness,
+        );
+        (rear, b.area())
+    }
+}
+
+impl CameraModel for RealisticCamera {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        self.make_weighted_ray(rng, u, v).map(|(r, _)| r)
+    }
+
+    fn make_weighted_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<(Ray, f32)> {
+        // the lens turns the image upside down
+        let film = Vec3::new(
+            (0.5 - u) * self.film_width,
+            (0.5 - v) * self.film_height,
+            0.0,
+        );
+        let (rear, area) = self.sample_exit_pupil(film.x, film.y, rng);
+        let direction = rear - film;
+        let r = self.lens.trace_from_film(Ray::new(film, direction, 0.0))?;
+        // the irradiance falls off with the fourth power of the cosine to the axis, relative
+        // to the centre of the film
+        let cos = direction.make_unit_vector().z;
+        let weight = cos.powi(4) * area / self.pupil_bounds[0].area();
+        let to_world = |p: Vec3| p.x * self.right + p.y * self.up + p.z * self.forward;
+        let time = shutter_time(rng, self.shutter_open, self.shutter_close);
+        Some((
+            Ray::new(
+                self.position + to_world(r.origin),
+                to_world(r.direction),
+                time,
+            ),
+            weight,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LensSystem, RealisticCamera};
+    use crate::{camera::CameraModel, ray::Ray, sampling::Pcg32, vec3::Vec3};
+
+    // A 50 mm f/2 double Gauss lens, as in pbrt's dgauss.50mm.dat.
+    const DOUBLE_GAUSS: &str = "
+# radius  thickness  ior  aperture
+29.475    3.76       1.67   25.2
+84.83     0.12       1      25.2
+19.275    4.025      1.67   23
+40.77     3.275      1.699  23
+12.75     5.705      1      18
+0         4.5        0      17.1   # the aperture stop
+-14.495   1.18       1.603  17
+40.77     6.065      1.658  20
+-20.385   0.19       1      20
+437.065   3.22       1.717  20
+-39.73    0          1      20
+";
+
+    #[test]
+    fn parses_prescriptions() {
+        let lens = LensSystem::parse(DOUBLE_GAUSS).unwrap();
+        assert_eq!(lens.elements().len(), 11);
+        let stop = lens.elements()[5];
+        assert_eq!(stop.curvature_radius, 0.0);
+        assert_eq!(stop.ior, 1.0);
+        assert!((stop.aperture_radius - 0.00855).abs() < 1e-7);
+        let stopped = lens.with_aperture(8.0);
+        assert!((stopped.elements()[5].aperture_radius - 0.004).abs() < 1e-7);
+
+        assert!(LensSystem::parse("# nothing\n").is_err());
+        assert!(LensSystem::parse("1 2 3\n").is_err());
+        assert!(LensSystem::parse("1 2 x 4\n").is_err());
+        assert!(LensSystem::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn focuses_and_vignettes() {
+        let lens = LensSystem::parse(DOUBLE_GAUSS).unwrap();
+        let camera = RealisticCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            lens.with_aperture(4.0),
+            35.0,
+            1.5,
+            5.0,
+        )
+        .unwrap();
+        // the film ends up a few centimetres behind the rear element
+        let rear = camera.lens().elements()[10];
+        assert!(rear.thickness > 0.03 && rear.thickness < 0.06);
+
+        // rays from the centre of the film meet again near the focus distance
+        let mut rng = Pcg32::new(0, 0);
+        let mut traced = 0;
+        for _ in 0..256 {
+            if let Some((r, weight)) = camera.make_weighted_ray(&mut rng, 0.5, 0.5) {
+                assert!(weight > 0.5 && weight <= 1.0);
+                let p = r.point_at_parameter((5.0 - r.origin.z) / r.direction.z);
+                assert!(p.x.hypot(p.y) < 1e-3);
+                traced += 1;
+            }
+        }
+        assert!(traced > 64);
+
+        // the corners of the image are darker
+        let corner: f32 = (0..256)
+            .filter_map(|_| camera.make_weighted_ray(&mut rng, 0.0, 0.0))
+            .map(|(_, weight)| weight)
+            .sum();
+        let centre: f32 = (0..256)
+            .filter_map(|_| camera.make_weighted_ray(&mut rng, 0.5, 0.5))
+            .map(|(_, weight)| weight)
+            .sum();
+        assert!(corner < centre);
+
+        // a ray from the top left of the image leaves the lens towards the top left
+        let r: Ray = (0..64)
+            .find_map(|_| camera.make_ray(&mut rng, 0.1, 0.9))
+            .unwrap();
+        assert!(r.direction.x > 0.0 && r.direction.y > 0.0);
+    }
+
+    #[test]
+    fn fails_to_focus_too_close() {
+        let camera = |lens: LensSystem| {
+            RealisticCamera::new(
+                Vec3::zeros(),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                lens,
+                35.0,
+                1.5,
+                0.05,
+            )
+        };
+        assert!(camera(LensSystem::parse(DOUBLE_GAUSS).unwrap()).is_err());
+        // a pinhole has nothing to focus with
+        assert!(camera(LensSystem::parse("0 50 0 2\n").unwrap()).is_err());
+    }
+}
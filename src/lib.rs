@@ -0,0 +1,102 @@
+//! A physically based path tracer, which grew out of "Ray Tracing in One Weekend".
+//!
+//! A scene is a [`World`] of [`Hitable`] objects with [`Material`]s, optionally with extra
+//! lights and a background, seen through a [`Camera`] or another [`CameraModel`]. [`render`]
+//! traces it with the given [`RenderSettings`] and returns a [`Framebuffer`] of linear radiance.
+//!
+//! ```
+//! use rust_ray_trace::{render, Camera, Hitable, Material, RenderSettings, Sphere, Vec3, World};
+//!
+//! let world = World::new(vec![Hitable::Sphere(Sphere::new(
+//!     Vec3::new(0.0, 0.0, -1.0),
+//!     0.5,
+//!     Material::lambertian(Vec3::new(0.8, 0.3, 0.3)),
+//! ))]);
+//! let settings = RenderSettings::new(32, 16, 4);
+//! let camera = Camera::new(
+//!     Vec3::zeros(),
+//!     Vec3::new(0.0, 0.0, -1.0),
+//!     Vec3::new(0.0, 1.0, 0.0),
+//!     60.0,
+//!     settings.aspect(),
+//!     0.0,
+//!     1.0,
+//! );
+//! let image = render(&settings, &camera, &world);
+//! assert_eq!(image.pixels.len(), 32 * 16);
+//! ```
+//!
+//! Scenes can also be loaded from files with [`gltf_scene`], [`pbrt`], and meshes with
+//! [`mesh::TriangleMesh::load`].
+
+#![deny(
+    clippy::pedantic,
+    rust_2018_idioms,
+    future_incompatible,
+    nonstandard_style
+)]
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss,
+    clippy::float_cmp,
+    clippy::many_single_char_names
+)]
+// Most of the API returns plain values and io::Result, where these add noise rather than help.
+#![allow(
+    clippy::must_use_candidate,
+    clippy::return_self_not_must_use,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc
+)]
+
+pub mod aabb;
+pub mod animation;
+pub mod aperture;
+pub mod bvh;
+pub mod camera;
+pub mod checkpoint;
+pub mod color;
+pub mod csg;
+pub mod environment;
+pub mod gltf_scene;
+pub mod hitable;
+pub mod ies;
+pub mod instance;
+pub mod lens;
+pub mod light;
+pub mod light_sampler;
+pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod panoramic;
+pub mod pbrt;
+pub mod ply;
+pub mod primitives;
+pub mod ray;
+pub mod renderer;
+pub mod sampling;
+pub mod sdf;
+pub mod sky;
+pub mod stereo;
+pub mod stl;
+pub mod texture;
+pub mod tile;
+pub mod transform;
+pub mod vec3;
+pub mod voxel;
+
+pub use animation::{CameraAnimation, CameraKeyframe};
+pub use aperture::Aperture;
+pub use camera::{Camera, CameraModel, OrthographicCamera};
+pub use checkpoint::Checkpoint;
+pub use hitable::{Hitable, Sphere, World};
+pub use lens::{LensSystem, RealisticCamera};
+pub use light::Light;
+pub use material::Material;
+pub use panoramic::{CubeFace, EquirectangularCamera, FisheyeCamera, FisheyeMapping};
+pub use renderer::{render, render_resumable, render_tiles, Framebuffer, RenderSettings};
+pub use stereo::{StereoCamera, StereoLayout};
+pub use tile::{Region, TileOrder};
+pub use transform::Transform;
+pub use vec3::Vec3;
@@ -0,0 +1,418 @@
+use crate::{
+    color::luminance,
+    ies::{IesProfile, Photometry},
+    sampling::{uniform_cone, Pcg32},
+    vec3::Vec3,
+};
+use std::{f32::consts::PI, sync::Arc};
+
+// Lumens per watt, for converting the candela of photometric data into W/sr.
+const LUMINOUS_EFFICACY: f32 = 683.0;
+
+/// A direction sampled towards a light, with the radiance arriving from it and the solid angle
+/// pdf of having sampled it. The direction is a unit vector and the distance to the light is
+/// infinite for lights at infinity. Punctual lights have a pdf of one.
+#[derive(Copy, Clone)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    pub radiance: Vec3,
+    pub pdf: f32,
+}
+
+impl LightSample {
+    fn punctual(direction: Vec3, distance: f32, radiance: Vec3) -> LightSample {
+        LightSample {
+            direction,
+            distance,
+            radiance,
+            pdf: 1.0,
+        }
+    }
+}
+
+// 1 - cos(theta_max) for the cone subtended by a sphere, accurate for distant spheres.
+fn one_minus_cos_subtended(radius_squared: f32, distance_squared: f32) -> f32 {
+    let sin2 = radius_squared / distance_squared;
+    sin2 / (1.0 + (1.0 - sin2).max(0.0).sqrt())
+}
+
+/// A light source at a single point, radiating in all directions. Intensity is in W/sr, scaled
+/// by the colour. With a photometric profile the intensity is the peak of the profile.
+#[derive(Clone)]
+pub struct PointLight {
+    pub position: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub photometry: Option<Photometry>,
+}
+
+// The fraction of the peak intensity emitted along `direction`, away from the light.
+fn profile_intensity(photometry: Option<&Photometry>, direction: Vec3) -> f32 {
+    photometry.map_or(1.0, |p| p.intensity(direction))
+}
+
+impl PointLight {
+    pub fn illuminate(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let direction = (1.0 / distance) * to_light;
+        let intensity = self.intensity * profile_intensity(self.photometry.as_ref(), -direction);
+        if intensity == 0.0 {
+            return None;
+        }
+        Some(LightSample::punctual(
+            direction,
+            distance,
+            (intensity / distance_squared) * self.color,
+        ))
+    }
+
+    pub fn power(&self) -> f32 {
+        let mean = self.photometry.as_ref().map_or(1.0, |p| p.profile.mean());
+        4.0 * PI * self.intensity * mean
+    }
+}
+
+/// A point light restricted to a cone. The intensity (W/sr on the axis) falls off smoothly
+/// between the inner and outer cone angles. A photometric profile, with its nadir along the axis,
+/// further shapes the intensity inside the cone.
+#[derive(Clone)]
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub photometry: Option<Photometry>,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+impl SpotLight {
+    /// The cone angles are half angles measured from the axis, in degrees.
+    pub fn new(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> SpotLight {
+        let outer_angle = outer_angle.max(inner_angle);
+        SpotLight {
+            position,
+            direction: direction.make_unit_vector(),
+            color,
+            intensity,
+            photometry: None,
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.to_radians().cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    pub fn illuminate(&self, p: Vec3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let direction = (1.0 / distance) * to_light;
+        let falloff = self.falloff(-direction.dot(self.direction))
+            * profile_intensity(self.photometry.as_ref(), -direction);
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample::punctual(
+            direction,
+            distance,
+            (self.intensity * falloff / distance_squared) * self.color,
+        ))
+    }
+
+    pub fn cos_outer(&self) -> f32 {
+        self.cos_outer
+    }
+
+    pub fn cos_inner(&self) -> f32 {
+        self.cos_inner
+    }
+
+    pub fn power(&self) -> f32 {
+        // the solid angle of the cone halfway through the falloff region
+        let cone = 2.0 * PI * self.intensity * (1.0 - 0.5 * (self.cos_inner + self.cos_outer));
+        match &self.photometry {
+            Some(p) => cone.min(4.0 * PI * self.intensity * p.profile.mean()),
+            None => cone,
+        }
+    }
+}
+
+/// A light infinitely far away, such as the sun. Irradiance is in W/m^2 on a surface facing the
+/// light, scaled by the colour.
+#[derive(Copy, Clone)]
+pub struct DirectionalLight {
+    /// the direction the light travels in
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub irradiance: f32,
+}
+
+impl DirectionalLight {
+    pub fn illuminate(&self) -> LightSample {
+        LightSample::punctual(
+            -self.direction.make_unit_vector(),
+            f32::MAX,
+            self.irradiance * self.color,
+        )
+    }
+}
+
+/// The light emitted by a sphere with an emissive material. `object` is the index of the sphere
+/// in the world.
+#[derive(Copy, Clone)]
+pub struct SphereLight {
+    pub center: Vec3,
+    pub radius: f32,
+    pub radiance: Vec3,
+    pub object: usize,
+}
+
+impl SphereLight {
+    /// Samples the cone of directions in which the sphere is visible from `p`.
+    pub fn sample(&self, p: Vec3, rng: &mut Pcg32) -> Option<LightSample> {
+        let to_center = self.center - p;
+        let distance_squared = to_center.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        let one_minus_cos = one_minus_cos_subtended(radius_squared, distance_squared);
+        let axis = to_center.make_unit_vector();
+        let direction = uniform_cone(axis, 1.0 - one_minus_cos, rng).make_unit_vector();
+        // distance to the near side of the sphere, clamped to the tangent point for directions
+        // grazing the silhouette
+        let b = to_center.dot(direction);
+        let discriminant = (b * b - (distance_squared - radius_squared)).max(0.0);
+        let distance = b - discriminant.sqrt();
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.radiance,
+            pdf: 1.0 / (2.0 * PI * one_minus_cos),
+        })
+    }
+
+    /// The solid angle pdf of sampling any of the directions towards the sphere from `p`.
+    pub fn pdf(&self, p: Vec3) -> f32 {
+        let distance_squared = (self.center - p).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * one_minus_cos_subtended(radius_squared, distance_squared))
+    }
+
+    pub fn power(&self) -> f32 {
+        PI * 4.0 * PI * self.radius * self.radius * luminance(self.radiance)
+    }
+}
+
+#[derive(Clone)]
+pub enum Light {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+    Sphere(SphereLight),
+}
+
+impl Light {
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Light {
+        Light::Point(PointLight {
+            position,
+            color,
+            intensity,
+            photometry: None,
+        })
+    }
+
+    /// A point light shaped by an IES profile, whose nadir points along `nadir`. The profile's
+    /// candela give the intensity, scaled by the colour.
+    pub fn point_ies(position: Vec3, nadir: Vec3, color: Vec3, profile: Arc<IesProfile>) -> Light {
+        Light::Point(PointLight {
+            position,
+            color,
+            intensity: profile.max_candela() / LUMINOUS_EFFICACY,
+            photometry: Some(Photometry::new(profile, nadir)),
+        })
+    }
+
+    /// A point light emitting `watts` of power in total.
+    pub fn point_with_power(position: Vec3, color: Vec3, watts: f32) -> Light {
+        Light::point(position, color, watts / (4.0 * PI))
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Light {
+        Light::Spot(SpotLight::new(
+            position,
+            direction,
+            color,
+            intensity,
+            inner_angle,
+            outer_angle,
+        ))
+    }
+
+    /// A spot light shaped by an IES profile, whose nadir points along the spot direction.
+    pub fn spot_ies(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        profile: Arc<IesProfile>,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Light {
+        let mut light = SpotLight::new(
+            position,
+            direction,
+            color,
+            profile.max_candela() / LUMINOUS_EFFICACY,
+            inner_angle,
+            outer_angle,
+        );
+        light.photometry = Some(Photometry::new(profile, direction));
+        Light::Spot(light)
+    }
+
+    pub fn directional(direction: Vec3, color: Vec3, irradiance: f32) -> Light {
+        Light::Directional(DirectionalLight {
+            direction,
+            color,
+            irradiance,
+        })
+    }
+
+    /// Samples the light arriving at `p`, ignoring occlusion. None if no light reaches the
+    /// point.
+    pub fn sample(&self, p: Vec3, rng: &mut Pcg32) -> Option<LightSample> {
+        match self {
+            Light::Point(l) => l.illuminate(p),
+            Light::Spot(l) => l.illuminate(p),
+            Light::Directional(l) => Some(l.illuminate()),
+            Light::Sphere(l) => l.sample(p, rng),
+        }
+    }
+
+    /// Whether the light can only be reached by sampling it, never by a scattered ray.
+    pub fn is_delta(&self) -> bool {
+        match self {
+            Light::Point(_) | Light::Spot(_) | Light::Directional(_) => true,
+            Light::Sphere(_) => false,
+        }
+    }
+
+    /// Whether the light is infinitely far away.
+    pub fn is_infinite(&self) -> bool {
+        matches!(self, Light::Directional(_))
+    }
+
+    /// The luminous power of the light, used to decide how often to sample it.
+    pub fn power(&self) -> f32 {
+        match self {
+            Light::Point(l) => l.power() * luminance(l.color),
+            Light::Spot(l) => l.power() * luminance(l.color),
+            Light::Directional(l) => l.irradiance * luminance(l.color),
+            Light::Sphere(l) => l.power(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Light;
+    use crate::{sampling::Pcg32, vec3::Vec3};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn point_light_falls_off_with_the_square_of_distance() {
+        let light = Light::point(Vec3::new(0.0, 4.0, 0.0), Vec3::new(1.0, 0.5, 0.0), 8.0);
+        let mut rng = Pcg32::new(0, 0);
+        let near = light.sample(Vec3::new(0.0, 2.0, 0.0), &mut rng).unwrap();
+        let far = light.sample(Vec3::new(0.0, 0.0, 0.0), &mut rng).unwrap();
+        assert_eq!(near.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert!(close(near.distance, 2.0) && close(far.distance, 4.0));
+        assert!(close(near.radiance.x, 2.0) && close(near.radiance.y, 1.0));
+        assert!(close(far.radiance.x, 0.5));
+        assert_eq!(near.pdf, 1.0);
+        assert!(light.is_delta());
+        assert!(light.sample(Vec3::new(0.0, 4.0, 0.0), &mut rng).is_none());
+    }
+
+    #[test]
+    fn spot_light_cones() {
+        // pointing down from one unit above the plane y = 0, full within 30 degrees and dark
+        // beyond 60
+        let light = Light::spot(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -2.0, 0.0),
+            Vec3::ones(),
+            1.0,
+            30.0,
+            60.0,
+        );
+        let mut rng = Pcg32::new(0, 0);
+        let mut at_angle = |degrees: f32| {
+            let x = degrees.to_radians().tan();
+            light
+                .sample(Vec3::new(x, 0.0, 0.0), &mut rng)
+                .map(|s| s.radiance.x * s.distance * s.distance)
+        };
+        assert!(close(at_angle(0.0).unwrap(), 1.0));
+        assert!(close(at_angle(29.0).unwrap(), 1.0));
+        // smoothly in between, halfway in cosine at the midpoint
+        let mid = 0.5 * (30f32.to_radians().cos() + 60f32.to_radians().cos());
+        let falloff = at_angle(mid.acos().to_degrees()).unwrap();
+        assert!(close(falloff, 0.5));
+        let (a, b) = (at_angle(40.0).unwrap(), at_angle(50.0).unwrap());
+        assert!(1.0 > a && a > b && b > 0.0);
+        assert!(at_angle(61.0).is_none());
+        // nothing behind the light
+        assert!(light.sample(Vec3::new(0.0, 2.0, 0.0), &mut rng).is_none());
+    }
+
+    #[test]
+    fn directional_light_comes_from_against_its_direction() {
+        let light = Light::directional(Vec3::new(0.0, -3.0, 0.0), Vec3::ones(), 2.0);
+        let mut rng = Pcg32::new(0, 0);
+        let sample = light.sample(Vec3::new(5.0, 0.0, 5.0), &mut rng).unwrap();
+        assert_eq!(sample.direction, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(sample.distance, f32::MAX);
+        assert_eq!(sample.radiance, Vec3::new(2.0, 2.0, 2.0));
+        assert!(light.is_infinite() && light.is_delta());
+    }
+}
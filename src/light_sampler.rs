@@ -0,0 +1,413 @@
+use crate::{
+    aabb::Aabb,
+    light::{Light, SpotLight},
+    sampling::Distribution1D,
+    vec3::Vec3,
+};
+use std::f32::consts::PI;
+
+// Spatial and directional bounds of the light emitted by a group of lights, following
+// "Importance Sampling of Many Lights with Adaptive Tree Splitting" by Conty Estevez & Kulla.
+// Emission is bounded by a cone of directions around `axis` with spread `theta_o`, plus up to
+// `theta_e` of falloff around it.
+#[derive(Copy, Clone)]
+struct LightBounds {
+    bounds: Aabb,
+    power: f32,
+    axis: Vec3,
+    theta_o: f32,
+    theta_e: f32,
+}
+
+// Half angle of the smallest cone around `a` which contains the cone (`b`, `theta_b`).
+fn cone_union(a: Vec3, theta_a: f32, b: Vec3, theta_b: f32) -> (Vec3, f32) {
+    if theta_b > theta_a {
+        return cone_union(b, theta_b, a, theta_a);
+    }
+    let theta_d = a.dot(b).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (a, theta_a);
+    }
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    if theta_o >= PI {
+        return (a, PI);
+    }
+    // rotate a towards b by the angle between the new and the old axis
+    let theta_r = theta_o - theta_a;
+    let w = a.cross(b);
+    if w.squared_length() < 1e-12 {
+        return (a, PI);
+    }
+    let w = w.make_unit_vector();
+    let axis = theta_r.cos() * a + theta_r.sin() * w.cross(a);
+    (axis.make_unit_vector(), theta_o)
+}
+
+impl LightBounds {
+    fn for_light(light: &Light) -> Option<LightBounds> {
+        let power = light.power();
+        match light {
+            Light::Point(l) => Some(LightBounds {
+                bounds: Aabb::new(l.position, l.position),
+                power,
+                axis: Vec3::new(0.0, 1.0, 0.0),
+                theta_o: PI,
+                theta_e: 0.5 * PI,
+            }),
+            Light::Spot(l) => Some(LightBounds::for_spot(l, power)),
+            Light::Sphere(l) => {
+                let r = Vec3::new(l.radius, l.radius, l.radius);
+                Some(LightBounds {
+                    bounds: Aabb::new(l.center - r, l.center + r),
+                    power,
+                    axis: Vec3::new(0.0, 1.0, 0.0),
+                    theta_o: PI,
+                    theta_e: 0.5 * PI,
+                })
+            }
+            Light::Directional(_) => None,
+        }
+    }
+
+    fn for_spot(light: &SpotLight, power: f32) -> LightBounds {
+        let theta_o = light.cos_inner().acos();
+        LightBounds {
+            bounds: Aabb::new(light.position, light.position),
+            power,
+            axis: light.direction,
+            theta_o,
+            theta_e: light.cos_outer().acos() - theta_o,
+        }
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0.0 {
+            return *other;
+        }
+        if other.power == 0.0 {
+            return *self;
+        }
+        let (axis, theta_o) = cone_union(self.axis, self.theta_o, other.axis, other.theta_o);
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            power: self.power + other.power,
+            axis,
+            theta_o,
+            theta_e: self.theta_e.max(other.theta_e),
+        }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        0.5 * (self.bounds.min + self.bounds.max)
+    }
+
+    // An estimate of the light arriving at `p` from these bounds, for a receiver with normal
+    // `n`, accounting for distance and the orientation of both the emitters and the receiver.
+    fn importance(&self, p: Vec3, n: Option<Vec3>) -> f32 {
+        let center = self.centroid();
+        let half_diagonal = 0.5 * self.bounds.size().length();
+        let to_p = p - center;
+        // avoid the singularity close to the lights by never going below the bounds' size
+        let distance_squared = to_p.squared_length().max(half_diagonal * half_diagonal);
+        if distance_squared == 0.0 {
+            return self.power;
+        }
+        let distance = to_p.length();
+        let wi = if distance > 0.0 {
+            (1.0 / distance) * to_p
+        } else {
+            self.axis
+        };
+
+        // angle subtended by the bounds as seen from p
+        let theta_b = if self.bounds.contains(p) || distance <= half_diagonal {
+            PI
+        } else {
+            (half_diagonal / distance).clamp(-1.0, 1.0).asin()
+        };
+
+        // how far p is outside the emission cone
+        let theta_w = self.axis.dot(wi).clamp(-1.0, 1.0).acos();
+        let theta = (theta_w - self.theta_o - theta_b).max(0.0);
+        if theta >= self.theta_e {
+            return 0.0;
+        }
+        let mut importance = self.power * theta.cos() / distance_squared;
+
+        if let Some(n) = n {
+            // the receiver may face away from the lights
+            let theta_i = n.dot(-wi).abs().clamp(-1.0, 1.0).acos();
+            importance *= (theta_i - theta_b).max(0.0).cos();
+        }
+        importance.max(0.0)
+    }
+}
+
+enum LightBvhNode {
+    Leaf {
+        bounds: LightBounds,
+        light: usize,
+    },
+    Interior {
+        bounds: LightBounds,
+        // the first child directly follows its parent
+        second_child: usize,
+    },
+}
+
+impl LightBvhNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightBvhNode::Leaf { bounds, .. } | LightBvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Picks lights by walking a bounding volume hierarchy over them, choosing a child at each node
+/// in proportion to its estimated importance to the shading point. Lights at infinity cannot be
+/// bounded and are picked uniformly instead.
+pub struct LightBvh {
+    nodes: Vec<LightBvhNode>,
+    infinite_lights: Vec<usize>,
+    // per light, the left (0) or right (1) turns from the root to its leaf
+    bit_trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Light]) -> LightBvh {
+        let mut infinite_lights = Vec::new();
+        let mut bounded = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match LightBounds::for_light(light) {
+                Some(bounds) if bounds.power > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => infinite_lights.push(index),
+            }
+        }
+        let mut bvh = LightBvh {
+            nodes: Vec::new(),
+            infinite_lights,
+            bit_trails: vec![None; lights.len()],
+        };
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], bit_trail: u64, depth: u32) -> usize {
+        let node_index = self.nodes.len();
+        if lights.len() == 1 || depth == 63 {
+            let (light, bounds) = lights[0];
+            self.nodes.push(LightBvhNode::Leaf { bounds, light });
+            self.bit_trails[light] = Some(bit_trail);
+            return node_index;
+        }
+
+        // split at the median centroid along the largest axis of the centroid bounds
+        let mut centroid_bounds = Aabb::new(lights[0].1.centroid(), lights[0].1.centroid());
+        for (_, bounds) in lights.iter() {
+            let c = bounds.centroid();
+            centroid_bounds = centroid_bounds.union(&Aabb::new(c, c));
+        }
+        let axis = centroid_bounds.size().largest_axis();
+        lights.sort_by(|a, b| {
+            a.1.centroid()[axis]
+                .partial_cmp(&b.1.centroid()[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let mid = lights.len() / 2;
+
+        let bounds = lights
+            .iter()
+            .skip(1)
+            .fold(lights[0].1, |acc, (_, b)| acc.union(b));
+        self.nodes.push(LightBvhNode::Interior {
+            bounds,
+            second_child: 0,
+        });
+        let (left, right) = lights.split_at_mut(mid);
+        self.build(left, bit_trail, depth + 1);
+        let second = self.build(right, bit_trail | (1 << depth), depth + 1);
+        if let LightBvhNode::Interior { second_child, .. } = &mut self.nodes[node_index] {
+            *second_child = second;
+        }
+        node_index
+    }
+
+    fn infinite_probability(&self) -> f32 {
+        let bvh_count = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        let infinite_count = self.infinite_lights.len() as f32;
+        if infinite_count == 0.0 {
+            0.0
+        } else {
+            infinite_count / (infinite_count + bvh_count)
+        }
+    }
+
+    /// Picks a light for the shading point `p` with normal `n` (None inside media), returning its
+    /// index and the probability of having picked it.
+    pub fn sample(&self, p: Vec3, n: Option<Vec3>, mut u: f32) -> Option<(usize, f32)> {
+        let p_infinite = self.infinite_probability();
+        if u < p_infinite {
+            let count = self.infinite_lights.len();
+            let index = ((u / p_infinite * count as f32) as usize).min(count - 1);
+            return Some((self.infinite_lights[index], p_infinite / count as f32));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+        u = ((u - p_infinite) / (1.0 - p_infinite)).min(1.0 - f32::EPSILON);
+        let mut pmf = 1.0 - p_infinite;
+        let mut node_index = 0;
+        loop {
+            match &self.nodes[node_index] {
+                LightBvhNode::Leaf { bounds, light } => {
+                    return if bounds.importance(p, n) > 0.0 {
+                        Some((*light, pmf))
+                    } else {
+                        None
+                    };
+                }
+                LightBvhNode::Interior { second_child, .. } => {
+                    let left = self.nodes[node_index + 1].bounds().importance(p, n);
+                    let right = self.nodes[*second_child].bounds().importance(p, n);
+                    if left == 0.0 && right == 0.0 {
+                        return None;
+                    }
+                    let p_left = left / (left + right);
+                    // reuse the random number for the next level
+                    if u < p_left {
+                        node_index += 1;
+                        pmf *= p_left;
+                        u = (u / p_left).min(1.0 - f32::EPSILON);
+                    } else {
+                        node_index = *second_child;
+                        pmf *= 1.0 - p_left;
+                        u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f32::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The probability of `sample` picking `light` for the shading point `p` with normal `n`.
+    pub fn pmf(&self, p: Vec3, n: Option<Vec3>, light: usize) -> f32 {
+        let p_infinite = self.infinite_probability();
+        if self.infinite_lights.contains(&light) {
+            return p_infinite / self.infinite_lights.len() as f32;
+        }
+        let Some(mut bit_trail) = self.bit_trails.get(light).copied().flatten() else {
+            return 0.0;
+        };
+        let mut pmf = 1.0 - p_infinite;
+        let mut node_index = 0;
+        loop {
+            match &self.nodes[node_index] {
+                LightBvhNode::Leaf { .. } => return pmf,
+                LightBvhNode::Interior { second_child, .. } => {
+                    let left = self.nodes[node_index + 1].bounds().importance(p, n);
+                    let right = self.nodes[*second_child].bounds().importance(p, n);
+                    if left == 0.0 && right == 0.0 {
+                        return 0.0;
+                    }
+                    if bit_trail & 1 == 0 {
+                        pmf *= left / (left + right);
+                        node_index += 1;
+                    } else {
+                        pmf *= right / (left + right);
+                        node_index = *second_child;
+                    }
+                    bit_trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Chooses which light to sample for direct lighting.
+pub enum LightSampler {
+    // Picks lights in proportion to their power, regardless of where they are.
+    Power(Distribution1D),
+    Bvh(LightBvh),
+}
+
+impl LightSampler {
+    pub fn power(lights: &[Light]) -> LightSampler {
+        LightSampler::Power(Distribution1D::new(
+            lights.iter().map(Light::power).collect(),
+        ))
+    }
+
+    pub fn bvh(lights: &[Light]) -> LightSampler {
+        LightSampler::Bvh(LightBvh::new(lights))
+    }
+
+    pub fn sample(&self, p: Vec3, n: Option<Vec3>, u: f32) -> Option<(usize, f32)> {
+        match self {
+            LightSampler::Power(distribution) => {
+                if distribution.count() == 0 || distribution.integral() == 0.0 {
+                    return None;
+                }
+                Some(distribution.sample_discrete(u))
+            }
+            LightSampler::Bvh(bvh) => bvh.sample(p, n, u),
+        }
+    }
+
+    pub fn pmf(&self, p: Vec3, n: Option<Vec3>, light: usize) -> f32 {
+        match self {
+            LightSampler::Power(distribution) => {
+                if distribution.integral() == 0.0 {
+                    return 0.0;
+                }
+                distribution.discrete_pdf(light)
+            }
+            LightSampler::Bvh(bvh) => bvh.pmf(p, n, light),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LightBvh;
+    use crate::{light::Light, vec3::Vec3};
+
+    #[test]
+    fn bvh_pmf_matches_sampling() {
+        let mut lights = Vec::new();
+        for i in 0..10 {
+            let x = i as f32;
+            lights.push(Light::point(Vec3::new(x, 2.0, -x), Vec3::ones(), 1.0 + x));
+        }
+        lights.push(Light::spot(
+            Vec3::new(0.0, 5.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::ones(),
+            10.0,
+            20.0,
+            30.0,
+        ));
+        lights.push(Light::directional(
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::ones(),
+            1.0,
+        ));
+        let bvh = LightBvh::new(&lights);
+
+        let p = Vec3::new(1.0, 0.0, 0.5);
+        let n = Some(Vec3::new(0.0, 1.0, 0.0));
+        let mut total = 0.0;
+        for i in 0..lights.len() {
+            total += bvh.pmf(p, n, i);
+        }
+        assert!((total - 1.0).abs() < 1e-4);
+
+        let samples = 1000;
+        for i in 0..samples {
+            let u = (i as f32 + 0.5) / samples as f32;
+            let (light, pmf) = bvh.sample(p, n, u).unwrap();
+            assert!((bvh.pmf(p, n, light) - pmf).abs() < 1e-5);
+        }
+    }
+}
@@ -4,43 +4,23 @@
     future_incompatible,
     nonstandard_style
 )]
+#![allow(clippy::cast_precision_loss)]
 
-mod camera;
-mod hitable;
-mod material;
-mod ray;
-mod renderer;
-mod vec3;
-
-use camera::Camera;
-use hitable::{Sphere, World};
-use material::Material;
 use minifb::{Key, Window, WindowOptions};
 use rand::prelude::*;
-use vec3::Vec3;
-
-const WIDTH: usize = 640;
-const HEIGHT: usize = 320;
-
-fn main() {
-    println!("starting raytracing now!");
-    let mut window = Window::new(
-        "Raytracer - ESC to exit",
-        WIDTH,
-        HEIGHT,
-        WindowOptions::default(),
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+use rust_ray_trace::{
+    animation::render_sequence, primitives::Plane, render, render_resumable, Camera,
+    CameraAnimation, Checkpoint, Framebuffer, Hitable, Material, RenderSettings, Sphere, Vec3,
+    World,
+};
+use std::{env, ops::RangeInclusive, path::Path, process};
 
+// The cover scene of the book. The small spheres are placed from a fixed seed, so that every
+// run, and every machine rendering part of an animation, sees the same scene.
+fn cover_scene() -> World {
+    let mut rng = StdRng::seed_from_u64(42);
     // hitable spheres
     let mut spheres = vec![
-        Sphere::new(
-            Vec3::new(0.0, -1000.0, -1.0),
-            1000.0,
-            Material::lambertian(Vec3::new(0.5, 0.5, 0.5)),
-        ),
         Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Material::dielectric(1.5)),
         Sphere::new(
             Vec3::new(-4.0, 1.0, 0.0),
@@ -59,8 +39,8 @@ fn main() {
         for b in -5..5 {
             let a = a as f32;
             let b = b as f32;
-            let center = Vec3::new(a + 0.9 * random::<f32>(), 0.2, b + 0.9 * random::<f32>());
-            let choose_mat = random::<f32>();
+            let center = Vec3::new(a + 0.9 * rng.gen::<f32>(), 0.2, b + 0.9 * rng.gen::<f32>());
+            let choose_mat = rng.gen::<f32>();
             if (center - sphere_offset).length() > 0.9 {
                 if choose_mat < 0.8 {
                     // diffuse
@@ -68,9 +48,9 @@ fn main() {
                         center,
                         0.2,
                         Material::lambertian(Vec3::new(
-                            random::<f32>() * random::<f32>(),
-                            random::<f32>() * random::<f32>(),
-                            random::<f32>() * random::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
                         )),
                     ));
                 } else if choose_mat < 0.95 {
@@ -80,37 +60,161 @@ fn main() {
                         0.2,
                         Material::metal(
                             Vec3::new(
-                                random::<f32>() * random::<f32>(),
-                                random::<f32>() * random::<f32>(),
-                                random::<f32>() * random::<f32>(),
+                                rng.gen::<f32>() * rng.gen::<f32>(),
+                                rng.gen::<f32>() * rng.gen::<f32>(),
+                                rng.gen::<f32>() * rng.gen::<f32>(),
                             ),
-                            0.5 * random::<f32>(),
+                            0.5 * rng.gen::<f32>(),
                         ),
-                    ))
+                    ));
                 } else {
-                    spheres.push(Sphere::new(center, 0.2, Material::dielectric(1.5)));
+                    // tinted glass
+                    spheres.push(Sphere::new(
+                        center,
+                        0.2,
+                        Material::tinted_dielectric(
+                            1.5,
+                            Vec3::new(
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                                0.5 * (1.0 + rng.gen::<f32>()),
+                            ),
+                            0.4, // diameter
+                        ),
+                    ));
                 }
             }
         }
     }
 
+    let floor = Plane::new(
+        Vec3::zeros(),
+        Vec3::new(0.0, 1.0, 0.0),
+        Material::lambertian(Vec3::new(0.5, 0.5, 0.5)),
+    );
+    let mut hitables = vec![Hitable::Plane(floor)];
+    hitables.extend(spheres.into_iter().map(Hitable::Sphere));
+    World::new(hitables)
+}
+
+// The value following `name` on the command line.
+fn option<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == name)?;
+    args.get(i + 1).map(String::as_str)
+}
+
+// A frame range written as "first-last", or a single frame.
+fn frame_range(text: &str) -> Option<RangeInclusive<u32>> {
+    let (first, last) = text.split_once('-').unwrap_or((text, text));
+    Some(first.parse().ok()?..=last.parse().ok()?)
+}
+
+// Renders frames of a camera animation to files instead of showing a window:
+//
+//     rust-ray-trace --keyframes camera.txt [--frames 0-47] [--output frames/frame_####.ppm]
+fn render_frames(settings: &RenderSettings, world: &World, args: &[String], keyframes: &str) {
+    let fail = |message: String| -> ! {
+        eprintln!("{message}");
+        process::exit(1);
+    };
+    let animation = CameraAnimation::load(Path::new(keyframes))
+        .unwrap_or_else(|e| fail(format!("could not read {keyframes}: {e}")));
+    let frames = match option(args, "--frames") {
+        Some(text) => {
+            frame_range(text).unwrap_or_else(|| fail(format!("invalid frame range {text:?}")))
+        }
+        None => animation.frames(),
+    };
+    let pattern = option(args, "--output").unwrap_or("frame_####.ppm");
+    render_sequence(
+        settings,
+        &animation,
+        world,
+        frames,
+        pattern,
+        |frame, path| {
+            println!("frame {frame}: {}", path.display());
+        },
+    )
+    .unwrap_or_else(|e| fail(format!("could not write frames: {e}")));
+}
+
+// Renders in passes, saving the progress to a checkpoint file after each, so that a render
+// stopped by a reboot can be taken up again where it left off:
+//
+//     rust-ray-trace --checkpoint render.chk [--resume] [--samples 1024]
+fn render_checkpointed(
+    settings: &RenderSettings,
+    camera: &Camera,
+    world: &World,
+    args: &[String],
+    path: &str,
+) -> Framebuffer {
+    let fail = |message: String| -> ! {
+        eprintln!("{message}");
+        process::exit(1);
+    };
+    let path = Path::new(path);
+    let mut checkpoint = if args.iter().any(|arg| arg == "--resume") {
+        Checkpoint::load(path)
+            .unwrap_or_else(|e| fail(format!("could not read {}: {e}", path.display())))
+    } else {
+        Checkpoint::new(settings)
+    };
+    render_resumable(settings, camera, world, &mut checkpoint, 16, |checkpoint| {
+        checkpoint.save(path)?;
+        let samples = checkpoint.samples(0, 0);
+        println!("saved {samples} samples per pixel to {}", path.display());
+        Ok(())
+    })
+    .unwrap_or_else(|e| fail(format!("could not render from {}: {e}", path.display())))
+}
+
+fn main() {
+    println!("starting raytracing now!");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut settings = RenderSettings::default();
+    if let Some(samples) = option(&args, "--samples") {
+        settings.samples = samples.parse().unwrap_or_else(|_| {
+            eprintln!("invalid sample count {samples:?}");
+            process::exit(1);
+        });
+    }
+    let world = cover_scene();
+
+    if let Some(keyframes) = option(&args, "--keyframes") {
+        render_frames(&settings, &world, &args, keyframes);
+        return;
+    }
+
+    let mut window = Window::new(
+        "Raytracer - ESC to exit",
+        settings.width,
+        settings.height,
+        WindowOptions::default(),
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+
     let lookfrom = Vec3::new(16.0, 2.0, 4.0);
     let lookat = Vec3::new(0.0, 0.0, 0.0);
-    let aspect_ratio = (WIDTH as f32) / (HEIGHT as f32);
 
     let camera = Camera::new(
         lookfrom,
         lookat,
         Vec3::new(0.0, 1.0, 0.0),     // vup
         15.0,                         // vfov
-        aspect_ratio,                 // aspect ratio
+        settings.aspect(),            // aspect ratio
         0.2,                          // aperture
         (lookfrom - lookat).length(), // focus distance
     );
 
-    let world = World::new(spheres);
-
-    let buffer = renderer::render(WIDTH, HEIGHT, camera, world);
+    let image = match option(&args, "--checkpoint") {
+        Some(path) => render_checkpointed(&settings, &camera, &world, &args, path),
+        None => render(&settings, &camera, &world),
+    };
+    let buffer = image.to_bgra();
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         // We unwrap here as we want this code to exit if it fails.
@@ -1,19 +1,47 @@
 use crate::{
     hitable::HitRecord,
+    medium::{PhaseFunction, VolumeEmission},
     ray::Ray,
-    vec3::{random_in_unit_sphere, Vec3},
+    sampling::Pcg32,
+    texture::Texture,
+    vec3::{random_in_unit_sphere, random_unit_vector, Vec3},
 };
 use rand::prelude::*;
+use std::{f32::consts::PI, sync::Arc};
 
 #[derive(Copy, Clone)]
 pub struct Scatter {
     pub attenuation: Vec3,
     pub ray: Ray,
+    /// The solid angle pdf of the scattered direction, or None for specular scattering which
+    /// cannot be combined with light sampling.
+    pub pdf: Option<f32>,
 }
 
 impl Scatter {
     pub fn new(attenuation: Vec3, ray: Ray) -> Scatter {
-        Scatter { attenuation, ray }
+        Scatter {
+            attenuation,
+            ray,
+            pdf: None,
+        }
+    }
+
+    pub fn with_pdf(attenuation: Vec3, ray: Ray, pdf: f32) -> Scatter {
+        Scatter {
+            attenuation,
+            ray,
+            pdf: Some(pdf),
+        }
+    }
+}
+
+// The normal on the side of the surface the ray arrived from.
+fn facing_normal(direction: Vec3, n: Vec3) -> Vec3 {
+    if direction.dot(n) > 0.0 {
+        -n
+    } else {
+        n
     }
 }
 
@@ -23,11 +51,22 @@ pub struct Lambertian {
 }
 
 impl Lambertian {
-    pub fn scatter(self, _: Ray, hit: HitRecord<'_>, rng: &mut ThreadRng) -> Scatter {
-        let target = hit.p + hit.n + random_in_unit_sphere(rng);
-        let attenuation = self.albedo;
-        let scattered_ray = Ray::new(hit.p, target - hit.p);
-        Scatter::new(attenuation, scattered_ray)
+    /// Returns the BRDF times the cosine term for light arriving from `direction`, and the pdf
+    /// of `scatter` sampling that direction.
+    pub fn eval(self, ray: Ray, hit: &HitRecord<'_>, direction: Vec3) -> (Vec3, f32) {
+        let n = facing_normal(ray.direction, hit.n);
+        let cosine = n.dot(direction.make_unit_vector()).max(0.0);
+        ((cosine / PI) * (self.albedo * hit.color), cosine / PI)
+    }
+
+    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Scatter {
+        // offsetting by a point on the unit sphere gives a cosine distributed direction
+        let n = facing_normal(ray.direction, hit.n);
+        let target = hit.p + n + random_unit_vector(rng);
+        let attenuation = self.albedo * hit.color;
+        let scattered_ray = Ray::new(hit.p, target - hit.p, ray.time);
+        let cosine = n.dot(scattered_ray.direction.make_unit_vector()).max(0.0);
+        Scatter::with_pdf(attenuation, scattered_ray, cosine / PI)
     }
 }
 
@@ -38,10 +77,14 @@ pub struct Metal {
 }
 
 impl Metal {
-    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut ThreadRng) -> Scatter {
+    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Scatter {
         let reflected = ray.direction.reflect(hit.n);
-        let attenuation = self.albedo;
-        let scattered = Ray::new(hit.p, reflected + self.fuzz * random_in_unit_sphere(rng));
+        let attenuation = self.albedo * hit.color;
+        let scattered = Ray::new(
+            hit.p,
+            reflected + self.fuzz * random_in_unit_sphere(rng),
+            ray.time,
+        );
         Scatter::new(attenuation, scattered)
     }
 }
@@ -49,9 +92,11 @@ impl Metal {
 #[derive(Copy, Clone)]
 pub struct Dielectric {
     pub refraction_index: f32,
+    /// Beer-Lambert absorption coefficient per unit distance travelled inside the medium
+    pub absorption: Vec3,
 }
 
-fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
+pub(crate) fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
     // ni * sin(i) = nt * sin(t)
     // sin(t) = sin(i) * (ni / nt)
     let uv = v.make_unit_vector();
@@ -65,6 +110,9 @@ fn refract(v: Vec3, n: Vec3, ni_over_nt: f32) -> Option<Vec3> {
     }
 }
 
+/// The least transmittance `Dielectric::absorption_from_transmittance` accepts.
+pub const MIN_TRANSMITTANCE: f32 = 1e-6;
+
 fn schlick(cosine: f32, refraction_index: f32) -> f32 {
     let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
     r0 = r0 * r0;
@@ -72,9 +120,36 @@ fn schlick(cosine: f32, refraction_index: f32) -> f32 {
 }
 
 impl Dielectric {
-    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut ThreadRng) -> Scatter {
+    /// The absorption coefficient which leaves `transmittance` of the light after travelling
+    /// `distance` units inside the medium. Transmittance is clamped to [`MIN_TRANSMITTANCE`, 1],
+    /// as none at all would need an infinite coefficient. Panics unless `distance` is positive.
+    pub fn absorption_from_transmittance(transmittance: Vec3, distance: f32) -> Vec3 {
+        assert!(
+            distance > 0.0,
+            "the distance of a transmittance must be positive"
+        );
+        let clamp = |t: f32| t.clamp(MIN_TRANSMITTANCE, 1.0);
+        let transmittance = Vec3::new(
+            clamp(transmittance.x),
+            clamp(transmittance.y),
+            clamp(transmittance.z),
+        );
+        (-1.0 / distance) * transmittance.ln()
+    }
+
+    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Scatter {
+        let inside = ray.direction.dot(hit.n) > 0.0;
+        // the ray travelled from its origin to the hit point inside the medium, so attenuate
+        // according to the distance covered
+        let attenuation = if inside {
+            let distance = hit.t * ray.direction.length();
+            (-distance * self.absorption).exp()
+        } else {
+            Vec3::ones()
+        };
+
         // if the ray direction and hit normal are in the same half-sphere
-        let (outward_normal, ni_over_nt, cosine) = if ray.direction.dot(hit.n) > 0.0 {
+        let (outward_normal, ni_over_nt, cosine) = if inside {
             (
                 -hit.n,
                 self.refraction_index,
@@ -95,18 +170,147 @@ impl Dielectric {
             } else {
                 refracted
             };
-            Scatter::new(Vec3::ones(), Ray::new(hit.p, out_dir))
+            Scatter::new(attenuation, Ray::new(hit.p, out_dir, ray.time))
         } else {
-            Scatter::new(Vec3::ones(), Ray::new(hit.p, ray.direction.reflect(hit.n)))
+            Scatter::new(
+                attenuation,
+                Ray::new(hit.p, ray.direction.reflect(hit.n), ray.time),
+            )
+        }
+    }
+}
+
+/// Scattering inside a participating medium, where the phase function takes the role of the BRDF.
+#[derive(Clone)]
+pub struct Volumetric {
+    pub albedo: Vec3,
+    pub phase: PhaseFunction,
+    pub emission: Option<VolumeEmission>,
+}
+
+impl Volumetric {
+    /// The emitted radiance is weighted by the probability of the collision being an absorption,
+    /// since only the absorbing part of the medium emits.
+    pub fn emitted(&self, hit: &HitRecord<'_>) -> Vec3 {
+        match &self.emission {
+            Some(emission) => (Vec3::ones() - self.albedo) * emission.emitted(hit.p),
+            None => Vec3::zeros(),
         }
     }
+
+    pub fn eval(&self, ray: Ray, direction: Vec3) -> (Vec3, f32) {
+        let phase = self.phase.eval(ray.direction, direction);
+        (phase * self.albedo, phase)
+    }
+
+    pub fn scatter(&self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Scatter {
+        let direction = self.phase.sample(ray.direction, rng);
+        let pdf = self.phase.eval(ray.direction, direction);
+        Scatter::with_pdf(self.albedo, Ray::new(hit.p, direction, ray.time), pdf)
+    }
 }
 
+/// A surface emitting light from its front side, which absorbs all light arriving at it.
 #[derive(Copy, Clone)]
+pub struct Emissive {
+    pub radiance: Vec3,
+}
+
+impl Emissive {
+    pub fn emitted(self, ray: Ray, hit: &HitRecord<'_>) -> Vec3 {
+        if ray.direction.dot(hit.n) < 0.0 {
+            self.radiance
+        } else {
+            Vec3::zeros()
+        }
+    }
+}
+
+/// The metallic-roughness material of glTF. Dielectric surfaces are a diffuse base under a
+/// specular coat with a reflectance of 4% at normal incidence, metals reflect in the base colour.
+/// The specular lobe is glossy like `Metal`, with the roughness as fuzz. The factors are
+/// multiplied by the textures, whose metallic value is in the blue and roughness in the green
+/// channel.
+#[derive(Clone)]
+pub struct Pbr {
+    pub base_color: Vec3,
+    pub base_color_texture: Option<Arc<Texture>>,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub metallic_roughness_texture: Option<Arc<Texture>>,
+    pub emissive: Vec3,
+    pub emissive_texture: Option<Arc<Texture>>,
+}
+
+// A texture lookup at the hit point, or white without a texture.
+fn lookup(texture: Option<&Arc<Texture>>, hit: &HitRecord<'_>) -> Vec3 {
+    texture.map_or(Vec3::ones(), |t| t.sample(hit.u, hit.v))
+}
+
+impl Pbr {
+    // The base colour, metallic and roughness at the hit point.
+    fn parameters(&self, hit: &HitRecord<'_>) -> (Vec3, f32, f32) {
+        let base = self.base_color * lookup(self.base_color_texture.as_ref(), hit) * hit.color;
+        let mr = lookup(self.metallic_roughness_texture.as_ref(), hit);
+        let metallic = (self.metallic * mr.z).clamp(0.0, 1.0);
+        let roughness = (self.roughness * mr.y).clamp(0.0, 1.0);
+        (base, metallic, roughness)
+    }
+
+    // The probability of scattering specularly, which is the Fresnel reflectance of the coat
+    // for dielectrics.
+    fn specular_probability(metallic: f32, cosine: f32) -> f32 {
+        let coat = schlick(cosine, 1.5);
+        metallic + (1.0 - metallic) * coat
+    }
+
+    pub fn emitted(&self, hit: &HitRecord<'_>) -> Vec3 {
+        self.emissive * lookup(self.emissive_texture.as_ref(), hit)
+    }
+
+    /// Only the diffuse lobe takes part in light sampling.
+    pub fn eval(&self, ray: Ray, hit: &HitRecord<'_>, direction: Vec3) -> (Vec3, f32) {
+        let (base, metallic, _) = self.parameters(hit);
+        let n = facing_normal(ray.direction, hit.n);
+        let incident = -ray.direction.make_unit_vector().dot(n);
+        let diffuse = 1.0 - Pbr::specular_probability(metallic, incident.max(0.0));
+        let cosine = n.dot(direction.make_unit_vector()).max(0.0);
+        let pdf = diffuse * cosine / PI;
+        (pdf * base, pdf)
+    }
+
+    pub fn scatter(&self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Scatter {
+        let (base, metallic, roughness) = self.parameters(&hit);
+        let n = facing_normal(ray.direction, hit.n);
+        let incident = (-ray.direction.make_unit_vector().dot(n)).max(0.0);
+        let specular = Pbr::specular_probability(metallic, incident);
+        if rng.gen::<f32>() < specular {
+            // the reflectance tends to white at grazing angles, for metals as well
+            let f0 = (1.0 - metallic) * Vec3::new(0.04, 0.04, 0.04) + metallic * base;
+            let grazing = (1.0 - incident).powf(5.0);
+            let reflectance = f0 + grazing * (Vec3::ones() - f0);
+            let direction = ray.direction.reflect(n) + roughness * random_in_unit_sphere(rng);
+            Scatter::new(
+                (1.0 / specular) * reflectance,
+                Ray::new(hit.p, direction, ray.time),
+            )
+        } else {
+            let target = hit.p + n + random_unit_vector(rng);
+            let scattered_ray = Ray::new(hit.p, target - hit.p, ray.time);
+            let cosine = n.dot(scattered_ray.direction.make_unit_vector()).max(0.0);
+            Scatter::with_pdf(base, scattered_ray, (1.0 - specular) * cosine / PI)
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Material {
     Dielectric(Dielectric),
+    Emissive(Emissive),
     Lambertian(Lambertian),
     Metal(Metal),
+    Pbr(Pbr),
+    Volumetric(Volumetric),
 }
 
 impl Material {
@@ -118,15 +322,140 @@ impl Material {
         Material::Metal(Metal { albedo, fuzz })
     }
 
+    /// An untextured metallic-roughness material.
+    pub fn pbr(base_color: Vec3, metallic: f32, roughness: f32) -> Material {
+        Material::Pbr(Pbr {
+            base_color,
+            base_color_texture: None,
+            metallic,
+            roughness,
+            metallic_roughness_texture: None,
+            emissive: Vec3::zeros(),
+            emissive_texture: None,
+        })
+    }
+
     pub fn dielectric(refraction_index: f32) -> Material {
-        return Material::Dielectric(Dielectric { refraction_index });
+        Material::Dielectric(Dielectric {
+            refraction_index,
+            absorption: Vec3::zeros(),
+        })
     }
 
-    pub fn scatter(self, ray: Ray, hit: HitRecord<'_>, rng: &mut ThreadRng) -> Scatter {
-        match hit.material {
-            Material::Dielectric(d) => d.scatter(ray, hit, rng),
-            Material::Lambertian(l) => l.scatter(ray, hit, rng),
-            Material::Metal(m) => m.scatter(ray, hit, rng),
+    pub fn absorbing_dielectric(refraction_index: f32, absorption: Vec3) -> Material {
+        Material::Dielectric(Dielectric {
+            refraction_index,
+            absorption,
+        })
+    }
+
+    /// Glass tinted so that light travelling `distance` units through it is left with
+    /// `transmittance`, e.g. the colour of a 1 cm thick slab.
+    pub fn tinted_dielectric(
+        refraction_index: f32,
+        transmittance: Vec3,
+        distance: f32,
+    ) -> Material {
+        Material::absorbing_dielectric(
+            refraction_index,
+            Dielectric::absorption_from_transmittance(transmittance, distance),
+        )
+    }
+
+    pub fn emissive(radiance: Vec3) -> Material {
+        Material::Emissive(Emissive { radiance })
+    }
+
+    pub fn volumetric(albedo: Vec3, phase: PhaseFunction) -> Material {
+        Material::Volumetric(Volumetric {
+            albedo,
+            phase,
+            emission: None,
+        })
+    }
+
+    /// Evaluates scattering from `ray` into `direction` for light sampling. Returns the
+    /// attenuation (BSDF times cosine, or the phase function) and the pdf of `scatter` choosing
+    /// the direction, or None for materials which only scatter specularly.
+    pub fn eval(&self, ray: Ray, hit: &HitRecord<'_>, direction: Vec3) -> Option<(Vec3, f32)> {
+        match self {
+            Material::Lambertian(l) => Some(l.eval(ray, hit, direction)),
+            Material::Pbr(p) => Some(p.eval(ray, hit, direction)),
+            Material::Volumetric(v) => Some(v.eval(ray, direction)),
+            Material::Dielectric(_) | Material::Emissive(_) | Material::Metal(_) => None,
+        }
+    }
+
+    pub fn emitted(&self, ray: Ray, hit: &HitRecord<'_>) -> Vec3 {
+        match self {
+            Material::Emissive(e) => e.emitted(ray, hit),
+            Material::Pbr(p) => p.emitted(hit),
+            Material::Volumetric(v) => v.emitted(hit),
+            _ => Vec3::zeros(),
         }
     }
+
+    /// Returns None if the light is absorbed.
+    pub fn scatter(&self, ray: Ray, hit: HitRecord<'_>, rng: &mut Pcg32) -> Option<Scatter> {
+        match self {
+            Material::Dielectric(d) => Some(d.scatter(ray, hit, rng)),
+            Material::Emissive(_) => None,
+            Material::Lambertian(l) => Some(l.scatter(ray, hit, rng)),
+            Material::Metal(m) => Some(m.scatter(ray, hit, rng)),
+            Material::Pbr(p) => Some(p.scatter(ray, hit, rng)),
+            Material::Volumetric(v) => Some(v.scatter(ray, hit, rng)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dielectric, Material};
+    use crate::{hitable::HitRecord, ray::Ray, sampling::Pcg32, vec3::Vec3};
+
+    #[test]
+    fn absorption_from_transmittance() {
+        let absorption = Dielectric::absorption_from_transmittance(Vec3::new(0.5, 1.0, 0.0), 2.0);
+        assert!((absorption.x - 0.5f32.ln() / -2.0).abs() < 1e-6);
+        assert_eq!(absorption.y, 0.0);
+        // no transmittance at all is clamped to a finite coefficient
+        assert!(absorption.z.is_finite() && absorption.z > 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn absorption_over_no_distance() {
+        Dielectric::absorption_from_transmittance(Vec3::ones(), 0.0);
+    }
+
+    #[test]
+    fn dielectric_attenuation() {
+        let material = Material::tinted_dielectric(1.5, Vec3::new(0.5, 0.25, 1.0), 2.0);
+        let Material::Dielectric(glass) = material else {
+            unreachable!();
+        };
+        let mut rng = Pcg32::new(0, 0);
+        let hit = |t: f32, n: Vec3| HitRecord {
+            t,
+            p: Vec3::zeros(),
+            n,
+            u: 0.0,
+            v: 0.0,
+            color: Vec3::ones(),
+            material: &material,
+        };
+        // leaving the glass after two units inside, at normal incidence
+        let inside = Ray::new(Vec3::new(0.0, 0.0, -2.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let scatter = glass.scatter(inside, hit(2.0, Vec3::new(0.0, 0.0, 1.0)), &mut rng);
+        assert!((scatter.attenuation - Vec3::new(0.5, 0.25, 1.0)).length() < 1e-5);
+        // a ray direction twice as long covers the same distance in half the ray parameter
+        let inside = Ray::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let scatter = glass.scatter(inside, hit(0.5, Vec3::new(0.0, 0.0, 1.0)), &mut rng);
+        let half = Vec3::new(0.5f32.sqrt(), 0.5, 1.0);
+        assert!((scatter.attenuation - half).length() < 1e-5);
+        // entering the glass is not attenuated
+        let outside = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let scatter = glass.scatter(outside, hit(2.0, Vec3::new(0.0, 0.0, 1.0)), &mut rng);
+        assert_eq!(scatter.attenuation, Vec3::ones());
+    }
 }
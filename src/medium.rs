@@ -0,0 +1,361 @@
+use crate::{
+    color::blackbody,
+    hitable::{HitRecord, Hitable},
+    material::Material,
+    ray::Ray,
+    sampling::Pcg32,
+    vec3::{coordinate_system, Vec3},
+    voxel::VoxelGrid,
+};
+use rand::prelude::*;
+use std::{f32::consts::PI, sync::Arc};
+
+// Upper bound on the number of boundary crossings followed along a single ray.
+const MAX_BOUNDARY_CROSSINGS: usize = 64;
+
+#[derive(Copy, Clone)]
+pub enum PhaseFunction {
+    Isotropic,
+    // The asymmetry parameter g lies in (-1, 1): positive values scatter forward, negative
+    // values backward.
+    HenyeyGreenstein(f32),
+}
+
+impl PhaseFunction {
+    /// Evaluates the phase function for light travelling along `direction` being scattered
+    /// into `scattered`. The phase function is normalized, so this is also the sampling pdf.
+    pub fn eval(self, direction: Vec3, scattered: Vec3) -> f32 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let cos_theta =
+                    direction.dot(scattered) / (direction.length() * scattered.length());
+                let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+            }
+        }
+    }
+
+    /// Samples a unit direction to continue along, given the direction the light was travelling.
+    pub fn sample(self, direction: Vec3, rng: &mut Pcg32) -> Vec3 {
+        let u1 = rng.gen::<f32>();
+        let u2 = rng.gen::<f32>();
+        let cos_theta = match self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let sq = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+                ((1.0 + g * g - sq * sq) / (2.0 * g)).clamp(-1.0, 1.0)
+            }
+            _ => 1.0 - 2.0 * u1,
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        let w = direction.make_unit_vector();
+        let (u, v) = coordinate_system(w);
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+}
+
+/// A medium of constant density. It fills the inside of a closed shape when wrapped in a
+/// `ConstantMedium`, or the world around the scene when used as the world's atmosphere.
+#[derive(Clone)]
+pub struct HomogeneousMedium {
+    pub density: f32,
+    pub material: Material,
+}
+
+impl HomogeneousMedium {
+    pub fn new(density: f32, albedo: Vec3, phase: PhaseFunction) -> HomogeneousMedium {
+        HomogeneousMedium {
+            density,
+            material: Material::volumetric(albedo, phase),
+        }
+    }
+
+    /// Samples the free-flight distance until the next collision with the medium.
+    pub fn sample_distance(&self, rng: &mut Pcg32) -> f32 {
+        if self.density <= 0.0 {
+            return f32::MAX;
+        }
+        -(1.0 - rng.gen::<f32>()).ln() / self.density
+    }
+
+    pub fn transmittance(&self, distance: f32) -> f32 {
+        (-self.density * distance).exp()
+    }
+
+    fn hit_record(&self, ray: &Ray, t: f32) -> HitRecord<'_> {
+        HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            // media have no surface, the normal just faces back along the ray
+            n: -ray.direction.make_unit_vector(),
+            u: 0.0,
+            v: 0.0,
+            color: Vec3::ones(),
+            material: &self.material,
+        }
+    }
+}
+
+/// A homogeneous medium filling the inside of a closed boundary shape, such as a puff of smoke.
+/// The boundary must have outward facing normals.
+#[derive(Clone)]
+pub struct ConstantMedium {
+    pub boundary: Box<Hitable>,
+    pub medium: HomogeneousMedium,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Hitable, medium: HomogeneousMedium) -> ConstantMedium {
+        ConstantMedium {
+            boundary: Box::new(boundary),
+            medium,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        let ray_length = ray.direction.length();
+        let mut distance = self.medium.sample_distance(rng);
+        for (t0, t1) in inside_intervals(&self.boundary, ray, t_min, t_max, rng) {
+            let span = (t1 - t0) * ray_length;
+            if distance < span {
+                return Some(self.medium.hit_record(ray, t0 + distance / ray_length));
+            }
+            distance -= span;
+        }
+        None
+    }
+
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        let ray_length = ray.direction.length();
+        inside_intervals(&self.boundary, ray, t_min, t_max, rng)
+            .iter()
+            .map(|(t0, t1)| self.medium.transmittance((t1 - t0) * ray_length))
+            .product()
+    }
+}
+
+// Finds the parameter intervals, clipped to [t_min, t_max], in which the ray is inside the
+// closed boundary. Works for non-convex shapes by walking every boundary crossing.
+fn inside_intervals(
+    boundary: &Hitable,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+    rng: &mut Pcg32,
+) -> Vec<(f32, f32)> {
+    let mut intervals = Vec::new();
+    let mut entry: Option<f32> = None;
+    let mut t = -f32::MAX;
+    for _ in 0..MAX_BOUNDARY_CROSSINGS {
+        let Some(hit) = boundary.hit(ray, t, f32::MAX, rng) else {
+            break;
+        };
+        if ray.direction.dot(hit.n) > 0.0 {
+            // leaving the shape
+            let t0 = entry.unwrap_or(-f32::MAX).max(t_min);
+            let t1 = hit.t.min(t_max);
+            if t0 < t1 {
+                intervals.push((t0, t1));
+            }
+            entry = None;
+        } else {
+            entry = Some(hit.t);
+        }
+        if hit.t >= t_max {
+            break;
+        }
+        t = hit.t + 0.0001;
+    }
+    intervals
+}
+
+/// Light emitted by a voxel grid, e.g. for fire. The emission channel is scaled by `color`, and
+/// the temperature channel glows like a black body with a brightness following the
+/// Stefan-Boltzmann law, relative to a 1000 K body of radiance `temperature_scale`.
+#[derive(Clone)]
+pub struct VolumeEmission {
+    pub grid: Arc<VoxelGrid>,
+    pub color: Vec3,
+    pub temperature_scale: f32,
+}
+
+impl VolumeEmission {
+    pub fn emitted(&self, p: Vec3) -> Vec3 {
+        let mut radiance = self.grid.emission_at(p) * self.color;
+        if self.temperature_scale > 0.0 {
+            let temperature = self.grid.temperature_at(p);
+            let relative = temperature / 1000.0;
+            radiance += (self.temperature_scale * relative.powi(4)) * blackbody(temperature);
+        }
+        radiance
+    }
+}
+
+/// A heterogeneous medium with density given by a voxel grid. Collisions are sampled with delta
+/// tracking and shadow rays are attenuated with ratio tracking, both against the maximum density
+/// in the grid.
+#[derive(Clone)]
+pub struct GridMedium {
+    pub grid: Arc<VoxelGrid>,
+    pub density_scale: f32,
+    pub material: Material,
+    majorant: f32,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: Arc<VoxelGrid>,
+        density_scale: f32,
+        albedo: Vec3,
+        phase: PhaseFunction,
+    ) -> GridMedium {
+        let majorant = density_scale * grid.max_density();
+        GridMedium {
+            grid,
+            density_scale,
+            material: Material::volumetric(albedo, phase),
+            majorant,
+        }
+    }
+
+    /// Makes the grid glow according to its emission and temperature channels.
+    pub fn with_emission(mut self, color: Vec3, temperature_scale: f32) -> GridMedium {
+        if let Material::Volumetric(volumetric) = &mut self.material {
+            volumetric.emission = Some(VolumeEmission {
+                grid: self.grid.clone(),
+                color,
+                temperature_scale,
+            });
+        }
+        self
+    }
+
+    fn density_at(&self, p: Vec3) -> f32 {
+        self.density_scale * self.grid.density_at(p)
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> Option<HitRecord<'_>> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (mut t, t_end) = self.grid.bounds.hit(ray, t_min, t_max)?;
+        let inv_step = 1.0 / (self.majorant * ray.direction.length());
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() * inv_step;
+            if t >= t_end {
+                return None;
+            }
+            // accept a real collision with probability density / majorant, otherwise it was a
+            // null collision and tracking continues
+            let p = ray.point_at_parameter(t);
+            if rng.gen::<f32>() * self.majorant < self.density_at(p) {
+                return Some(HitRecord {
+                    t,
+                    p,
+                    n: -ray.direction.make_unit_vector(),
+                    u: 0.0,
+                    v: 0.0,
+                    color: Vec3::ones(),
+                    material: &self.material,
+                });
+            }
+        }
+    }
+
+    pub fn transmittance(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut Pcg32) -> f32 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        let Some((mut t, t_end)) = self.grid.bounds.hit(ray, t_min, t_max) else {
+            return 1.0;
+        };
+        let inv_step = 1.0 / (self.majorant * ray.direction.length());
+        let mut transmittance = 1.0;
+        loop {
+            t -= (1.0 - rng.gen::<f32>()).ln() * inv_step;
+            if t >= t_end {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density_at(ray.point_at_parameter(t)) / self.majorant;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConstantMedium, HomogeneousMedium, PhaseFunction};
+    use crate::{
+        hitable::{Hitable, Sphere},
+        material::Material,
+        ray::Ray,
+        sampling::Pcg32,
+        vec3::Vec3,
+    };
+    use std::f32::consts::PI;
+
+    const SAMPLES: usize = 100_000;
+
+    #[test]
+    fn mean_free_path() {
+        let medium = HomogeneousMedium::new(4.0, Vec3::ones(), PhaseFunction::Isotropic);
+        let mut rng = Pcg32::new(0, 0);
+        let mean = (0..SAMPLES)
+            .map(|_| medium.sample_distance(&mut rng))
+            .sum::<f32>()
+            / SAMPLES as f32;
+        assert!((mean - 0.25).abs() < 0.01);
+        let empty = HomogeneousMedium::new(0.0, Vec3::ones(), PhaseFunction::Isotropic);
+        assert_eq!(empty.sample_distance(&mut rng), f32::MAX);
+        assert_eq!(empty.transmittance(10.0), 1.0);
+    }
+
+    #[test]
+    fn phase_functions() {
+        let direction = Vec3::new(0.0, 0.0, 2.0);
+        let mut rng = Pcg32::new(0, 0);
+        for &g in &[0.0, 0.7, -0.4] {
+            let phase = PhaseFunction::HenyeyGreenstein(g);
+            // the mean cosine of the scattering angle is g
+            let mean = (0..SAMPLES)
+                .map(|_| phase.sample(direction, &mut rng).z)
+                .sum::<f32>()
+                / SAMPLES as f32;
+            assert!((mean - g).abs() < 0.01);
+            // the phase function integrates to one over the sphere
+            let steps = 1000;
+            let integral: f32 = (0..steps)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as f32 + 0.5) / steps as f32;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                    let scattered = Vec3::new(sin_theta, 0.0, cos_theta);
+                    2.0 * PI * phase.eval(direction, scattered) * 2.0 / steps as f32
+                })
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3);
+        }
+        let isotropic = PhaseFunction::Isotropic;
+        assert_eq!(isotropic.eval(direction, -direction), 1.0 / (4.0 * PI));
+        let sample = isotropic.sample(direction, &mut rng);
+        assert!((sample.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn transmittance_through_a_sphere() {
+        let boundary = Sphere::new(Vec3::zeros(), 1.0, Material::lambertian(Vec3::ones()));
+        let medium = HomogeneousMedium::new(0.5, Vec3::ones(), PhaseFunction::Isotropic);
+        let smoke = ConstantMedium::new(Hitable::Sphere(boundary), medium);
+        let mut rng = Pcg32::new(0, 0);
+        // straight through the centre covers the diameter, whatever the length of the direction
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 2.0), 0.0);
+        let t = smoke.transmittance(&ray, 0.0, f32::MAX, &mut rng);
+        assert!((t - (-0.5f32 * 2.0).exp()).abs() < 1e-5);
+        // starting in the middle, and stopping short of the far side
+        let ray = Ray::new(Vec3::zeros(), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let t = smoke.transmittance(&ray, 0.0, 0.5, &mut rng);
+        assert!((t - (-0.5f32 * 0.5).exp()).abs() < 1e-5);
+        // missing the sphere
+        let ray = Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert_eq!(smoke.transmittance(&ray, 0.0, f32::MAX, &mut rng), 1.0);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    aabb::Aabb, bvh::Bvh, hitable::HitRecord, material::Material, ply, ray::Ray, stl, vec3::Vec3,
+};
+use std::{
+    io::{self, Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+/// Indexed triangles with optional per-vertex attributes. Triangles wind counterclockwise when
+/// seen from the front, which is taken to be the outside.
+#[derive(Clone, Debug, Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub colors: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl TriangleMesh {
+    /// Loads a PLY or STL file, depending on the extension.
+    pub fn load(path: &Path) -> io::Result<TriangleMesh> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("ply") => ply::load(path),
+            Some("stl") => stl::load(path),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "unknown mesh format, expected .ply or .stl",
+            )),
+        }
+    }
+
+    fn vertices(&self, triangle: usize) -> [usize; 3] {
+        let [a, b, c] = self.triangles[triangle];
+        [a as usize, b as usize, c as usize]
+    }
+
+    pub fn triangle_bounds(&self, triangle: usize) -> Aabb {
+        let [a, b, c] = self.vertices(triangle);
+        let p0 = self.positions[a];
+        Aabb::new(p0, p0)
+            .union(&Aabb::new(self.positions[b], self.positions[b]))
+            .union(&Aabb::new(self.positions[c], self.positions[c]))
+    }
+
+    /// Checks that every index refers to a vertex and that the attributes match the vertices.
+    pub fn validate(&self) -> io::Result<()> {
+        let count = self.positions.len();
+        let invalid = |message: &str| Err(Error::new(ErrorKind::InvalidData, message.to_string()));
+        if self
+            .triangles
+            .iter()
+            .flatten()
+            .any(|&index| index as usize >= count)
+        {
+            return invalid("triangle refers to a missing vertex");
+        }
+        if self.normals.as_ref().is_some_and(|n| n.len() != count)
+            || self.colors.as_ref().is_some_and(|c| c.len() != count)
+            || self.uvs.as_ref().is_some_and(|uv| uv.len() != count)
+        {
+            return invalid("vertex attribute count doesn't match the vertices");
+        }
+        Ok(())
+    }
+}
+
+/// A triangle mesh in the scene with a single material. Place copies of it with instances, which
+/// share the mesh and its BVH.
+#[derive(Clone)]
+pub struct Mesh {
+    pub geometry: Arc<TriangleMesh>,
+    pub material: Material,
+    bvh: Arc<Bvh>,
+    bounds: Option<Aabb>,
+}
+
+// Moller-Trumbore ray-triangle intersection, returning the ray parameter and the barycentric
+// coordinates of the second and third vertex.
+fn intersect_triangle(ray: &Ray, p0: Vec3, p1: Vec3, p2: Vec3) -> Option<(f32, f32, f32)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let pvec = ray.direction.cross(edge2);
+    let det = edge1.dot(pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin - p0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let qvec = tvec.cross(edge1);
+    let b2 = ray.direction.dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    Some((edge2.dot(qvec) * inv_det, b1, b2))
+}
+
+impl Mesh {
+    pub fn new(geometry: Arc<TriangleMesh>, material: Material) -> Mesh {
+        let triangle_bounds: Vec<Option<Aabb>> = (0..geometry.triangles.len())
+            .map(|i| Some(geometry.triangle_bounds(i)))
+            .collect();
+        let bounds = triangle_bounds
+            .iter()
+            .flatten()
+            .fold(None, |acc: Option<Aabb>, b| {
+                Some(acc.map_or(*b, |acc| acc.union(b)))
+            });
+        Mesh {
+            bvh: Arc::new(Bvh::new(&triangle_bounds)),
+            geometry,
+            material,
+            bounds,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mesh = &self.geometry;
+        let mut closest = None;
+        self.bvh.traverse(ray, t_min, t_max, |triangle, t_max| {
+            let [a, b, c] = mesh.vertices(triangle);
+            let (t, b1, b2) =
+                intersect_triangle(ray, mesh.positions[a], mesh.positions[b], mesh.positions[c])?;
+            if t <= t_min || t >= t_max {
+                return None;
+            }
+            closest = Some((triangle, t, b1, b2));
+            Some(t)
+        });
+        let (triangle, t, b1, b2) = closest?;
+
+        let [a, b, c] = mesh.vertices(triangle);
+        let b0 = 1.0 - b1 - b2;
+        let interpolate = |values: &[Vec3]| b0 * values[a] + b1 * values[b] + b2 * values[c];
+        let (p0, p1, p2) = (mesh.positions[a], mesh.positions[b], mesh.positions[c]);
+        let geometric = (p1 - p0).cross(p2 - p0).make_unit_vector();
+        // interpolated normals give smooth shading, but stay on the side the triangle faces
+        let n = match &mesh.normals {
+            Some(normals) => {
+                let n = interpolate(normals).make_unit_vector();
+                if n.dot(geometric) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => geometric,
+        };
+        let (u, v) = match &mesh.uvs {
+            Some(uvs) => (
+                b0 * uvs[a].0 + b1 * uvs[b].0 + b2 * uvs[c].0,
+                b0 * uvs[a].1 + b1 * uvs[b].1 + b2 * uvs[c].1,
+            ),
+            None => (b1, b2),
+        };
+        let color = mesh.colors.as_deref().map_or(Vec3::ones(), interpolate);
+        Some(HitRecord {
+            t,
+            p: ray.point_at_parameter(t),
+            n,
+            u,
+            v,
+            color,
+            material: &self.material,
+        })
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mesh, TriangleMesh};
+    use crate::{material::Material, ray::Ray, vec3::Vec3};
+    use std::sync::Arc;
+
+    #[test]
+    fn interpolates_vertex_attributes() {
+        let mesh = TriangleMesh {
+            positions: vec![
+                Vec3::zeros(),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ],
+            colors: Some(vec![
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ]),
+            triangles: vec![[0, 1, 2]],
+            ..TriangleMesh::default()
+        };
+        let mesh = Mesh::new(Arc::new(mesh), Material::lambertian(Vec3::ones()));
+        let ray = Ray::new(Vec3::new(0.25, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let hit = mesh.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-6);
+        assert_eq!(hit.n, Vec3::new(0.0, 0.0, 1.0));
+        assert!((hit.color - Vec3::new(0.25, 0.25, 0.5)).length() < 1e-6);
+        let miss = Ray::new(Vec3::new(0.75, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(mesh.hit(&miss, 0.001, f32::MAX).is_none());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::{
+    camera::{shutter_time, view_basis, Camera, CameraModel},
+    hitable::World,
+    ray::Ray,
+    renderer::{render, Framebuffer, RenderSettings},
+    sampling::Pcg32,
+    vec3::Vec3,
+};
+use std::f32::consts::PI;
+
+/// A camera seeing all directions around `position`, laid out by longitude across and latitude
+/// up the image. The centre of the image looks towards `lookat`, and the top row is straight
+/// up along `vup`. Looking down +z with +y up gives the layout of `EnvironmentMap`, so the
+/// render can be used as one. Images are usually twice as wide as high.
+#[derive(Copy, Clone)]
+pub struct EquirectangularCamera {
+    position: Vec3,
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    eye_offset: f32,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl EquirectangularCamera {
+    pub fn new(position: Vec3, lookat: Vec3, vup: Vec3) -> EquirectangularCamera {
+        let (right, up, back) = view_basis(position, lookat, vup);
+        EquirectangularCamera {
+            position,
+            right,
+            up,
+            forward: -back,
+            eye_offset: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// See `Camera::with_shutter`.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> EquirectangularCamera {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+
+    /// One eye of an omni-directional stereo pair. Rays start on a circle of radius `offset`
+    /// around the position, tangent to it, so that the eye is `offset` to the right of the
+    /// centre in every viewing direction (negative for the left eye). The circle shrinks
+    /// towards the poles, where the eyes cannot be told apart.
+    pub fn with_eye_offset(mut self, offset: f32) -> EquirectangularCamera {
+        self.eye_offset = offset;
+        self
+    }
+}
+
+impl CameraModel for EquirectangularCamera {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        let phi = 2.0 * PI * (u - 0.5);
+        let latitude = PI * (v - 0.5);
+        let horizontal = phi.sin() * self.right + phi.cos() * self.forward;
+        let direction = latitude.cos() * horizontal + latitude.sin() * self.up;
+        let sideways = phi.cos() * self.right - phi.sin() * self.forward;
+        let origin = self.position + (self.eye_offset * latitude.cos()) * sideways;
+        let time = shutter_time(rng, self.shutter_open, self.shutter_close);
+        Some(Ray::new(origin, direction, time))
+    }
+}
+
+/// The six faces of a cube map.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// The axis the face looks along.
+    pub fn direction(self) -> Vec3 {
+        match self {
+            CubeFace::PositiveX => Vec3::new(1.0, 0.0, 0.0),
+            CubeFace::NegativeX => Vec3::new(-1.0, 0.0, 0.0),
+            CubeFace::PositiveY => Vec3::new(0.0, 1.0, 0.0),
+            CubeFace::NegativeY => Vec3::new(0.0, -1.0, 0.0),
+            CubeFace::PositiveZ => Vec3::new(0.0, 0.0, 1.0),
+            CubeFace::NegativeZ => Vec3::new(0.0, 0.0, -1.0),
+        }
+    }
+
+    /// The side faces are upright. The top face has -z up and the bottom face +z, so that both
+    /// join the +z face along an edge.
+    pub fn up(self) -> Vec3 {
+        match self {
+            CubeFace::PositiveY => Vec3::new(0.0, 0.0, -1.0),
+            CubeFace::NegativeY => Vec3::new(0.0, 0.0, 1.0),
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    /// The square 90 degree view from `position` through this face, as seen from inside the
+    /// cube. Render it at a square resolution.
+    pub fn camera(self, position: Vec3) -> Camera {
+        Camera::new(
+            position,
+            position + self.direction(),
+            self.up(),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        )
+    }
+}
+
+/// Renders the six faces of a cube map around `position`, in the order of `CubeFace::ALL`.
+/// `settings` should be square.
+pub fn render_cube_map(
+    settings: &RenderSettings,
+    position: Vec3,
+    world: &World,
+) -> Vec<(CubeFace, Framebuffer)> {
+    CubeFace::ALL
+        .iter()
+        .map(|&face| (face, render(settings, &face.camera(position), world)))
+        .collect()
+}
+
+/// How a fisheye lens maps the angle from the optical axis to the distance from the centre of
+/// the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// the distance grows in proportion to the angle
+    Equidistant,
+    /// equal solid angles cover equal image areas
+    Equisolid,
+}
+
+/// A circular fisheye looking from `lookfrom` towards `lookat`. The image circle spans the
+/// shorter side of the image and covers `fov` degrees, up to 360. Outside of it the image is
+/// black.
+#[derive(Copy, Clone)]
+pub struct FisheyeCamera {
+    position: Vec3,
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+    // half the field of view, in radians
+    half_fov: f32,
+    mapping: FisheyeMapping,
+    aspect: f32,
+    shutter_open: f32,
+    shutter_close: f32,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        fov: f32,
+        mapping: FisheyeMapping,
+        aspect: f32,
+    ) -> FisheyeCamera {
+        let (right, up, back) = view_basis(lookfrom, lookat, vup);
+        FisheyeCamera {
+            position: lookfrom,
+            right,
+            up,
+            forward: -back,
+            half_fov: 0.5 * fov.clamp(0.0, 360.0).to_radians(),
+            mapping,
+            aspect,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// See `Camera::with_shutter`.
+    pub fn with_shutter(mut self, open: f32, close: f32) -> FisheyeCamera {
+        self.shutter_open = open;
+        self.shutter_close = close.max(open);
+        self
+    }
+}
+
+impl CameraModel for FisheyeCamera {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        // image coordinates with the image circle of radius one in the centre
+        let (x, y) = if self.aspect >= 1.0 {
+            ((2.0 * u - 1.0) * self.aspect, 2.0 * v - 1.0)
+        } else {
+            (2.0 * u - 1.0, (2.0 * v - 1.0) / self.aspect)
+        };
+        let r = x.hypot(y);
+        if r > 1.0 {
+            return None;
+        }
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.half_fov,
+            FisheyeMapping::Equisolid => 2.0 * (r * (0.5 * self.half_fov).sin()).asin(),
+        };
+        let radial = if r > 0.0 {
+            (1.0 / r) * (x * self.right + y * self.up)
+        } else {
+            Vec3::zeros()
+        };
+        let direction = theta.cos() * self.forward + theta.sin() * radial;
+        let time = shutter_time(rng, self.shutter_open, self.shutter_close);
+        Some(Ray::new(self.position, direction, time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CubeFace, EquirectangularCamera, FisheyeCamera, FisheyeMapping};
+    use crate::{camera::CameraModel, sampling::Pcg32, vec3::Vec3};
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a.make_unit_vector() - b).length() < 1e-5
+    }
+
+    #[test]
+    fn equirectangular_directions() {
+        let camera = EquirectangularCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let mut rng = Pcg32::new(0, 0);
+        let mut direction = |u, v| camera.make_ray(&mut rng, u, v).unwrap().direction;
+        assert!(close(direction(0.5, 0.5), Vec3::new(0.0, 0.0, 1.0)));
+        assert!(close(direction(0.0, 0.5), Vec3::new(0.0, 0.0, -1.0)));
+        // turning right from +z
+        assert!(close(direction(0.75, 0.5), Vec3::new(-1.0, 0.0, 0.0)));
+        assert!(close(direction(0.3, 1.0), Vec3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn cube_faces_tile_the_sphere() {
+        let mut rng = Pcg32::new(0, 0);
+        for face in CubeFace::ALL {
+            let camera = face.camera(Vec3::zeros());
+            let centre = camera.make_ray(&mut rng, 0.5, 0.5).unwrap();
+            assert!(close(centre.direction, face.direction()));
+            // the corners are at 45 degrees from the axis along both edges
+            let corner = camera.make_ray(&mut rng, 1.0, 1.0).unwrap().direction;
+            let axis = face.direction();
+            assert!((corner.dot(axis) - corner.length() / 3.0f32.sqrt()).abs() < 1e-5);
+        }
+        // the top face continues the +z face across its top edge
+        let front = CubeFace::PositiveZ.camera(Vec3::zeros());
+        let top = CubeFace::PositiveY.camera(Vec3::zeros());
+        let front_edge = front.make_ray(&mut rng, 0.5, 1.0).unwrap().direction;
+        let top_edge = top.make_ray(&mut rng, 0.5, 0.0).unwrap().direction;
+        assert!(close(front_edge, top_edge.make_unit_vector()));
+    }
+
+    #[test]
+    fn fisheye_mappings() {
+        let camera = |mapping| {
+            FisheyeCamera::new(
+                Vec3::zeros(),
+                Vec3::new(0.0, 0.0, -1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                180.0,
+                mapping,
+                2.0,
+            )
+        };
+        let mut rng = Pcg32::new(0, 0);
+        for mapping in [FisheyeMapping::Equidistant, FisheyeMapping::Equisolid] {
+            let camera = camera(mapping);
+            let centre = camera.make_ray(&mut rng, 0.5, 0.5).unwrap();
+            assert!(close(centre.direction, Vec3::new(0.0, 0.0, -1.0)));
+            // the edge of the image circle is at 90 degrees
+            let edge = camera.make_ray(&mut rng, 0.5, 1.0).unwrap();
+            assert!(close(edge.direction, Vec3::new(0.0, 1.0, 0.0)));
+            // the image circle spans the height, so the sides of the wide image are empty
+            assert!(camera.make_ray(&mut rng, 0.05, 0.5).is_none());
+        }
+        // halfway out, the equidistant lens sees 45 degrees and the equisolid lens more
+        let y = |mapping, rng: &mut _| {
+            let camera = camera(mapping);
+            let d = camera
+                .make_ray(rng, 0.5, 0.75)
+                .unwrap()
+                .direction
+                .make_unit_vector();
+            d.y.asin().to_degrees()
+        };
+        assert!((y(FisheyeMapping::Equidistant, &mut rng) - 45.0).abs() < 1e-3);
+        let equisolid = y(FisheyeMapping::Equisolid, &mut rng);
+        assert!(
+            (equisolid - 2.0 * (0.5f32 * 45f32.to_radians().sin()).asin().to_degrees()).abs()
+                < 1e-3
+        );
+    }
+}
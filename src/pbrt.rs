@@ -0,0 +1,832 @@
+use crate::{
+    camera::Camera,
+    color::blackbody,
+    environment::{Background, EnvironmentMap},
+    hitable::{Hitable, Sphere, World},
+    light::Light,
+    material::Material,
+    mesh::{Mesh, TriangleMesh},
+    ply,
+    transform::{Mat4, Transform},
+    vec3::Vec3,
+};
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+// Parser for the subset of the pbrt-v3 scene format which maps onto this renderer: perspective
+// cameras, the film resolution and sample count, transforms, attribute blocks, spheres and
+// triangle meshes, matte, metal and glass materials, point, spot, distant and constant
+// infinite lights, diffuse area lights and includes. Anything else is skipped with a warning.
+//
+// pbrt's world space is left-handed, so the scene is mirrored along x to give the same image
+// with our right-handed camera.
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// A scene ready to render, with the settings of the file.
+pub struct PbrtScene {
+    pub camera: Camera,
+    pub world: World,
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+    pub filename: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+/// Included and referenced files are found relative to the directory of the scene file.
+pub fn load(path: &Path) -> io::Result<PbrtScene> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut parser = Parser::new(directory);
+    parser.includes.push(fs::canonicalize(path)?);
+    parser.run(&fs::read_to_string(path)?, &path.display().to_string())?;
+    parser.finish()
+}
+
+/// Parses a scene given as text, with included and referenced files relative to `directory`.
+pub fn parse(text: &str, directory: &Path) -> io::Result<PbrtScene> {
+    let mut parser = Parser::new(directory);
+    parser.run(text, "<scene>")?;
+    parser.finish()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Directive(String),
+    Str(String),
+    Number(f32),
+    Open,
+    Close,
+}
+
+// Splits the text into tokens, each with the line it's on.
+fn tokenize(text: &str) -> io::Result<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let mut chars = content.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+            chars.next();
+            let token = match c {
+                '[' => Token::Open,
+                ']' => Token::Close,
+                '"' => {
+                    let mut end = None;
+                    for (i, c) in chars.by_ref() {
+                        if c == '"' {
+                            end = Some(i);
+                            break;
+                        }
+                    }
+                    let end = end.ok_or_else(|| {
+                        invalid_data(&format!("unterminated string on line {}", line + 1))
+                    })?;
+                    Token::Str(content[start + 1..end].to_string())
+                }
+                _ => {
+                    let mut end = content.len();
+                    while let Some(&(i, c)) = chars.peek() {
+                        if c.is_whitespace() || c == '[' || c == ']' || c == '"' || c == '#' {
+                            end = i;
+                            break;
+                        }
+                        chars.next();
+                    }
+                    let word = &content[start..end];
+                    if c.is_ascii_alphabetic() {
+                        Token::Directive(word.to_string())
+                    } else {
+                        Token::Number(word.parse().map_err(|_| {
+                            invalid_data(&format!("bad number '{word}' on line {}", line + 1))
+                        })?)
+                    }
+                }
+            };
+            tokens.push((line + 1, token));
+        }
+    }
+    Ok(tokens)
+}
+
+// A typed parameter such as `"rgb Kd" [0.5 0.5 0.5]`.
+struct Param {
+    kind: String,
+    name: String,
+    numbers: Vec<f32>,
+    strings: Vec<String>,
+}
+
+struct Params(Vec<Param>);
+
+impl Params {
+    // Reads the parameters following the positional arguments of a directive.
+    fn parse(args: &[Token]) -> io::Result<Params> {
+        let mut params = Vec::new();
+        let mut i = 0;
+        while i < args.len() {
+            let Token::Str(declaration) = &args[i] else {
+                return Err(invalid_data("expected a parameter declaration"));
+            };
+            let mut words = declaration.split_whitespace();
+            let (Some(kind), Some(name), None) = (words.next(), words.next(), words.next()) else {
+                return Err(invalid_data(&format!(
+                    "bad parameter declaration '{declaration}'"
+                )));
+            };
+            let mut param = Param {
+                kind: kind.to_string(),
+                name: name.to_string(),
+                numbers: Vec::new(),
+                strings: Vec::new(),
+            };
+            i += 1;
+            let bracketed = args.get(i) == Some(&Token::Open);
+            if bracketed {
+                i += 1;
+            }
+            while let Some(token) = args.get(i) {
+                match token {
+                    Token::Number(x) => param.numbers.push(*x),
+                    Token::Str(s) => param.strings.push(s.clone()),
+                    Token::Close if bracketed => {
+                        i += 1;
+                        break;
+                    }
+                    _ => return Err(invalid_data(&format!("bad value for '{name}'"))),
+                }
+                i += 1;
+                if !bracketed {
+                    break;
+                }
+            }
+            params.push(param);
+        }
+        Ok(Params(params))
+    }
+
+    fn get(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    fn float(&self, name: &str, default: f32) -> f32 {
+        self.get(name)
+            .and_then(|p| p.numbers.first().copied())
+            .unwrap_or(default)
+    }
+
+    fn floats(&self, name: &str) -> Option<&[f32]> {
+        self.get(name).map(|p| p.numbers.as_slice())
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.get(name)
+            .and_then(|p| p.strings.first())
+            .map(String::as_str)
+    }
+
+    fn point(&self, name: &str, default: Vec3) -> Vec3 {
+        match self.floats(name) {
+            Some([x, y, z]) => Vec3::new(*x, *y, *z),
+            _ => default,
+        }
+    }
+
+    // An RGB or black body colour. Sampled spectra and textures are not supported and give the
+    // default with a warning.
+    fn color(&self, name: &str, default: Vec3, warnings: &mut Vec<String>) -> Vec3 {
+        let Some(param) = self.get(name) else {
+            return default;
+        };
+        match (param.kind.as_str(), param.numbers.as_slice()) {
+            ("rgb" | "color", [r, g, b]) => Vec3::new(*r, *g, *b),
+            ("blackbody", [temperature, scale]) => *scale * blackbody(*temperature),
+            ("blackbody", [temperature]) => blackbody(*temperature),
+            _ => {
+                warnings.push(format!(
+                    "unsupported {} value for '{name}', using the default",
+                    param.kind
+                ));
+                default
+            }
+        }
+    }
+}
+
+// The numbers of a transform directive, with or without brackets.
+fn numbers(args: &[Token], count: usize, directive: &str) -> io::Result<Vec<f32>> {
+    let numbers: Vec<f32> = args
+        .iter()
+        .filter_map(|t| match t {
+            Token::Number(x) => Some(*x),
+            _ => None,
+        })
+        .collect();
+    if numbers.len() == count {
+        Ok(numbers)
+    } else {
+        Err(invalid_data(&format!(
+            "{directive} expects {count} numbers"
+        )))
+    }
+}
+
+// The type name leading the arguments of Camera, Shape, Material etc., and the parameters after it.
+fn typed(args: &[Token], directive: &str) -> io::Result<(String, Params)> {
+    match args.split_first() {
+        Some((Token::Str(kind), rest)) => Ok((kind.clone(), Params::parse(rest)?)),
+        _ => Err(invalid_data(&format!("{directive} expects a type name"))),
+    }
+}
+
+// The camera to world matrix of pbrt's LookAt, whose inverse is the transform from world to
+// camera space.
+fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+    let dir = (target - eye).make_unit_vector();
+    let right = up.make_unit_vector().cross(dir).make_unit_vector();
+    let up = dir.cross(right);
+    Mat4::new([
+        [right.x, up.x, dir.x, eye.x],
+        [right.y, up.y, dir.y, eye.y],
+        [right.z, up.z, dir.z, eye.z],
+        [0.0, 0.0, 0.0, 1.0],
+    ])
+}
+
+// The transform given by a directive, which must have a finite inverse. A zero scale or a LookAt
+// at its own eye has none.
+fn checked_transform(matrix: Mat4, directive: &str) -> io::Result<Transform> {
+    let finite = |m: &Mat4| m.m.iter().flatten().all(|x| x.is_finite());
+    match matrix.inverse() {
+        Some(inverse) if finite(&matrix) && finite(&inverse) => {
+            Ok(Transform::from_matrices(matrix, inverse))
+        }
+        _ => Err(invalid_data(&format!("{directive} transform is singular"))),
+    }
+}
+
+// The normal incidence reflectance of a conductor with the given index of refraction and
+// absorption coefficient.
+fn conductor_reflectance(eta: Vec3, k: Vec3) -> Vec3 {
+    let r = |n: f32, k: f32| ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+    Vec3::new(r(eta.x, k.x), r(eta.y, k.y), r(eta.z, k.z))
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    transform: Transform,
+    material: Material,
+    // the radiance of shapes inside an AreaLightSource block
+    area_light: Option<Vec3>,
+}
+
+struct Parser {
+    directory: PathBuf,
+    // the files being read, to catch files which include themselves
+    includes: Vec<PathBuf>,
+    state: GraphicsState,
+    attributes: Vec<GraphicsState>,
+    transforms: Vec<Transform>,
+    // the world to camera transform and parameters of the camera
+    camera: Option<(Transform, Params)>,
+    width: usize,
+    height: usize,
+    samples: u32,
+    filename: Option<String>,
+    hitables: Vec<Hitable>,
+    lights: Vec<Light>,
+    background: Option<Background>,
+    warnings: Vec<String>,
+}
+
+impl Parser {
+    fn new(directory: &Path) -> Parser {
+        Parser {
+            directory: directory.to_path_buf(),
+            includes: Vec::new(),
+            state: GraphicsState {
+                transform: Transform::identity(),
+                material: Material::lambertian(Vec3::new(0.5, 0.5, 0.5)),
+                area_light: None,
+            },
+            attributes: Vec::new(),
+            transforms: Vec::new(),
+            camera: None,
+            width: 640,
+            height: 480,
+            samples: 16,
+            filename: None,
+            hitables: Vec::new(),
+            lights: Vec::new(),
+            background: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn run(&mut self, text: &str, file: &str) -> io::Result<()> {
+        let tokens = tokenize(text)?;
+        let mut i = 0;
+        while i < tokens.len() {
+            let (line, Token::Directive(name)) = &tokens[i] else {
+                return Err(invalid_data(&format!(
+                    "{file}:{}: expected a directive",
+                    tokens[i].0
+                )));
+            };
+            let end = tokens[i + 1..]
+                .iter()
+                .position(|(_, t)| matches!(t, Token::Directive(_)))
+                .map_or(tokens.len(), |n| i + 1 + n);
+            let args: Vec<Token> = tokens[i + 1..end].iter().map(|(_, t)| t.clone()).collect();
+            let warnings = self.warnings.len();
+            self.directive(name, &args)
+                .map_err(|e| Error::new(e.kind(), format!("{file}:{line}: {e}")))?;
+            // say where the warnings of the directive come from
+            for warning in &mut self.warnings[warnings..] {
+                *warning = format!("{file}:{line}: {warning}");
+            }
+            i = end;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, args: &[Token]) -> io::Result<()> {
+        if self.transform_directive(name, args)? {
+            return Ok(());
+        }
+        match name {
+            "Camera" => {
+                let (kind, params) = typed(args, name)?;
+                if kind != "perspective" {
+                    self.warnings
+                        .push(format!("unsupported camera '{kind}', using perspective"));
+                }
+                self.camera = Some((self.state.transform, params));
+            }
+            "Film" => {
+                let (_, params) = typed(args, name)?;
+                self.width = params.float("xresolution", 640.0) as usize;
+                self.height = params.float("yresolution", 480.0) as usize;
+                self.filename = params.string("filename").map(str::to_string);
+            }
+            "Sampler" => {
+                let (_, params) = typed(args, name)?;
+                self.samples = params.float("pixelsamples", 16.0) as u32;
+            }
+            "WorldBegin" => self.state.transform = Transform::identity(),
+            "WorldEnd" => {}
+            "AttributeBegin" => self.attributes.push(self.state.clone()),
+            "AttributeEnd" => {
+                self.state = self
+                    .attributes
+                    .pop()
+                    .ok_or_else(|| invalid_data("AttributeEnd without AttributeBegin"))?;
+            }
+            "Shape" => {
+                let (kind, params) = typed(args, name)?;
+                self.shape(&kind, &params)?;
+            }
+            "Material" => {
+                let (kind, params) = typed(args, name)?;
+                self.state.material = self.material(&kind, &params);
+            }
+            "LightSource" => {
+                let (kind, params) = typed(args, name)?;
+                self.light(&kind, &params);
+            }
+            "AreaLightSource" => {
+                let (kind, params) = typed(args, name)?;
+                if kind != "diffuse" {
+                    self.warnings
+                        .push(format!("unsupported area light '{kind}', using diffuse"));
+                }
+                let scale = params.color("scale", Vec3::ones(), &mut self.warnings);
+                let radiance = params.color("L", Vec3::ones(), &mut self.warnings);
+                self.state.area_light = Some(scale * radiance);
+            }
+            "Include" => match args {
+                [Token::Str(file)] => {
+                    let path = self.directory.join(file);
+                    let canonical = fs::canonicalize(&path)?;
+                    if self.includes.contains(&canonical) {
+                        return Err(invalid_data(&format!("{file} includes itself")));
+                    }
+                    let text = fs::read_to_string(&path)?;
+                    self.includes.push(canonical);
+                    self.run(&text, &path.display().to_string())?;
+                    self.includes.pop();
+                }
+                _ => return Err(invalid_data("Include expects a file name")),
+            },
+            _ => self
+                .warnings
+                .push(format!("unsupported directive '{name}' ignored")),
+        }
+        Ok(())
+    }
+
+    // Applies the directives changing the current transform, returning whether `name` was one.
+    fn transform_directive(&mut self, name: &str, args: &[Token]) -> io::Result<bool> {
+        let v = |n: &[f32]| Vec3::new(n[0], n[1], n[2]);
+        let transform = match name {
+            "Identity" => {
+                self.state.transform = Transform::identity();
+                return Ok(true);
+            }
+            "Translate" => {
+                let matrix = Transform::translate(v(&numbers(args, 3, name)?)).matrix;
+                checked_transform(matrix, name)?
+            }
+            "Scale" => {
+                let n = numbers(args, 3, name)?;
+                let mut matrix = Mat4::identity();
+                for (i, x) in n.iter().enumerate() {
+                    matrix.m[i][i] = *x;
+                }
+                checked_transform(matrix, name)?
+            }
+            "Rotate" => {
+                let n = numbers(args, 4, name)?;
+                checked_transform(Transform::rotate(v(&n[1..]), n[0]).matrix, name)?
+            }
+            "LookAt" => {
+                let n = numbers(args, 9, name)?;
+                checked_transform(look_at(v(&n), v(&n[3..]), v(&n[6..])), name)?.inverted()
+            }
+            "Transform" | "ConcatTransform" => {
+                // pbrt matrices are given by columns
+                let n = numbers(args, 16, name)?;
+                let mut m = [[0.0; 4]; 4];
+                for (i, x) in n.iter().enumerate() {
+                    m[i % 4][i / 4] = *x;
+                }
+                let transform = checked_transform(Mat4::new(m), name)?;
+                if name == "Transform" {
+                    self.state.transform = transform;
+                    return Ok(true);
+                }
+                transform
+            }
+            "TransformBegin" => {
+                self.transforms.push(self.state.transform);
+                return Ok(true);
+            }
+            "TransformEnd" => {
+                self.state.transform = self
+                    .transforms
+                    .pop()
+                    .ok_or_else(|| invalid_data("TransformEnd without TransformBegin"))?;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+        self.state.transform = self.state.transform * transform;
+        Ok(true)
+    }
+
+    // The object to world transform of shapes and lights, mirrored into our right-handed space.
+    fn object_to_world(&self) -> Transform {
+        Transform::scale(Vec3::new(-1.0, 1.0, 1.0)) * self.state.transform
+    }
+
+    fn material(&mut self, kind: &str, params: &Params) -> Material {
+        let warnings = &mut self.warnings;
+        match kind {
+            "matte" => Material::lambertian(params.color("Kd", Vec3::new(0.5, 0.5, 0.5), warnings)),
+            "metal" => {
+                // pbrt's default is copper
+                let eta = params.color("eta", Vec3::new(0.2004, 0.9240, 1.1022), warnings);
+                let k = params.color("k", Vec3::new(3.9129, 2.4528, 2.1422), warnings);
+                let roughness = params.float("roughness", 0.01);
+                Material::metal(conductor_reflectance(eta, k), roughness)
+            }
+            "glass" => {
+                let eta = params.get("eta").map_or(params.float("index", 1.5), |p| {
+                    p.numbers.first().copied().unwrap_or(1.5)
+                });
+                Material::dielectric(eta)
+            }
+            _ => {
+                warnings.push(format!("unsupported material '{kind}', using matte"));
+                Material::lambertian(params.color("Kd", Vec3::new(0.5, 0.5, 0.5), warnings))
+            }
+        }
+    }
+
+    fn shape(&mut self, kind: &str, params: &Params) -> io::Result<()> {
+        let material = match self.state.area_light {
+            Some(radiance) => Material::emissive(radiance),
+            None => self.state.material.clone(),
+        };
+        let transform = self.object_to_world();
+        let geometry = match kind {
+            "sphere" => {
+                if ["zmin", "zmax", "phimax"]
+                    .iter()
+                    .any(|p| params.get(p).is_some())
+                {
+                    self.warnings
+                        .push("partial spheres are not supported, using a full sphere".into());
+                }
+                // spheres are placed in world space, so that emissive ones become lights
+                let radius = params.float("radius", 1.0);
+                let scale = transform.vector(Vec3::new(1.0, 0.0, 0.0)).length();
+                let center = transform.point(Vec3::zeros());
+                self.hitables.push(Hitable::Sphere(Sphere::new(
+                    center,
+                    scale * radius,
+                    material,
+                )));
+                return Ok(());
+            }
+            "trianglemesh" => triangle_mesh(params)?,
+            "plymesh" => {
+                let file = params
+                    .string("filename")
+                    .ok_or_else(|| invalid_data("plymesh without a filename"))?;
+                ply::load(&self.directory.join(file))?
+            }
+            _ => {
+                self.warnings
+                    .push(format!("unsupported shape '{kind}' ignored"));
+                return Ok(());
+            }
+        };
+        let mesh = Hitable::Mesh(Mesh::new(Arc::new(geometry), material));
+        self.hitables
+            .push(Hitable::instance(Arc::new(mesh), transform));
+        Ok(())
+    }
+
+    fn light(&mut self, kind: &str, params: &Params) {
+        let transform = self.object_to_world();
+        let warnings = &mut self.warnings;
+        let scale = params.color("scale", Vec3::ones(), warnings);
+        let from = params.point("from", Vec3::zeros());
+        let to = params.point("to", Vec3::new(0.0, 0.0, 1.0));
+        match kind {
+            "point" => {
+                let intensity = scale * params.color("I", Vec3::ones(), warnings);
+                self.lights
+                    .push(Light::point(transform.point(from), intensity, 1.0));
+            }
+            "spot" => {
+                let intensity = scale * params.color("I", Vec3::ones(), warnings);
+                let cone = params.float("coneangle", 30.0);
+                let delta = params.float("conedelta", 5.0);
+                self.lights.push(Light::spot(
+                    transform.point(from),
+                    transform.vector(to - from),
+                    intensity,
+                    1.0,
+                    cone - delta,
+                    cone,
+                ));
+            }
+            "distant" => {
+                let radiance = scale * params.color("L", Vec3::ones(), warnings);
+                self.lights.push(Light::directional(
+                    transform.vector(to - from),
+                    radiance,
+                    1.0,
+                ));
+            }
+            "infinite" => {
+                if params.get("mapname").is_some() {
+                    warnings.push("infinite light maps are not supported, using L".into());
+                }
+                let radiance = scale * params.color("L", Vec3::ones(), warnings);
+                self.background = Some(Background::EnvironmentMap(EnvironmentMap::from_fn(
+                    4,
+                    2,
+                    |_| radiance,
+                )));
+            }
+            _ => warnings.push(format!("unsupported light '{kind}' ignored")),
+        }
+    }
+
+    fn finish(mut self) -> io::Result<PbrtScene> {
+        let (world_to_camera, params) = self
+            .camera
+            .take()
+            .ok_or_else(|| invalid_data("scene without a Camera"))?;
+        let aspect = params.float(
+            "frameaspectratio",
+            self.width as f32 / self.height.max(1) as f32,
+        );
+        // the field of view spans the shorter image axis
+        let fov = params.float("fov", 90.0);
+        let vfov = if aspect >= 1.0 {
+            fov
+        } else {
+            2.0 * ((0.5 * fov.to_radians()).tan() / aspect)
+                .atan()
+                .to_degrees()
+        };
+        let camera_to_world =
+            Transform::scale(Vec3::new(-1.0, 1.0, 1.0)) * world_to_camera.inverted();
+        let lens_radius = params.float("lensradius", 0.0);
+        // the focus distance only matters with a lens, and scales the camera rays
+        let focus_distance = if lens_radius > 0.0 {
+            params.float("focaldistance", 1e6)
+        } else {
+            1.0
+        };
+        let camera = Camera::new(
+            camera_to_world.point(Vec3::zeros()),
+            camera_to_world.point(Vec3::new(0.0, 0.0, 1.0)),
+            camera_to_world.vector(Vec3::new(0.0, 1.0, 0.0)),
+            vfov,
+            aspect,
+            2.0 * lens_radius,
+            focus_distance,
+        );
+        let mut world = World::new(mem::take(&mut self.hitables));
+        for light in self.lights {
+            world.add_light(light);
+        }
+        if let Some(background) = self.background {
+            world.background = background;
+        }
+        Ok(PbrtScene {
+            camera,
+            world,
+            width: self.width,
+            height: self.height,
+            samples: self.samples,
+            filename: self.filename,
+            warnings: self.warnings,
+        })
+    }
+}
+
+fn triangle_mesh(params: &Params) -> io::Result<TriangleMesh> {
+    let vectors = |name: &str| -> Option<Vec<Vec3>> {
+        params.floats(name).map(|n| {
+            n.chunks_exact(3)
+                .map(|c| Vec3::new(c[0], c[1], c[2]))
+                .collect()
+        })
+    };
+    let positions = vectors("P").ok_or_else(|| invalid_data("trianglemesh without P"))?;
+    let indices: Vec<u32> = match params.floats("indices") {
+        Some(indices) => indices
+            .iter()
+            .map(|&i| {
+                if i < 0.0 || i.fract() != 0.0 || f64::from(i) > f64::from(u32::MAX) {
+                    Err(invalid_data(&format!("bad vertex index {i}")))
+                } else {
+                    Ok(i as u32)
+                }
+            })
+            .collect::<io::Result<_>>()?,
+        None if positions.len() == 3 => vec![0, 1, 2],
+        None => return Err(invalid_data("trianglemesh without indices")),
+    };
+    let uvs = params
+        .floats("uv")
+        .or_else(|| params.floats("st"))
+        .map(|n| n.chunks_exact(2).map(|c| (c[0], c[1])).collect());
+    let mesh = TriangleMesh {
+        normals: vectors("N"),
+        uvs,
+        triangles: indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect(),
+        positions,
+        ..TriangleMesh::default()
+    };
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load, parse};
+    use crate::camera::CameraModel;
+    use crate::sampling::Pcg32;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn subset_with_warnings() {
+        let text = r#"
+# camera at z = -5 looking down +z, so that +x is to the right of the image
+LookAt 0 0 -5  0 0 0  0 1 0
+Camera "perspective" "float fov" [30]
+Film "image" "integer xresolution" [400] "integer yresolution" 200 "string filename" "out.exr"
+Sampler "halton" "integer pixelsamples" 64
+Integrator "path"
+WorldBegin
+LightSource "point" "rgb I" [10 10 10] "point from" [0 4 0]
+AttributeBegin
+  Material "matte" "rgb Kd" [0.8 0.2 0.2]
+  Translate 2 0 0
+  Shape "sphere" "float radius" 0.5
+AttributeEnd
+AttributeBegin
+  Material "plastic" "rgb Kd" [0.2 0.8 0.2]
+  Texture "checks" "spectrum" "checkerboard"
+  Shape "trianglemesh" "integer indices" [0 1 2] "point P" [-1 -1 3  1 -1 3  0 1 3]
+AttributeEnd
+WorldEnd
+"#;
+        let scene = parse(text, Path::new(".")).unwrap();
+        assert_eq!((scene.width, scene.height, scene.samples), (400, 200, 64));
+        assert_eq!(scene.filename.as_deref(), Some("out.exr"));
+        assert_eq!(scene.world.lights().len(), 1);
+        assert_eq!(scene.warnings.len(), 3);
+        assert!(scene.warnings[0].starts_with("<scene>:7: unsupported directive 'Integrator'"));
+        assert!(scene.warnings[1].contains("plastic"));
+        assert!(scene.warnings[2].contains("Texture"));
+
+        let mut rng = Pcg32::new(0, 0);
+        let right = scene.camera.make_ray(&mut rng, 0.95, 0.5).unwrap();
+        // mirrored into our right-handed space
+        let hit = scene.world.hit(&right, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.p.x + 2.0).abs() < 0.5);
+        let left = scene.camera.make_ray(&mut rng, 0.05, 0.5).unwrap();
+        assert!(scene.world.hit(&left, 0.001, f32::MAX, &mut rng).is_none());
+        // the triangle behind the sphere is in the centre of the image
+        let centre = scene.camera.make_ray(&mut rng, 0.5, 0.5).unwrap();
+        let hit = scene.world.hit(&centre, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((hit.t - 8.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn rejects_singular_transforms() {
+        for directive in [
+            "Scale 0 0 0",
+            "Scale 1 0 1",
+            "Rotate 90 0 0 0",
+            "LookAt 1 2 3  1 2 3  0 1 0",
+            "LookAt 0 0 0  0 1 0  0 1 0",
+            "ConcatTransform [1 0 0 0  0 1 0 0  0 0 0 0  0 0 0 1]",
+        ] {
+            let error = parse(directive, Path::new(".")).err().unwrap();
+            assert!(error.to_string().contains("singular"), "{}", directive);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_vertex_indices() {
+        let mesh = |indices: &str| {
+            format!(
+                "Camera \"perspective\"\nShape \"trianglemesh\" \"integer indices\" [{indices}] \
+                 \"point P\" [0 0 0  1 0 0  0 1 0]"
+            )
+        };
+        assert!(parse(&mesh("0 1 2"), Path::new(".")).is_ok());
+        for indices in ["0 -1 2", "0 0.5 2"] {
+            let error = parse(&mesh(indices), Path::new(".")).err().unwrap();
+            assert!(
+                error.to_string().contains("bad vertex index"),
+                "{}",
+                indices
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_recursive_includes() {
+        let directory = std::env::temp_dir().join(format!("pbrt-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("a.pbrt"), "Include \"b.pbrt\"\n").unwrap();
+        fs::write(directory.join("b.pbrt"), "Include \"a.pbrt\"\n").unwrap();
+        fs::write(
+            directory.join("c.pbrt"),
+            "Include \"d.pbrt\"\nInclude \"d.pbrt\"\nCamera \"perspective\"\n",
+        )
+        .unwrap();
+        fs::write(directory.join("d.pbrt"), "Translate 1 0 0\n").unwrap();
+        let a = load(&directory.join("a.pbrt"));
+        let b = parse("Include \"b.pbrt\"", &directory);
+        // including the same file twice is fine, as long as it doesn't include itself
+        let c = load(&directory.join("c.pbrt"));
+        fs::remove_dir_all(&directory).unwrap();
+        assert!(a
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("a.pbrt includes itself"));
+        assert!(b
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("b.pbrt includes itself"));
+        assert!(c.is_ok());
+    }
+}
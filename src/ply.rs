@@ -0,0 +1,417 @@
+use crate::{mesh::TriangleMesh, vec3::Vec3};
+use std::{
+    fs::File,
+    io::{self, Error, ErrorKind, Read},
+    path::Path,
+    str::SplitAsciiWhitespace,
+};
+
+// Reader for the PLY polygon format, in its ASCII and binary variants. Vertices may have
+// normals (nx, ny, nz), colours (red, green, blue) and texture coordinates (u, v or s, t).
+// Polygonal faces are split into triangle fans, and other elements are skipped.
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(invalid_data(&format!("unknown PLY type '{name}'"))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // The value which maps to one for colour channels.
+    fn full_scale(self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    // the type of the length, and of the items
+    List(String, Scalar, Scalar),
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+// The body of the file after the header, read one value at a time.
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary {
+        data: &'a [u8],
+        offset: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| invalid_data("unexpected end of PLY data"))?;
+                token
+                    .parse::<f64>()
+                    .map_err(|_| invalid_data(&format!("bad PLY value '{token}'")))
+            }
+            Body::Binary {
+                data,
+                offset,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let bytes = data
+                    .get(*offset..*offset + size)
+                    .ok_or_else(|| invalid_data("unexpected end of PLY data"))?;
+                *offset += size;
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buffer;
+                Ok(match scalar {
+                    Scalar::I8 => f64::from(i8::from_le_bytes([b0])),
+                    Scalar::U8 => f64::from(b0),
+                    Scalar::I16 => f64::from(i16::from_le_bytes([b0, b1])),
+                    Scalar::U16 => f64::from(u16::from_le_bytes([b0, b1])),
+                    Scalar::I32 => f64::from(i32::from_le_bytes([b0, b1, b2, b3])),
+                    Scalar::U32 => f64::from(u32::from_le_bytes([b0, b1, b2, b3])),
+                    Scalar::F32 => f64::from(f32::from_le_bytes([b0, b1, b2, b3])),
+                    Scalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+}
+
+fn parse_header(header: &str) -> io::Result<(Format, Vec<Element>)> {
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(invalid_data("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(invalid_data(&format!("unknown PLY format '{name}'"))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: (*name).to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data("bad PLY element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("PLY property outside of an element"))?
+                .properties
+                .push(Property::List(
+                    (*name).to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("PLY property outside of an element"))?
+                .properties
+                .push(Property::Scalar(
+                    (*name).to_string(),
+                    Scalar::parse(scalar)?,
+                )),
+            _ => {} // comments, obj_info and blank lines
+        }
+    }
+    let format = format.ok_or_else(|| invalid_data("PLY header has no format"))?;
+    Ok((format, elements))
+}
+
+// Finds where the header ends and the body starts.
+fn split_header(data: &[u8]) -> io::Result<(&str, &[u8])> {
+    const END: &[u8] = b"end_header";
+    let position = data
+        .windows(END.len())
+        .position(|w| w == END)
+        .ok_or_else(|| invalid_data("PLY header has no end"))?;
+    let mut body = position + END.len();
+    // the line ending, which may be \r\n
+    while body < data.len() && (data[body] == b'\r' || data[body] == b' ') {
+        body += 1;
+    }
+    if body < data.len() && data[body] == b'\n' {
+        body += 1;
+    }
+    let header = std::str::from_utf8(&data[..position])
+        .map_err(|_| invalid_data("PLY header is not text"))?;
+    Ok((header, &data[body..]))
+}
+
+// The properties of one vertex or face which matter for meshes.
+struct Record {
+    position: Vec3,
+    normal: Vec3,
+    color: Vec3,
+    uv: (f32, f32),
+    face: Vec<u32>,
+}
+
+fn read_record(body: &mut Body<'_>, element: &Element) -> io::Result<Record> {
+    let mut position = [0.0f32; 3];
+    let mut normal = [0.0f32; 3];
+    let mut color = [1.0f32; 3];
+    let mut uv = (0.0f32, 0.0f32);
+    let mut face: Vec<u32> = Vec::new();
+    for property in &element.properties {
+        match property {
+            Property::Scalar(name, scalar) => {
+                let value = body.read(*scalar)?;
+                let channel = || (value / scalar.full_scale()) as f32;
+                match name.as_str() {
+                    "x" => position[0] = value as f32,
+                    "y" => position[1] = value as f32,
+                    "z" => position[2] = value as f32,
+                    "nx" => normal[0] = value as f32,
+                    "ny" => normal[1] = value as f32,
+                    "nz" => normal[2] = value as f32,
+                    "red" | "r" => color[0] = channel(),
+                    "green" | "g" => color[1] = channel(),
+                    "blue" | "b" => color[2] = channel(),
+                    "u" | "s" | "texture_u" => uv.0 = value as f32,
+                    "v" | "t" | "texture_v" => uv.1 = value as f32,
+                    _ => {}
+                }
+            }
+            Property::List(name, count, item) => {
+                let count = body.read(*count)? as usize;
+                let is_face = name == "vertex_indices" || name == "vertex_index";
+                for _ in 0..count {
+                    let value = body.read(*item)?;
+                    if is_face {
+                        if value < 0.0 || value.fract() != 0.0 || value > f64::from(u32::MAX) {
+                            return Err(invalid_data(&format!("bad vertex index {value}")));
+                        }
+                        face.push(value as u32);
+                    }
+                }
+            }
+        }
+    }
+    Ok(Record {
+        position: Vec3::new(position[0], position[1], position[2]),
+        normal: Vec3::new(normal[0], normal[1], normal[2]),
+        color: Vec3::new(color[0], color[1], color[2]),
+        uv,
+        face,
+    })
+}
+
+pub fn load(path: &Path) -> io::Result<TriangleMesh> {
+    read(File::open(path)?)
+}
+
+pub fn read<R: Read>(mut reader: R) -> io::Result<TriangleMesh> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let (header, body) = split_header(&data)?;
+    let (format, elements) = parse_header(header)?;
+    let mut body = match format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| invalid_data("ASCII PLY body is not text"))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => Body::Binary {
+            data: body,
+            offset: 0,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut mesh = TriangleMesh::default();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut uvs = Vec::new();
+    for element in &elements {
+        let has = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .any(|p| matches!(p, Property::Scalar(name, _) if names.contains(&name.as_str())))
+        };
+        let has_normals = has(&["nx"]);
+        let has_colors = has(&["red", "r"]);
+        let has_uvs = has(&["u", "s", "texture_u"]);
+        for _ in 0..element.count {
+            let record = read_record(&mut body, element)?;
+            match element.name.as_str() {
+                "vertex" => {
+                    mesh.positions.push(record.position);
+                    if has_normals {
+                        normals.push(record.normal);
+                    }
+                    if has_colors {
+                        colors.push(record.color);
+                    }
+                    if has_uvs {
+                        uvs.push(record.uv);
+                    }
+                }
+                "face" => {
+                    let face = record.face;
+                    for i in 2..face.len() {
+                        mesh.triangles.push([face[0], face[i - 1], face[i]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    if !normals.is_empty() {
+        mesh.normals = Some(normals);
+    }
+    if !colors.is_empty() {
+        mesh.colors = Some(colors);
+    }
+    if !uvs.is_empty() {
+        mesh.uvs = Some(uvs);
+    }
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn ascii_with_colors() {
+        let ply = "ply
+format ascii 1.0
+comment a unit square
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+        let mesh = read(ply.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.colors.unwrap()[1], Vec3::new(0.0, 1.0, 0.0));
+        assert!(mesh.normals.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_vertex_indices() {
+        let header = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar float vertex_indices
+end_header
+0 0 0
+1 0 0
+0 1 0
+";
+        assert!(read(format!("{header}3 0 1 2\n").as_bytes()).is_ok());
+        assert!(read(format!("{header}3 0 -1 2\n").as_bytes()).is_err());
+        assert!(read(format!("{header}3 0 1.5 2\n").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn binary_with_normals() {
+        let mut ply = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+property float x\nproperty float y\nproperty float z\n\
+property float nx\nproperty float ny\nproperty float nz\n\
+element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+            .to_vec();
+        for v in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in v.iter().chain([0.0, 0.0, 1.0].iter()) {
+                ply.extend_from_slice(&x.to_be_bytes());
+            }
+        }
+        ply.push(3);
+        for i in 0..3u32 {
+            ply.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = read(ply.as_slice()).unwrap();
+        assert_eq!(mesh.positions[1], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.normals.unwrap()[2], Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+
+        assert!(read(
+            &b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n"[..]
+        )
+        .is_err());
+    }
+}
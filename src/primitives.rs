@@ -0,0 +1,645 @@
+use crate::{
+    aabb::Aabb,
+    hitable::HitRecord,
+    material::Material,
+    ray::Ray,
+    vec3::{coordinate_system, Vec3},
+};
+use std::f32::consts::PI;
+
+fn record<'obj>(
+    ray: &Ray,
+    t: f32,
+    n: Vec3,
+    (u, v): (f32, f32),
+    material: &'obj Material,
+) -> HitRecord<'obj> {
+    HitRecord {
+        t,
+        p: ray.point_at_parameter(t),
+        n,
+        u,
+        v,
+        color: Vec3::ones(),
+        material,
+    }
+}
+
+// The parameter at which the ray crosses the plane through `point` with normal `n`, if it lies
+// strictly between `t_min` and `t_max`.
+fn plane_t(ray: &Ray, point: Vec3, n: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+    let denominator = n.dot(ray.direction);
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = n.dot(point - ray.origin) / denominator;
+    if t > t_min && t < t_max {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// The angle around the y axis of the point (x, z), mapped to [0, 1).
+fn azimuth(x: f32, z: f32) -> f32 {
+    let phi = z.atan2(x);
+    if phi < 0.0 {
+        (phi + 2.0 * PI) / (2.0 * PI)
+    } else {
+        phi / (2.0 * PI)
+    }
+}
+
+// The roots of a t^2 + b t + c in (t_min, t_max), in increasing order.
+fn quadratic_roots(a: f32, b: f32, c: f32, t_min: f32, t_max: f32) -> Vec<f32> {
+    let mut roots = Vec::with_capacity(2);
+    if a == 0.0 {
+        if b != 0.0 {
+            roots.push(-c / b);
+        }
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return roots;
+        }
+        // avoids cancellation between b and the square root
+        let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+        if q == 0.0 {
+            roots.push(0.0);
+        } else {
+            roots.push(q / a);
+            roots.push(c / q);
+        }
+    }
+    roots.retain(|&t| t > t_min && t < t_max);
+    roots.sort_by(f32::total_cmp);
+    roots
+}
+
+/// An infinite plane through `point`. The normal points to the front side. `u` and `v` are world
+/// space distances along two axes in the plane, so textures tile rather than stretch.
+#[derive(Clone)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Material,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Material) -> Plane {
+        Plane {
+            point,
+            normal: normal.make_unit_vector(),
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let t = plane_t(ray, self.point, self.normal, t_min, t_max)?;
+        let (tangent, bitangent) = coordinate_system(self.normal);
+        let offset = ray.point_at_parameter(t) - self.point;
+        let uv = (offset.dot(tangent), offset.dot(bitangent));
+        Some(record(ray, t, self.normal, uv, &self.material))
+    }
+}
+
+/// A flat disk, or an annulus if the inner radius is positive. `u` runs around the centre and
+/// `v` from the centre outwards.
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f32,
+    pub inner_radius: f32,
+    pub material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Material) -> Disk {
+        Disk {
+            center,
+            normal: normal.make_unit_vector(),
+            radius,
+            inner_radius: 0.0,
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let t = plane_t(ray, self.center, self.normal, t_min, t_max)?;
+        let offset = ray.point_at_parameter(t) - self.center;
+        let distance = offset.length();
+        if distance > self.radius || distance < self.inner_radius {
+            return None;
+        }
+        let (tangent, bitangent) = coordinate_system(self.normal);
+        let uv = (
+            azimuth(offset.dot(tangent), offset.dot(bitangent)),
+            distance / self.radius,
+        );
+        Some(record(ray, t, self.normal, uv, &self.material))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        // the extent of a disk along each axis is radius * sin(angle between axis and normal)
+        let n = self.normal;
+        let extent = self.radius
+            * Vec3::new(
+                (1.0 - n.x * n.x).max(0.0).sqrt(),
+                (1.0 - n.y * n.y).max(0.0).sqrt(),
+                (1.0 - n.z * n.z).max(0.0).sqrt(),
+            );
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+/// A parallelogram spanned by two edges from a corner, which is a rectangle when the edges are
+/// perpendicular. The front side faces along `edge_u` x `edge_v`, and (`u`, `v`) are the
+/// coordinates of the hit along the edges.
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub edge_u: Vec3,
+    pub edge_v: Vec3,
+    pub material: Material,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, edge_u: Vec3, edge_v: Vec3, material: Material) -> Quad {
+        Quad {
+            corner,
+            edge_u,
+            edge_v,
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let cross = self.edge_u.cross(self.edge_v);
+        let normal = cross.make_unit_vector();
+        let t = plane_t(ray, self.corner, normal, t_min, t_max)?;
+        let offset = ray.point_at_parameter(t) - self.corner;
+        // coordinates along the edges, which need not be perpendicular
+        let w = (1.0 / cross.dot(cross)) * cross;
+        let u = w.dot(offset.cross(self.edge_v));
+        let v = w.dot(self.edge_u.cross(offset));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(record(ray, t, normal, (u, v), &self.material))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let corners = [
+            self.corner + self.edge_u,
+            self.corner + self.edge_v,
+            self.corner + self.edge_u + self.edge_v,
+        ];
+        corners
+            .iter()
+            .fold(Aabb::new(self.corner, self.corner), |acc, &p| {
+                acc.union(&Aabb::new(p, p))
+            })
+    }
+}
+
+/// An axis-aligned box. Each face has its own (`u`, `v`) in [0, 1].
+#[derive(Clone)]
+pub struct Cuboid {
+    pub bounds: Aabb,
+    pub material: Material,
+}
+
+impl Cuboid {
+    pub fn new(min: Vec3, max: Vec3, material: Material) -> Cuboid {
+        Cuboid {
+            bounds: Aabb::new(min, max),
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t0, t1) = self.bounds.hit(ray, t_min, t_max)?;
+        // the box is entered at t0 unless the ray starts inside, or t0 is clipped by t_min
+        let t = if t0 > t_min {
+            t0
+        } else if t1 < t_max {
+            t1
+        } else {
+            return None;
+        };
+        let p = ray.point_at_parameter(t);
+        let size = self.bounds.size();
+        let local = p - self.bounds.min;
+        let relative = [local.x / size.x, local.y / size.y, local.z / size.z];
+        // the face is on the axis where the hit is closest to either side of the box
+        let mut axis = 0;
+        let mut closest = f32::MAX;
+        for (i, &r) in relative.iter().enumerate() {
+            let distance = (size[i] * r.min(1.0 - r)).abs();
+            if distance < closest {
+                closest = distance;
+                axis = i;
+            }
+        }
+        let sign = if relative[axis] > 0.5 { 1.0 } else { -1.0 };
+        let (n, uv) = match axis {
+            0 => (Vec3::new(sign, 0.0, 0.0), (relative[2], relative[1])),
+            1 => (Vec3::new(0.0, sign, 0.0), (relative[0], relative[2])),
+            _ => (Vec3::new(0.0, 0.0, sign), (relative[0], relative[1])),
+        };
+        Some(record(ray, t, n, uv, &self.material))
+    }
+}
+
+/// A cylinder standing on its base centre along the y axis, closed by two caps. Instances give
+/// other orientations. On the side `u` runs around the axis and `v` up it, on the caps they are
+/// as for a disk.
+#[derive(Clone)]
+pub struct Cylinder {
+    pub base: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cylinder {
+    pub fn new(base: Vec3, radius: f32, height: f32, material: Material) -> Cylinder {
+        Cylinder {
+            base,
+            radius,
+            height,
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let o = ray.origin - self.base;
+        let d = ray.direction;
+        let mut closest: Option<(f32, Vec3, (f32, f32))> = None;
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        for t in quadratic_roots(a, b, c, t_min, t_max) {
+            let p = o + t * d;
+            if p.y >= 0.0 && p.y <= self.height {
+                let n = Vec3::new(p.x / self.radius, 0.0, p.z / self.radius);
+                closest = Some((t, n, (azimuth(p.x, p.z), p.y / self.height)));
+                break;
+            }
+        }
+        for (y, ny) in [(0.0, -1.0), (self.height, 1.0)] {
+            let t_max = closest.map_or(t_max, |(t, _, _)| t);
+            if let Some(t) = plane_t(
+                ray,
+                self.base + Vec3::new(0.0, y, 0.0),
+                Vec3::new(0.0, ny, 0.0),
+                t_min,
+                t_max,
+            ) {
+                let p = o + t * d;
+                let r2 = p.x * p.x + p.z * p.z;
+                if r2 <= self.radius * self.radius {
+                    let uv = (azimuth(p.x, p.z), r2.sqrt() / self.radius);
+                    closest = Some((t, Vec3::new(0.0, ny, 0.0), uv));
+                }
+            }
+        }
+        let (t, n, uv) = closest?;
+        Some(record(ray, t, n, uv, &self.material))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(
+            self.base - Vec3::new(r, 0.0, r),
+            self.base + Vec3::new(r, self.height, r),
+        )
+    }
+}
+
+/// A cone standing on its base centre along the y axis with the apex above it, closed by a cap
+/// at the base. The (`u`, `v`) are laid out like the cylinder's.
+#[derive(Clone)]
+pub struct Cone {
+    pub base: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    pub material: Material,
+}
+
+impl Cone {
+    pub fn new(base: Vec3, radius: f32, height: f32, material: Material) -> Cone {
+        Cone {
+            base,
+            radius,
+            height,
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let o = ray.origin - self.base;
+        let d = ray.direction;
+        let mut closest: Option<(f32, Vec3, (f32, f32))> = None;
+        // x^2 + z^2 = (radius - k y)^2 on the side
+        let k = self.radius / self.height;
+        let r0 = self.radius - k * o.y;
+        let a = d.x * d.x + d.z * d.z - k * k * d.y * d.y;
+        let b = 2.0 * (o.x * d.x + o.z * d.z + k * d.y * r0);
+        let c = o.x * o.x + o.z * o.z - r0 * r0;
+        for t in quadratic_roots(a, b, c, t_min, t_max) {
+            let p = o + t * d;
+            if p.y >= 0.0 && p.y <= self.height {
+                let n = Vec3::new(p.x, k * (self.radius - k * p.y), p.z).make_unit_vector();
+                closest = Some((t, n, (azimuth(p.x, p.z), p.y / self.height)));
+                break;
+            }
+        }
+        let t_max = closest.map_or(t_max, |(t, _, _)| t);
+        if let Some(t) = plane_t(ray, self.base, Vec3::new(0.0, -1.0, 0.0), t_min, t_max) {
+            let p = o + t * d;
+            let r2 = p.x * p.x + p.z * p.z;
+            if r2 <= self.radius * self.radius {
+                let uv = (azimuth(p.x, p.z), r2.sqrt() / self.radius);
+                closest = Some((t, Vec3::new(0.0, -1.0, 0.0), uv));
+            }
+        }
+        let (t, n, uv) = closest?;
+        Some(record(ray, t, n, uv, &self.material))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::new(
+            self.base - Vec3::new(r, 0.0, r),
+            self.base + Vec3::new(r, self.height, r),
+        )
+    }
+}
+
+/// A ring around the y axis through `center`: the points at `minor_radius` from the circle of
+/// `major_radius`. `u` runs around the y axis and `v` around the tube.
+#[derive(Clone)]
+pub struct Torus {
+    pub center: Vec3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vec3, major_radius: f32, minor_radius: f32, material: Material) -> Torus {
+        Torus {
+            center,
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // the quartic is solved in double precision from where the ray enters the bounds, with a
+        // unit direction, to keep its coefficients well conditioned
+        let (t_enter, t_exit) = self.bounding_box().hit(ray, t_min, t_max)?;
+        let length = ray.direction.length();
+        let start = ray.point_at_parameter(t_enter) - self.center;
+        let d = (1.0 / length) * ray.direction;
+        let (ox, oy, oz) = (f64::from(start.x), f64::from(start.y), f64::from(start.z));
+        let (dx, dy, dz) = (f64::from(d.x), f64::from(d.y), f64::from(d.z));
+        let major2 = f64::from(self.major_radius).powi(2);
+        let minor2 = f64::from(self.minor_radius).powi(2);
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2) along p = o + s d, with |d| = 1
+        let od = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + major2 - minor2;
+        let coefficients = [
+            k * k - 4.0 * major2 * (ox * ox + oz * oz),
+            4.0 * od * k - 8.0 * major2 * (ox * dx + oz * dz),
+            2.0 * k + 4.0 * od * od - 4.0 * major2 * (dx * dx + dz * dz),
+            4.0 * od,
+            1.0,
+        ];
+        let s_max = f64::from((t_exit - t_enter) * length);
+        let s = solve_quartic(coefficients)
+            .into_iter()
+            .filter(|&s| s >= 0.0 && s <= s_max)
+            .fold(f64::MAX, f64::min);
+        if s == f64::MAX {
+            return None;
+        }
+        let t = t_enter + s as f32 / length;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let p = ray.point_at_parameter(t) - self.center;
+        let radial = Vec3::new(p.x, 0.0, p.z);
+        let radial_length = radial.length();
+        let core = if radial_length > 0.0 {
+            (self.major_radius / radial_length) * radial
+        } else {
+            Vec3::zeros()
+        };
+        let n = (p - core).make_unit_vector();
+        let tube = p.y.atan2(radial_length - self.major_radius);
+        let v = if tube < 0.0 {
+            (tube + 2.0 * PI) / (2.0 * PI)
+        } else {
+            tube / (2.0 * PI)
+        };
+        Some(record(ray, t, n, (azimuth(p.x, p.z), v), &self.material))
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(outer, self.minor_radius, outer);
+        Aabb::new(self.center - extent, self.center + extent)
+    }
+}
+
+const EPSILON: f64 = 1e-9;
+
+// The real roots of c[0] + c[1] x + c[2] x^2 = 0.
+fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2.0 * c[2]);
+    let q = c[0] / c[2];
+    let discriminant = p * p - q;
+    if discriminant.abs() < EPSILON {
+        vec![-p]
+    } else if discriminant < 0.0 {
+        Vec::new()
+    } else {
+        let root = discriminant.sqrt();
+        vec![root - p, -root - p]
+    }
+}
+
+// The real roots of c[0] + c[1] x + c[2] x^2 + c[3] x^3 = 0, by Cardano's method.
+fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+
+    // substitute x = y - a/3 to eliminate the quadratic term: y^3 + 3 p y + 2 q = 0
+    let a2 = a * a;
+    let p = (-a2 / 3.0 + b) / 3.0;
+    let q = 0.5 * (2.0 / 27.0 * a * a2 - a * b / 3.0 + cc);
+    let p3 = p * p * p;
+    let discriminant = q * q + p3;
+
+    let mut roots = if discriminant.abs() < EPSILON {
+        if q.abs() < EPSILON {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // three real roots
+        let phi = (-q / (-p3).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + std::f64::consts::PI / 3.0).cos(),
+            -t * (phi - std::f64::consts::PI / 3.0).cos(),
+        ]
+    } else {
+        let root = discriminant.sqrt();
+        vec![(root - q).cbrt() - (root + q).cbrt()]
+    };
+    for x in &mut roots {
+        *x -= a / 3.0;
+    }
+    roots
+}
+
+// The real roots of c[0] + c[1] x + ... + c[4] x^4 = 0, by Ferrari's method, polished with a
+// couple of Newton steps.
+fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    // substitute x = y - a/4 to eliminate the cubic term: y^4 + p y^2 + q y + r = 0
+    let a2 = a * a;
+    let p = -3.0 / 8.0 * a2 + b;
+    let q = a2 * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * a2 * a2 + a2 * b / 16.0 - a * cc / 4.0 + d;
+
+    let mut roots = if r.abs() < EPSILON {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+        roots
+    } else {
+        // one root of the resolvent cubic splits the quartic into two quadratics
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() < EPSILON {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if v.abs() < EPSILON {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return Vec::new();
+        };
+        let v = if q < 0.0 { -v } else { v };
+        let mut roots = solve_quadratic([z - u, v, 1.0]);
+        roots.extend(solve_quadratic([z + u, -v, 1.0]));
+        roots
+    };
+
+    let polynomial = |x: f64| (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+    let derivative = |x: f64| ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+    for x in &mut roots {
+        *x -= a / 4.0;
+        for _ in 0..2 {
+            let slope = derivative(*x);
+            if slope != 0.0 {
+                *x -= polynomial(*x) / slope;
+            }
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod test {
+    use super::{solve_quartic, Cone, Cuboid, Cylinder, Quad, Torus};
+    use crate::{material::Material, ray::Ray, vec3::Vec3};
+
+    fn material() -> Material {
+        Material::lambertian(Vec3::ones())
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x + 2)(x - 3)(x - 0.5)
+        let mut roots = solve_quartic([-3.0, 8.5, -4.0, -2.5, 1.0]);
+        roots.sort_by(f64::total_cmp);
+        let expected = [-2.0, 0.5, 1.0, 3.0];
+        assert_eq!(roots.len(), 4);
+        for (root, expected) in roots.iter().zip(expected.iter()) {
+            assert!((root - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn torus_hits() {
+        let torus = Torus::new(Vec3::zeros(), 2.0, 0.5, material());
+        let ray = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), 0.0);
+        let hit = torus.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!((hit.p.x + 2.5).abs() < 1e-4);
+        assert!((hit.n - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-4);
+        // straight through the hole
+        let ray = Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(torus.hit(&ray, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn outward_normals() {
+        let down = Ray::new(Vec3::new(0.2, 5.0, 0.1), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let side = Ray::new(Vec3::new(-5.0, 0.5, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let cylinder = Cylinder::new(Vec3::zeros(), 1.0, 2.0, material());
+        let hit = cylinder.hit(&down, 0.001, f32::MAX).unwrap();
+        assert!((hit.p.y - 2.0).abs() < 1e-5 && hit.n.y == 1.0);
+        let hit = cylinder.hit(&side, 0.001, f32::MAX).unwrap();
+        assert!((hit.p.x + 1.0).abs() < 1e-5 && hit.n.x < -0.99);
+        let cone = Cone::new(Vec3::zeros(), 1.0, 2.0, material());
+        let hit = cone.hit(&side, 0.001, f32::MAX).unwrap();
+        assert!((hit.p.x + 0.75).abs() < 1e-5 && hit.n.x < 0.0 && hit.n.y > 0.0);
+        let cuboid = Cuboid::new(-Vec3::ones(), Vec3::ones(), material());
+        let hit = cuboid.hit(&side, 0.001, f32::MAX).unwrap();
+        assert_eq!(hit.n, Vec3::new(-1.0, 0.0, 0.0));
+        let inside = Ray::new(Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert_eq!(
+            cuboid.hit(&inside, 0.001, f32::MAX).unwrap().n,
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn quad_coordinates() {
+        let quad = Quad::new(
+            Vec3::zeros(),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            material(),
+        );
+        let ray = Ray::new(Vec3::new(1.5, 1.0, -0.5), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        let hit = quad.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!((hit.u - 0.5).abs() < 1e-6 && (hit.v - 0.5).abs() < 1e-6);
+        assert_eq!(hit.n, Vec3::new(0.0, 1.0, 0.0));
+        let miss = Ray::new(Vec3::new(-0.5, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+        assert!(quad.hit(&miss, 0.001, f32::MAX).is_none());
+    }
+}
@@ -1,14 +1,21 @@
 use crate::vec3::Vec3;
 
+/// The time is the instant, within the camera's shutter interval, at which the ray samples the
+/// scene. Rays scattered from a hit keep the time of the ray which made the hit.
 #[derive(Copy, Clone, Debug)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    pub time: f32,
 }
 
 impl Ray {
-    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
-        Ray { origin, direction }
+    pub fn new(origin: Vec3, direction: Vec3, time: f32) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn point_at_parameter(self, t: f32) -> Vec3 {
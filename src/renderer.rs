@@ -1,54 +1,410 @@
-use crate::camera::Camera;
-use crate::hitable::World;
+use crate::camera::CameraModel;
+use crate::checkpoint::{Checkpoint, PixelState};
+use crate::hitable::{HitRecord, World};
+use crate::light::Light;
+use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampling::{power_heuristic, Pcg32};
+use crate::tile::{tiles, Region, TileOrder};
 use crate::vec3::Vec3;
 use rand::prelude::*;
 use rayon::prelude::*;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
 
-const NUM_SAMPLES: u32 = 128;
-const MAX_DEPTH: u32 = 16;
+const T_MIN: f32 = 0.001;
+// Shadow rays towards punctual lights stop this fraction short of the light.
+const SHADOW_EPSILON: f32 = 1e-4;
 
-fn color(r: Ray, world: &World, rng: &mut ThreadRng, depth: u32) -> Vec3 {
-    if let Some(hit) = world.hit(&r, 0.001, std::f32::MAX) {
-        if depth < MAX_DEPTH {
-            let scatter = hit.material.scatter(r, hit, rng);
-            return scatter.attenuation * color(scatter.ray, world, rng, depth + 1);
-        } else {
-            return Vec3::zeros();
-        }
+// Samples the background for direct lighting at a non-specular scattering event, weighting the
+// contribution against the chance of the scattered ray hitting the background by itself.
+fn sample_background(r: Ray, hit: &HitRecord<'_>, world: &World, rng: &mut Pcg32) -> Vec3 {
+    let Some(sample) = world.background.sample(rng) else {
+        return Vec3::zeros();
+    };
+    let Some((f, scatter_pdf)) = hit.material.eval(r, hit, sample.direction) else {
+        return Vec3::zeros();
+    };
+    let shadow_ray = Ray::new(hit.p, sample.direction, r.time);
+    let transmittance = world.transmittance(&shadow_ray, T_MIN, f32::MAX, rng);
+    if transmittance == 0.0 {
+        return Vec3::zeros();
+    }
+    let weight = power_heuristic(sample.pdf, scatter_pdf);
+    (transmittance * weight / sample.pdf) * (f * sample.radiance)
+}
+
+// The normal used to judge which lights matter at a scattering event, None inside media.
+fn shading_normal(hit: &HitRecord<'_>) -> Option<Vec3> {
+    match hit.material {
+        Material::Volumetric(_) => None,
+        _ => Some(hit.n),
+    }
+}
+
+// Picks one light with the world's light sampler and samples direct lighting from it.
+fn sample_light(r: Ray, hit: &HitRecord<'_>, world: &World, rng: &mut Pcg32) -> Vec3 {
+    let u = rng.gen::<f32>();
+    let Some((index, pmf)) = world.light_sampler().sample(hit.p, shading_normal(hit), u) else {
+        return Vec3::zeros();
+    };
+    let light = &world.lights()[index];
+    let Some(sample) = light.sample(hit.p, rng) else {
+        return Vec3::zeros();
+    };
+    let Some((f, scatter_pdf)) = hit.material.eval(r, hit, sample.direction) else {
+        return Vec3::zeros();
+    };
+    let shadow_ray = Ray::new(hit.p, sample.direction, r.time);
+    let t_max = sample.distance * (1.0 - SHADOW_EPSILON);
+    let transmittance = world.transmittance(&shadow_ray, T_MIN, t_max, rng);
+    if transmittance == 0.0 {
+        return Vec3::zeros();
+    }
+    let light_pdf = pmf * sample.pdf;
+    let weight = if light.is_delta() {
+        1.0
     } else {
-        let unit_direction = r.direction.make_unit_vector();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        return (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0);
+        power_heuristic(light_pdf, scatter_pdf)
+    };
+    (transmittance * weight / light_pdf) * (f * sample.radiance)
+}
+
+// The multiple importance sampling weight of emission found by a scattered ray which hit
+// `object`, accounting for the chance of light sampling having found it instead.
+fn emission_weight(world: &World, object: Option<usize>, previous: &ScatterEvent) -> f32 {
+    let Some(pdf) = previous.pdf else {
+        return 1.0;
+    };
+    let Some(light_index) = object.and_then(|object| world.object_light(object)) else {
+        return 1.0;
+    };
+    let light_pdf = match &world.lights()[light_index] {
+        Light::Sphere(sphere) => sphere.pdf(previous.p),
+        _ => return 1.0,
+    };
+    let pmf = world
+        .light_sampler()
+        .pmf(previous.p, previous.n, light_index);
+    power_heuristic(pdf, pmf * light_pdf)
+}
+
+// Where the current ray was scattered from.
+struct ScatterEvent {
+    p: Vec3,
+    n: Option<Vec3>,
+    // the pdf with which the ray was scattered, None for camera rays and specular bounces which
+    // light sampling cannot produce
+    pdf: Option<f32>,
+}
+
+fn color(mut r: Ray, world: &World, max_depth: u32, rng: &mut Pcg32) -> Vec3 {
+    let mut radiance = Vec3::zeros();
+    let mut throughput = Vec3::ones();
+    let mut previous = ScatterEvent {
+        p: r.origin,
+        n: None,
+        pdf: None,
+    };
+    for depth in 0..=max_depth {
+        let Some((object, hit)) = world.hit_object(&r, T_MIN, f32::MAX, rng) else {
+            let weight = match previous.pdf {
+                Some(pdf) => power_heuristic(pdf, world.background.pdf(r.direction)),
+                None => 1.0,
+            };
+            radiance += weight * (throughput * world.background.radiance(r.direction));
+            break;
+        };
+        let emitted = hit.material.emitted(r, &hit);
+        if emitted != Vec3::zeros() {
+            let weight = emission_weight(world, object, &previous);
+            radiance += weight * (throughput * emitted);
+        }
+        if depth == max_depth {
+            break;
+        }
+        radiance += throughput * sample_background(r, &hit, world, rng);
+        radiance += throughput * sample_light(r, &hit, world, rng);
+        let Some(scatter) = hit.material.scatter(r, hit, rng) else {
+            break;
+        };
+        throughput = throughput * scatter.attenuation;
+        previous = ScatterEvent {
+            p: hit.p,
+            n: shading_normal(&hit),
+            pdf: scatter.pdf,
+        };
+        r = scatter.ray;
     }
+    radiance
+}
+
+/// What to render and how hard to try.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderSettings {
+    /// image size in pixels
+    pub width: usize,
+    pub height: usize,
+    /// camera rays per pixel
+    pub samples: u32,
+    /// the number of times a path may scatter
+    pub max_depth: u32,
+    /// the width and height of the tiles the image is rendered in
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// the part of the image to render, all of it if None
+    pub crop: Option<Region>,
+    /// picks the random numbers, so that renders with the same seed come out the same
+    pub seed: u64,
+}
+
+impl RenderSettings {
+    pub fn new(width: usize, height: usize, samples: u32) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            samples,
+            max_depth: 16,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            crop: None,
+            seed: 0,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> RenderSettings {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_tiles(mut self, tile_size: usize, tile_order: TileOrder) -> RenderSettings {
+        self.tile_size = tile_size;
+        self.tile_order = tile_order;
+        self
+    }
+
+    /// Renders only `region` of the image, leaving the rest black.
+    pub fn with_crop(mut self, region: Region) -> RenderSettings {
+        self.crop = Some(region);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> RenderSettings {
+        self.seed = seed;
+        self
+    }
+
+    /// The part of the image to render, within its bounds.
+    pub fn region(&self) -> Region {
+        let image = Region::new(0, 0, self.width, self.height);
+        self.crop.map_or(image, |crop| crop.intersect(image))
+    }
+
+    /// The aspect ratio to give the camera.
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings::new(640, 320, 128)
+    }
+}
+
+/// A rendered image of linear radiance, stored by rows from the top left.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
 }
 
 fn to_bgra(r: u32, g: u32, b: u32) -> u32 {
     255 << 24 | r << 16 | g << 8 | b
 }
 
-pub fn render(width: usize, height: usize, camera: Camera, world: World) -> Vec<u32> {
-    (0..width * height)
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Vec3::zeros(); width * height],
+        }
+    }
+
+    /// The pixel in column `x` and row `y`, counting rows from the top.
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// 8 bit red, green and blue per pixel with a gamma of 2, clamping to white.
+    pub fn to_rgb8(&self) -> Vec<[u8; 3]> {
+        let encode = |x: f32| (255.99 * x.clamp(0.0, 1.0).sqrt()) as u8;
+        self.pixels
+            .iter()
+            .map(|c| [encode(c.x), encode(c.y), encode(c.z)])
+            .collect()
+    }
+
+    /// Packed 0xAARRGGBB pixels, as taken by window buffers.
+    pub fn to_bgra(&self) -> Vec<u32> {
+        self.to_rgb8()
+            .into_iter()
+            .map(|[r, g, b]| to_bgra(u32::from(r), u32::from(g), u32::from(b)))
+            .collect()
+    }
+
+    /// Writes the image as a binary PPM file, encoded as by `to_rgb8`.
+    pub fn write_ppm(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for rgb in self.to_rgb8() {
+            file.write_all(&rgb)?;
+        }
+        file.flush()
+    }
+}
+
+// Adds camera rays through the pixel in column `x` and row `y` from the top, until it has
+// `target` samples.
+fn render_pixel(
+    settings: &RenderSettings,
+    camera: &dyn CameraModel,
+    world: &World,
+    (x, y): (usize, usize),
+    pixel: &mut PixelState,
+    target: u32,
+) {
+    let (width, height) = (settings.width as f32, settings.height as f32);
+    let row = settings.height - 1 - y;
+    let rng = &mut pixel.rng;
+    while pixel.samples < target {
+        let u = ((x as f32) + rng.gen::<f32>()) / width;
+        let v = ((row as f32) + rng.gen::<f32>()) / height;
+        if let Some((r, weight)) = camera.make_weighted_ray(rng, u, v) {
+            pixel.sum += weight * color(r, world, settings.max_depth, rng);
+        }
+        pixel.samples += 1;
+    }
+}
+
+// Brings every pixel in the region of the settings up to `target` samples, tile by tile.
+fn render_pass<F>(
+    settings: &RenderSettings,
+    camera: &dyn CameraModel,
+    world: &World,
+    checkpoint: &mut Checkpoint,
+    target: u32,
+    on_tile: &F,
+) where
+    F: Fn(Region, &[Vec3]) + Sync,
+{
+    let tiles = tiles(settings.region(), settings.tile_size, settings.tile_order);
+    let next = AtomicUsize::new(0);
+    let width = settings.width;
+    let states = Mutex::new(&mut checkpoint.pixels);
+    (0..rayon::current_num_threads())
         .into_par_iter()
-        .map_init(
-            || thread_rng(),
-            |mut rng, screen_pos| {
-                let mut c = Vec3::new(0.0, 0.0, 0.0);
-                let i = height - 1 - screen_pos / width;
-                let j = screen_pos % width;
-                for _ in 0..NUM_SAMPLES {
-                    let u = ((j as f32) + rng.gen::<f32>()) / (width as f32);
-                    let v = ((i as f32) + rng.gen::<f32>()) / (height as f32);
-                    let r = camera.make_ray(&mut rng, u, v);
-                    c += color(r, &world, &mut rng, 0);
+        .for_each(|_| {
+            while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                let rows = tile.y..tile.y + tile.height;
+                let mut pixels: Vec<PixelState> = {
+                    let states = states.lock().unwrap();
+                    rows.clone()
+                        .flat_map(|y| &states[y * width + tile.x..][..tile.width])
+                        .copied()
+                        .collect()
+                };
+                let positions =
+                    rows.flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)));
+                for (position, pixel) in positions.zip(&mut pixels) {
+                    render_pixel(settings, camera, world, position, pixel, target);
+                }
+                {
+                    let mut states = states.lock().unwrap();
+                    for (row, line) in pixels.chunks_exact(tile.width).enumerate() {
+                        let start = (tile.y + row) * width + tile.x;
+                        states[start..start + tile.width].copy_from_slice(line);
+                    }
                 }
-                c = (1.0 / NUM_SAMPLES as f32) * c;
-                let ir = (255.99 * c.x.sqrt()) as u32;
-                let ig = (255.99 * c.y.sqrt()) as u32;
-                let ib = (255.99 * c.z.sqrt()) as u32;
-
-                to_bgra(ir, ig, ib)
-            },
-        )
-        .collect()
+                let colors: Vec<Vec3> = pixels.iter().map(PixelState::average).collect();
+                on_tile(tile, &colors);
+            }
+        });
+}
+
+/// Renders the world as seen by the camera, using all cores.
+pub fn render(settings: &RenderSettings, camera: &dyn CameraModel, world: &World) -> Framebuffer {
+    render_tiles(settings, camera, world, |_, _| {})
+}
+
+/// Renders the image tile by tile, in the order of the settings. Each thread takes the next
+/// tile when done with its last, and calls `on_tile` with the tile and its pixels, by rows from
+/// the top, so that viewers can show the image as it comes together.
+pub fn render_tiles<F>(
+    settings: &RenderSettings,
+    camera: &dyn CameraModel,
+    world: &World,
+    on_tile: F,
+) -> Framebuffer
+where
+    F: Fn(Region, &[Vec3]) + Sync,
+{
+    let mut checkpoint = Checkpoint::new(settings);
+    render_pass(
+        settings,
+        camera,
+        world,
+        &mut checkpoint,
+        settings.samples,
+        &on_tile,
+    );
+    checkpoint.framebuffer()
+}
+
+/// Renders in passes of `pass_samples` samples per pixel, continuing from `checkpoint` until
+/// every pixel has the samples of the settings, and calls `on_pass` with the checkpoint after
+/// each pass so that it can be saved. Resuming with more samples than the checkpoint was
+/// started with adds to it. The camera and world must be those the checkpoint was started with,
+/// and the result is then the same as that of `render`.
+pub fn render_resumable<F>(
+    settings: &RenderSettings,
+    camera: &dyn CameraModel,
+    world: &World,
+    checkpoint: &mut Checkpoint,
+    pass_samples: u32,
+    mut on_pass: F,
+) -> io::Result<Framebuffer>
+where
+    F: FnMut(&Checkpoint) -> io::Result<()>,
+{
+    if !checkpoint.matches(settings) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the checkpoint is of a render with another size or seed",
+        ));
+    }
+    let region = settings.region();
+    let mut done = (region.y..region.y + region.height)
+        .flat_map(|y| (region.x..region.x + region.width).map(move |x| (x, y)))
+        .map(|(x, y)| checkpoint.samples(x, y))
+        .min()
+        .unwrap_or(settings.samples);
+    while done < settings.samples {
+        let target = done
+            .saturating_add(pass_samples.max(1))
+            .min(settings.samples);
+        render_pass(settings, camera, world, checkpoint, target, &|_, _| {});
+        on_pass(checkpoint)?;
+        done = target;
+    }
+    Ok(checkpoint.framebuffer())
 }
@@ -0,0 +1,271 @@
+use crate::vec3::{coordinate_system, Vec3};
+use rand::prelude::*;
+use std::f32::consts::PI;
+
+/// A piecewise-constant distribution over [0, 1), built from a tabulated function.
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    func_int: f32,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f32>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].abs() / n as f32;
+        }
+        let func_int = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            // fall back to a uniform distribution if the function is zero everywhere
+            *c = if func_int > 0.0 {
+                *c / func_int
+            } else {
+                i as f32 / n as f32
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// The integral of the tabulated function over [0, 1).
+    pub fn integral(&self) -> f32 {
+        self.func_int
+    }
+
+    fn find_interval(&self, u: f32) -> usize {
+        // index of the last cdf entry <= u
+        let i = self.cdf.partition_point(|&c| c <= u);
+        i.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// Returns the sampled position in [0, 1), its pdf and the index of the segment it lies in.
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let offset = self.find_interval(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = self.pdf(offset);
+        ((offset as f32 + du) / self.count() as f32, pdf, offset)
+    }
+
+    /// Samples a segment index with probability proportional to the function value.
+    pub fn sample_discrete(&self, u: f32) -> (usize, f32) {
+        let offset = self.find_interval(u);
+        (offset, self.discrete_pdf(offset))
+    }
+
+    /// The density of `sample_continuous` inside segment `index`.
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.func_int > 0.0 {
+            self.func[index].abs() / self.func_int
+        } else {
+            1.0
+        }
+    }
+
+    /// The probability of `sample_discrete` returning `index`.
+    pub fn discrete_pdf(&self, index: usize) -> f32 {
+        self.pdf(index) / self.count() as f32
+    }
+}
+
+/// A piecewise-constant distribution over [0, 1)^2, built from a row-major table.
+#[derive(Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize, height: usize) -> Distribution2D {
+        let conditional: Vec<Distribution1D> = func
+            .chunks_exact(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal =
+            Distribution1D::new(conditional.iter().map(Distribution1D::integral).collect());
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Returns the sampled (u, v) position and its pdf.
+    pub fn sample_continuous(&self, u0: f32, u1: f32) -> ((f32, f32), f32) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// The integral of the tabulated function over [0, 1)^2.
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    pub fn pdf(&self, u: f32, v: f32) -> f32 {
+        let row_count = self.marginal.count();
+        let column_count = self.conditional[0].count();
+        let row = ((v * row_count as f32) as usize).min(row_count - 1);
+        let column = ((u * column_count as f32) as usize).min(column_count - 1);
+        let row_pdf = self.marginal.pdf(row);
+        if self.marginal.integral() > 0.0 {
+            self.conditional[row].pdf(column) * row_pdf
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Multiple importance sampling weight for a sample from a strategy with pdf `f` combined with a
+/// strategy with pdf `g`.
+pub fn power_heuristic(f: f32, g: f32) -> f32 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2 + g2 > 0.0 {
+        f2 / (f2 + g2)
+    } else {
+        0.0
+    }
+}
+
+/// Samples a direction uniformly within the cone of half angle acos(`cos_theta_max`) around the
+/// unit vector `axis`. The pdf is `1 / (2 pi (1 - cos_theta_max))`.
+pub fn uniform_cone(axis: Vec3, cos_theta_max: f32, rng: &mut Pcg32) -> Vec3 {
+    let cos_theta = 1.0 - rng.gen::<f32>() * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.gen::<f32>();
+    let (u, v) = coordinate_system(axis);
+    sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * axis
+}
+
+/// The PCG32 random number generator of O'Neill, used for all sampling. Unlike the thread
+/// local generator its state can be seeded, saved and restored, so that renders are repeatable
+/// and can be resumed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+    /// A generator of one of 2^63 independent `stream`s.
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// The state and stream increment, as taken by `from_state`.
+    pub fn state(&self) -> (u64, u64) {
+        (self.state, self.increment)
+    }
+
+    pub fn from_state(state: u64, increment: u64) -> Pcg32 {
+        Pcg32 {
+            state,
+            increment: increment | 1,
+        }
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(Pcg32::MULTIPLIER)
+            .wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32()) | u64::from(self.next_u32()) << 32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Distribution1D, Distribution2D, Pcg32};
+    use rand::prelude::*;
+
+    #[test]
+    fn pcg32_streams_and_state() {
+        // the reference output of the minimal C implementation for seed 42, sequence 54
+        let mut rng = Pcg32::new(42, 54);
+        let expected = [
+            0xa15c_02b7,
+            0x7b47_f409,
+            0xba1d_3330,
+            0x83d2_f293,
+            0xbfa4_784b,
+        ];
+        for &value in &expected {
+            assert_eq!(rng.next_u32(), value);
+        }
+        let (state, increment) = rng.state();
+        let mut copy = Pcg32::from_state(state, increment);
+        assert_eq!(copy.gen::<u64>(), rng.gen::<u64>());
+        assert_ne!(Pcg32::new(42, 1).next_u32(), Pcg32::new(42, 2).next_u32());
+    }
+
+    #[test]
+    fn distribution_1d() {
+        let d = Distribution1D::new(vec![1.0, 3.0]);
+        assert_eq!(d.integral(), 2.0);
+        let (x, pdf, offset) = d.sample_continuous(0.125);
+        assert_eq!((x, pdf, offset), (0.25, 0.5, 0));
+        let (x, pdf, offset) = d.sample_continuous(0.625);
+        assert_eq!((x, pdf, offset), (0.75, 1.5, 1));
+        assert_eq!(d.sample_discrete(0.3), (1, 0.75));
+    }
+
+    #[test]
+    fn distribution_1d_zero() {
+        let d = Distribution1D::new(vec![0.0, 0.0, 0.0, 0.0]);
+        let (x, pdf, _) = d.sample_continuous(0.6);
+        assert!((x - 0.6).abs() < 1e-6);
+        assert_eq!(pdf, 1.0);
+    }
+
+    #[test]
+    fn distribution_2d() {
+        let d = Distribution2D::new(&[0.0, 1.0, 0.0, 3.0], 2, 2);
+        let ((u, v), pdf) = d.sample_continuous(0.5, 0.5);
+        assert!(u >= 0.5 && v >= 0.5);
+        assert_eq!(pdf, d.pdf(u, v));
+        assert_eq!(d.pdf(0.75, 0.75), 3.0);
+        assert_eq!(d.pdf(0.25, 0.75), 0.0);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{aabb::Aabb, hitable::HitRecord, material::Material, ray::Ray, vec3::Vec3};
+
+const MAX_STEPS: usize = 512;
+// Distance at which the march counts as having reached the surface.
+const SURFACE_EPSILON: f32 = 1e-4;
+
+/// A signed distance function built from primitives and operators: negative inside the shape,
+/// positive outside, and ideally the distance to the surface. The primitives are centred at the
+/// origin, and operators such as `translate` move them into place.
+#[derive(Clone, Debug)]
+pub enum SdfNode {
+    Sphere(f32),
+    // half the size of the box along each axis
+    Cuboid(Vec3),
+    // major and minor radius of a ring around the y axis
+    Torus(f32, f32),
+    // a Mandelbulb fractal of the given power, using a distance estimate after some iterations
+    Mandelbulb { power: f32, iterations: u32 },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    Difference(Box<SdfNode>, Box<SdfNode>),
+    // a union blending the shapes over a distance `k`
+    SmoothUnion(Box<SdfNode>, Box<SdfNode>, f32),
+    Translate(Box<SdfNode>, Vec3),
+    Scale(Box<SdfNode>, f32),
+    // grows the shape by a radius, rounding its edges
+    Round(Box<SdfNode>, f32),
+    // repeats space with the given period along each axis, where a zero period doesn't repeat
+    Repeat(Box<SdfNode>, Vec3),
+    // rotates around the y axis by `rate` radians per unit of height
+    Twist(Box<SdfNode>, f32),
+    // offsets the surface by amplitude * sin(f x) sin(f y) sin(f z)
+    Displace(Box<SdfNode>, f32, f32),
+}
+
+fn repeat(x: f32, period: f32) -> f32 {
+    if period > 0.0 {
+        x - period * (x / period).round()
+    } else {
+        x
+    }
+}
+
+impl SdfNode {
+    pub fn sphere(radius: f32) -> SdfNode {
+        SdfNode::Sphere(radius)
+    }
+
+    pub fn cuboid(half_size: Vec3) -> SdfNode {
+        SdfNode::Cuboid(half_size)
+    }
+
+    /// A box of the given half size with edges rounded by `radius`.
+    pub fn rounded_box(half_size: Vec3, radius: f32) -> SdfNode {
+        let inner = half_size - Vec3::new(radius, radius, radius);
+        SdfNode::cuboid(inner).round(radius)
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> SdfNode {
+        SdfNode::Torus(major_radius, minor_radius)
+    }
+
+    pub fn mandelbulb(power: f32, iterations: u32) -> SdfNode {
+        SdfNode::Mandelbulb { power, iterations }
+    }
+
+    pub fn union(self, other: SdfNode) -> SdfNode {
+        SdfNode::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: SdfNode) -> SdfNode {
+        SdfNode::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn difference(self, other: SdfNode) -> SdfNode {
+        SdfNode::Difference(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, k: f32) -> SdfNode {
+        SdfNode::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn translate(self, offset: Vec3) -> SdfNode {
+        SdfNode::Translate(Box::new(self), offset)
+    }
+
+    pub fn scale(self, factor: f32) -> SdfNode {
+        SdfNode::Scale(Box::new(self), factor)
+    }
+
+    pub fn round(self, radius: f32) -> SdfNode {
+        SdfNode::Round(Box::new(self), radius)
+    }
+
+    pub fn repeat(self, period: Vec3) -> SdfNode {
+        SdfNode::Repeat(Box::new(self), period)
+    }
+
+    pub fn twist(self, rate: f32) -> SdfNode {
+        SdfNode::Twist(Box::new(self), rate)
+    }
+
+    pub fn displace(self, amplitude: f32, frequency: f32) -> SdfNode {
+        SdfNode::Displace(Box::new(self), amplitude, frequency)
+    }
+
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            SdfNode::Sphere(radius) => p.length() - radius,
+            SdfNode::Cuboid(b) => {
+                let q = Vec3::new(p.x.abs() - b.x, p.y.abs() - b.y, p.z.abs() - b.z);
+                let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            SdfNode::Torus(major, minor) => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major;
+                (ring * ring + p.y * p.y).sqrt() - minor
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, *power, *iterations),
+            SdfNode::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            SdfNode::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + h * (da - db) - k * h * (1.0 - h)
+            }
+            SdfNode::Translate(node, offset) => node.distance(p - *offset),
+            SdfNode::Scale(node, factor) => factor * node.distance((1.0 / factor) * p),
+            SdfNode::Round(node, radius) => node.distance(p) - radius,
+            SdfNode::Repeat(node, period) => node.distance(Vec3::new(
+                repeat(p.x, period.x),
+                repeat(p.y, period.y),
+                repeat(p.z, period.z),
+            )),
+            SdfNode::Twist(node, rate) => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                node.distance(Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+            }
+            SdfNode::Displace(node, amplitude, frequency) => {
+                let f = *frequency;
+                node.distance(p) + amplitude * (f * p.x).sin() * (f * p.y).sin() * (f * p.z).sin()
+            }
+        }
+    }
+
+    // An upper bound on how fast the function can change per unit distance, within `extent` of
+    // the y axis. Warping operators make it exceed one, which would make sphere tracing step
+    // through the surface unless the steps are shortened accordingly.
+    fn lipschitz(&self, extent: f32) -> f32 {
+        match self {
+            SdfNode::Sphere(_)
+            | SdfNode::Cuboid(_)
+            | SdfNode::Torus(..)
+            | SdfNode::Mandelbulb { .. } => 1.0,
+            SdfNode::Union(a, b)
+            | SdfNode::Intersection(a, b)
+            | SdfNode::Difference(a, b)
+            | SdfNode::SmoothUnion(a, b, _) => a.lipschitz(extent).max(b.lipschitz(extent)),
+            SdfNode::Translate(node, _)
+            | SdfNode::Scale(node, _)
+            | SdfNode::Round(node, _)
+            | SdfNode::Repeat(node, _) => node.lipschitz(extent),
+            SdfNode::Twist(node, rate) => {
+                node.lipschitz(extent) * (1.0 + (rate * extent).powi(2)).sqrt()
+            }
+            SdfNode::Displace(node, amplitude, frequency) => {
+                node.lipschitz(extent) + (amplitude * frequency).abs() * 3.0f32.sqrt()
+            }
+        }
+    }
+}
+
+fn mandelbulb(p: Vec3, power: f32, iterations: u32) -> f32 {
+    let mut z = p;
+    let mut dr = 1.0;
+    let mut r = 0.0;
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2.0 || r == 0.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z =
+            zr * Vec3::new(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) + p;
+    }
+    if r == 0.0 {
+        return 0.0;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// A shape given by a signed distance function, found by sphere tracing: stepping along the ray
+/// by the distance to the nearest surface until it is close enough. The function need only be
+/// evaluated inside `bounds`, which the user provides as it can't be derived in general.
+#[derive(Clone)]
+pub struct Sdf {
+    pub node: SdfNode,
+    pub bounds: Aabb,
+    pub material: Material,
+    lipschitz: f32,
+}
+
+impl Sdf {
+    pub fn new(node: SdfNode, bounds: Aabb, material: Material) -> Sdf {
+        // the largest distance from the y axis, which twists turn around
+        let x = bounds.min.x.abs().max(bounds.max.x.abs());
+        let z = bounds.min.z.abs().max(bounds.max.z.abs());
+        let extent = x.hypot(z);
+        let lipschitz = node.lipschitz(extent);
+        Sdf {
+            node,
+            bounds,
+            material,
+            lipschitz,
+        }
+    }
+
+    // The outward normal from the gradient, estimated with the tetrahedron technique.
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let h = 0.5 * SURFACE_EPSILON;
+        let offsets = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        let gradient = offsets.iter().fold(Vec3::zeros(), |acc, &k| {
+            acc + self.node.distance(p + h * k) * k
+        });
+        gradient.make_unit_vector()
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t0, t1) = self.bounds.hit(ray, t_min, t_max)?;
+        let length = ray.direction.length();
+        let mut t = t0;
+        let mut distance = self.node.distance(ray.point_at_parameter(t));
+        // a ray starting on the surface, as after scattering, first steps clear of it
+        let mut steps = 0;
+        while t0 == t_min && distance.abs() < SURFACE_EPSILON && steps < 8 {
+            t += SURFACE_EPSILON / length;
+            distance = self.node.distance(ray.point_at_parameter(t));
+            steps += 1;
+        }
+        // march on the absolute distance, which also finds the way out from inside the shape
+        for _ in 0..MAX_STEPS {
+            if t > t1 {
+                return None;
+            }
+            if distance.abs() < SURFACE_EPSILON {
+                if t <= t_min || t >= t_max {
+                    return None;
+                }
+                let p = ray.point_at_parameter(t);
+                return Some(HitRecord {
+                    t,
+                    p,
+                    n: self.normal(p),
+                    u: 0.0,
+                    v: 0.0,
+                    color: Vec3::ones(),
+                    material: &self.material,
+                });
+            }
+            t += distance.abs() / (self.lipschitz * length);
+            distance = self.node.distance(ray.point_at_parameter(t));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sdf, SdfNode};
+    use crate::{aabb::Aabb, material::Material, ray::Ray, vec3::Vec3};
+
+    #[test]
+    fn operators() {
+        let a = SdfNode::sphere(1.0);
+        let b = SdfNode::sphere(1.0).translate(Vec3::new(3.0, 0.0, 0.0));
+        let p = Vec3::new(1.5, 0.0, 0.0);
+        assert_eq!(a.clone().union(b.clone()).distance(p), 0.5);
+        assert!(a.clone().smooth_union(b, 1.0).distance(p) < 0.5);
+        assert_eq!(
+            a.clone()
+                .repeat(Vec3::new(4.0, 0.0, 0.0))
+                .distance(Vec3::new(8.0, 0.0, 0.0)),
+            -1.0
+        );
+        let rounded = SdfNode::rounded_box(Vec3::ones(), 0.25);
+        assert!(rounded.distance(Vec3::new(1.0, 0.0, 0.0)).abs() < 1e-6);
+        assert!(rounded.distance(Vec3::ones()) > 0.0);
+    }
+
+    #[test]
+    fn sphere_tracing_matches_sphere() {
+        let bounds = Aabb::new(-Vec3::ones(), Vec3::ones());
+        let sdf = Sdf::new(
+            SdfNode::sphere(1.0),
+            bounds,
+            Material::lambertian(Vec3::ones()),
+        );
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0), 0.0);
+        let hit = sdf.hit(&ray, 0.001, f32::MAX).unwrap();
+        assert!((hit.p.z - 1.0).abs() < 1e-3);
+        assert!((hit.n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-2);
+        // the way out from inside
+        let exit = sdf.hit(&ray, hit.t + 0.01, f32::MAX).unwrap();
+        assert!((exit.p.z + 1.0).abs() < 1e-3);
+        assert!((exit.n - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-2);
+    }
+
+    #[test]
+    fn twist_bound_covers_off_centre_bounds() {
+        let bounds = Aabb::new(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 3.0));
+        let sdf = Sdf::new(
+            SdfNode::sphere(1.0).twist(1.0),
+            bounds,
+            Material::lambertian(Vec3::ones()),
+        );
+        // the corner at (-3, y, 3) is furthest from the axis, which neither corner given reaches
+        assert!((sdf.lipschitz - 19f32.sqrt()).abs() < 1e-5);
+    }
+}
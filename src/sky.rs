@@ -0,0 +1,288 @@
+use crate::{
+    color::{blackbody, luminance, xyz_to_rgb},
+    environment::EnvironmentMap,
+    light::LightSample,
+    sampling::{uniform_cone, Pcg32},
+    vec3::Vec3,
+};
+use rand::prelude::*;
+use std::f32::consts::PI;
+
+// Angular radius of the sun as seen from the earth.
+const SUN_ANGULAR_RADIUS: f32 = 0.004_65;
+// Luminance of the sun disk outside the atmosphere, in kcd/m^2.
+const SUN_LUMINANCE: f32 = 1.6e6;
+// Resolution of the table the sky is importance sampled from.
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+// Coefficients of the Perez sky luminance distribution function.
+#[derive(Copy, Clone)]
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos() * gamma.cos())
+    }
+}
+
+/// The Preetham, Shirley & Smits analytic daylight model: a clear sky parameterised by the sun
+/// direction and the atmospheric turbidity, together with the sun disk as seen through that
+/// atmosphere. Radiance is in kcd/m^2, scaled by `intensity`.
+#[derive(Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    intensity: f32,
+    perez: [Perez; 3],
+    // zenith luminance Y and chromaticity x, y
+    zenith: [f32; 3],
+    sun_radiance: Vec3,
+    cos_sun_radius: f32,
+    table: EnvironmentMap,
+}
+
+fn polynomial(t: f32, coefficients: [f32; 4]) -> f32 {
+    ((coefficients[0] * t + coefficients[1]) * t + coefficients[2]) * t + coefficients[3]
+}
+
+// Attenuation of direct sunlight by Rayleigh and aerosol scattering at the given wavelength.
+fn sun_transmittance(wavelength_um: f32, turbidity: f32, air_mass: f32) -> f32 {
+    let beta = 0.046_08 * turbidity - 0.045_86;
+    let rayleigh = (-0.008_735 * wavelength_um.powf(-4.08) * air_mass).exp();
+    let aerosol = (-beta * wavelength_um.powf(-1.3) * air_mass).exp();
+    rayleigh * aerosol
+}
+
+impl Sky {
+    /// `sun_direction` points towards the sun, with +y up. Turbidity ranges from 2 for a very
+    /// clear sky to around 10 for a hazy one.
+    pub fn new(sun_direction: Vec3, turbidity: f32, intensity: f32) -> Sky {
+        let t = turbidity;
+        let sun_direction = sun_direction.make_unit_vector();
+        // the model is only valid for the sun above the horizon
+        let theta_s = sun_direction.y.clamp(0.0, 1.0).acos();
+        let perez = [
+            Perez {
+                a: 0.178_7 * t - 1.463_0,
+                b: -0.355_4 * t + 0.427_5,
+                c: -0.022_7 * t + 5.325_1,
+                d: 0.120_6 * t - 2.577_1,
+                e: -0.067_0 * t + 0.370_3,
+            },
+            Perez {
+                a: -0.019_3 * t - 0.259_2,
+                b: -0.066_5 * t + 0.000_8,
+                c: -0.000_4 * t + 0.212_5,
+                d: -0.064_1 * t - 0.898_9,
+                e: -0.003_3 * t + 0.045_2,
+            },
+            Perez {
+                a: -0.016_7 * t - 0.260_8,
+                b: -0.095_0 * t + 0.009_2,
+                c: -0.007_9 * t + 0.210_2,
+                d: -0.044_1 * t - 1.653_7,
+                e: -0.010_9 * t + 0.052_9,
+            },
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance =
+            ((4.045_3 * t - 4.971_0) * chi.tan() - 0.215_5 * t + 2.419_2).max(0.0);
+        let zenith_x = t * t * polynomial(theta_s, [0.001_66, -0.003_75, 0.002_09, 0.0])
+            + t * polynomial(theta_s, [-0.029_03, 0.063_77, -0.032_02, 0.003_94])
+            + polynomial(theta_s, [0.116_93, -0.211_96, 0.060_52, 0.258_86]);
+        let zenith_y = t * t * polynomial(theta_s, [0.002_75, -0.006_10, 0.003_17, 0.0])
+            + t * polynomial(theta_s, [-0.042_14, 0.089_70, -0.041_53, 0.005_16])
+            + polynomial(theta_s, [0.153_46, -0.267_56, 0.066_70, 0.266_88]);
+
+        let sun_radiance = if sun_direction.y > 0.0 {
+            let theta_deg = theta_s.to_degrees();
+            let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_deg).powf(-1.253));
+            let attenuation = Vec3::new(
+                sun_transmittance(0.68, t, air_mass),
+                sun_transmittance(0.55, t, air_mass),
+                sun_transmittance(0.44, t, air_mass),
+            );
+            (SUN_LUMINANCE * intensity) * (attenuation * blackbody(5778.0))
+        } else {
+            Vec3::zeros()
+        };
+
+        let mut sky = Sky {
+            sun_direction,
+            intensity,
+            perez,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            sun_radiance,
+            cos_sun_radius: SUN_ANGULAR_RADIUS.cos(),
+            table: EnvironmentMap::new(1, 1, vec![Vec3::ones()], 0.0, 1.0),
+        };
+        sky.table = EnvironmentMap::from_fn(TABLE_WIDTH, TABLE_HEIGHT, |d| sky.sky_radiance(d));
+        sky
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    /// Radiance of the sky dome alone, without the sun disk. Directions below the horizon see
+    /// the horizon colour.
+    pub fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        let d = direction.make_unit_vector();
+        let cos_theta = d.y.max(0.01);
+        let cos_theta_s = self.sun_direction.y.max(0.0);
+        let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_s = cos_theta_s.acos();
+        let mut yxy = [0.0; 3];
+        for (i, value) in yxy.iter_mut().enumerate() {
+            let perez = self.perez[i];
+            *value = self.zenith[i] * perez.eval(cos_theta, gamma) / perez.eval(1.0, theta_s);
+        }
+        let [luminance, x, y] = yxy;
+        if luminance <= 0.0 || y <= 0.0 {
+            return Vec3::zeros();
+        }
+        let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_rgb(xyz);
+        self.intensity * Vec3::new(rgb.x.max(0.0), rgb.y.max(0.0), rgb.z.max(0.0))
+    }
+
+    fn sees_sun(&self, direction: Vec3) -> bool {
+        direction.make_unit_vector().dot(self.sun_direction) >= self.cos_sun_radius
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let sky = self.sky_radiance(direction);
+        if self.sees_sun(direction) {
+            sky + self.sun_radiance
+        } else {
+            sky
+        }
+    }
+
+    // The probability of sampling the sun disk rather than the sky dome, based on how much
+    // light each contributes.
+    fn sun_probability(&self) -> f32 {
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_sun_radius);
+        let sun_power = luminance(self.sun_radiance) * solid_angle;
+        let sky_power = self.table.power();
+        if sun_power + sky_power > 0.0 {
+            (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn sample(&self, rng: &mut Pcg32) -> Option<LightSample> {
+        let direction = if rng.gen::<f32>() < self.sun_probability() {
+            uniform_cone(self.sun_direction, self.cos_sun_radius, rng)
+        } else {
+            self.table.sample(rng)?.direction
+        };
+        let pdf = self.pdf(direction);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: f32::MAX,
+            radiance: self.radiance(direction),
+            pdf,
+        })
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f32 {
+        let sun_probability = self.sun_probability();
+        let sun_pdf = if self.sees_sun(direction) {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        };
+        sun_probability * sun_pdf + (1.0 - sun_probability) * self.table.pdf(direction)
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Direction towards the sun as seen from the given location on earth, with +y up, -z north and
+/// +x east. Latitude and longitude are in degrees, north and east positive, and the time of day
+/// is in hours UTC. Uses the NOAA low precision solar position equations.
+pub fn sun_position(
+    latitude: f32,
+    longitude: f32,
+    year: i32,
+    month: u32,
+    day: u32,
+    hour_utc: f32,
+) -> Vec3 {
+    const MONTH_DAYS: [u32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    let month = month.clamp(1, 12) as usize;
+    let mut day_of_year = MONTH_DAYS[..month - 1].iter().sum::<u32>() + day;
+    if month > 2 && is_leap_year(year) {
+        day_of_year += 1;
+    }
+    let days_in_year = if is_leap_year(year) { 366.0 } else { 365.0 };
+
+    // fractional year in radians
+    let g = 2.0 * PI / days_in_year * (day_of_year as f32 - 1.0 + (hour_utc - 12.0) / 24.0);
+    let equation_of_time = 229.18
+        * (0.000_075 + 0.001_868 * g.cos()
+            - 0.032_077 * g.sin()
+            - 0.014_615 * (2.0 * g).cos()
+            - 0.040_849 * (2.0 * g).sin());
+    let declination = 0.006_918 - 0.399_912 * g.cos() + 0.070_257 * g.sin()
+        - 0.006_758 * (2.0 * g).cos()
+        + 0.000_907 * (2.0 * g).sin()
+        - 0.002_697 * (3.0 * g).cos()
+        + 0.001_48 * (3.0 * g).sin();
+    // true solar time in minutes, and the hour angle which is positive in the afternoon
+    let solar_time = hour_utc * 60.0 + equation_of_time + 4.0 * longitude;
+    let hour_angle = (solar_time / 4.0 - 180.0).to_radians();
+
+    let phi = latitude.to_radians();
+    let east = -declination.cos() * hour_angle.sin();
+    let north = phi.cos() * declination.sin() - phi.sin() * declination.cos() * hour_angle.cos();
+    let up = phi.sin() * declination.sin() + phi.cos() * declination.cos() * hour_angle.cos();
+    Vec3::new(east, up, -north)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{sun_position, Sky};
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn sun_position_equinox() {
+        // at the equator around the march equinox the sun is overhead at local noon, rises in
+        // the east and sets in the west
+        let noon = sun_position(0.0, 0.0, 2021, 3, 20, 12.0);
+        assert!(noon.y > 0.99);
+        let morning = sun_position(0.0, 0.0, 2021, 3, 20, 7.0);
+        assert!(morning.x > 0.9 && morning.y > 0.0);
+        let evening = sun_position(0.0, 90.0, 2021, 3, 20, 12.0);
+        assert!(evening.x < -0.99);
+        // in the northern hemisphere the midday sun is to the south
+        let helsinki = sun_position(60.17, 24.94, 2021, 6, 21, 10.3);
+        // at a solar elevation of 90 - 60.17 + 23.44 degrees
+        assert!(helsinki.z > 0.0 && (helsinki.y - 0.80).abs() < 0.01);
+    }
+
+    #[test]
+    fn sky_is_blue_and_brightest_near_sun() {
+        let sky = Sky::new(Vec3::new(0.0, 0.5, -1.0), 2.5, 1.0);
+        let zenith = sky.sky_radiance(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x);
+        let near_sun = sky.sky_radiance(Vec3::new(0.0, 0.55, -1.0));
+        let away = sky.sky_radiance(Vec3::new(0.0, 0.5, 1.0));
+        assert!(near_sun.y > away.y);
+        assert!(sky.radiance(sky.sun_direction()).y > 1000.0 * near_sun.y);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{
+    camera::{Camera, CameraModel},
+    panoramic::EquirectangularCamera,
+    ray::Ray,
+    sampling::Pcg32,
+};
+
+/// How the two eyes of a stereo pair share one image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// the left eye in the left half, the right eye in the right half
+    SideBySide,
+    /// the left eye in the top half, the right eye in the bottom half
+    TopBottom,
+}
+
+impl StereoLayout {
+    /// The aspect ratio of one eye's view in an image with the given aspect ratio.
+    pub fn eye_aspect(self, aspect: f32) -> f32 {
+        match self {
+            StereoLayout::SideBySide => 0.5 * aspect,
+            StereoLayout::TopBottom => 2.0 * aspect,
+        }
+    }
+}
+
+/// Renders the views of two cameras into one image, so that a single call to `render` produces
+/// a stereo pair.
+#[derive(Copy, Clone)]
+pub struct StereoCamera<C> {
+    left: C,
+    right: C,
+    layout: StereoLayout,
+}
+
+impl<C: CameraModel> StereoCamera<C> {
+    pub fn new(left: C, right: C, layout: StereoLayout) -> StereoCamera<C> {
+        StereoCamera {
+            left,
+            right,
+            layout,
+        }
+    }
+}
+
+impl StereoCamera<Camera> {
+    /// Off-axis stereo around `camera`, with the eyes `interocular` apart. Objects at
+    /// `convergence` distance appear at screen depth, nearer ones in front of the screen. The
+    /// camera's aspect ratio is that of one eye, see `StereoLayout::eye_aspect`.
+    pub fn off_axis(
+        camera: Camera,
+        interocular: f32,
+        convergence: f32,
+        layout: StereoLayout,
+    ) -> StereoCamera<Camera> {
+        let half = 0.5 * interocular;
+        StereoCamera::new(
+            camera.clone().with_eye_offset(-half, convergence),
+            camera.with_eye_offset(half, convergence),
+            layout,
+        )
+    }
+}
+
+impl StereoCamera<EquirectangularCamera> {
+    /// Omni-directional stereo around `camera`, with the eyes `interocular` apart in every
+    /// direction. Use `StereoLayout::TopBottom` to keep each eye twice as wide as high.
+    pub fn omnidirectional(
+        camera: EquirectangularCamera,
+        interocular: f32,
+        layout: StereoLayout,
+    ) -> StereoCamera<EquirectangularCamera> {
+        let half = 0.5 * interocular;
+        StereoCamera::new(
+            camera.with_eye_offset(-half),
+            camera.with_eye_offset(half),
+            layout,
+        )
+    }
+}
+
+impl<C: CameraModel> StereoCamera<C> {
+    // The eye which sees image position (u, v), and the position within its own view.
+    fn eye(&self, u: f32, v: f32) -> (&C, f32, f32) {
+        match self.layout {
+            StereoLayout::SideBySide if u < 0.5 => (&self.left, 2.0 * u, v),
+            StereoLayout::SideBySide => (&self.right, 2.0 * u - 1.0, v),
+            StereoLayout::TopBottom if v >= 0.5 => (&self.left, u, 2.0 * v - 1.0),
+            StereoLayout::TopBottom => (&self.right, u, 2.0 * v),
+        }
+    }
+}
+
+impl<C: CameraModel> CameraModel for StereoCamera<C> {
+    fn make_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<Ray> {
+        let (eye, u, v) = self.eye(u, v);
+        eye.make_ray(rng, u, v)
+    }
+
+    fn make_weighted_ray(&self, rng: &mut Pcg32, u: f32, v: f32) -> Option<(Ray, f32)> {
+        let (eye, u, v) = self.eye(u, v);
+        eye.make_weighted_ray(rng, u, v)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StereoCamera, StereoLayout};
+    use crate::{
+        camera::{Camera, CameraModel},
+        panoramic::EquirectangularCamera,
+        ray::Ray,
+        sampling::Pcg32,
+        vec3::Vec3,
+    };
+
+    #[test]
+    fn off_axis_eyes_converge() {
+        let camera = Camera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            1.0,
+            0.0,
+            2.0,
+        );
+        let stereo = StereoCamera::off_axis(camera, 0.1, 5.0, StereoLayout::SideBySide);
+        let mut rng = Pcg32::new(0, 0);
+        let left = stereo.make_ray(&mut rng, 0.25, 0.5).unwrap();
+        let right = stereo.make_ray(&mut rng, 0.75, 0.5).unwrap();
+        assert!((left.origin - Vec3::new(-0.05, 0.0, 0.0)).length() < 1e-6);
+        assert!((right.origin - Vec3::new(0.05, 0.0, 0.0)).length() < 1e-6);
+        // the centres of both views meet at the convergence distance
+        let meet = |r: Ray| r.point_at_parameter(-5.0 / r.direction.z);
+        assert!((meet(left) - Vec3::new(0.0, 0.0, -5.0)).length() < 1e-5);
+        assert!((meet(right) - Vec3::new(0.0, 0.0, -5.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn omnidirectional_eyes_circle_the_centre() {
+        let camera = EquirectangularCamera::new(
+            Vec3::zeros(),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let stereo = StereoCamera::omnidirectional(camera, 0.1, StereoLayout::TopBottom);
+        let mut rng = Pcg32::new(0, 0);
+        for &u in &[0.1, 0.5, 0.8] {
+            let left = stereo.make_ray(&mut rng, u, 0.75).unwrap();
+            let right = stereo.make_ray(&mut rng, u, 0.25).unwrap();
+            // the same direction, seen from opposite sides of the circle
+            assert!((left.direction - right.direction).length() < 1e-6);
+            assert!((left.origin + right.origin).length() < 1e-6);
+            assert!((left.origin.length() - 0.05).abs() < 1e-6);
+            assert!(left.origin.dot(left.direction).abs() < 1e-6);
+            // the right eye is on the right of the view
+            let right_of_view = left.direction.cross(Vec3::new(0.0, 1.0, 0.0));
+            assert!(right.origin.dot(right_of_view) > 0.0);
+        }
+    }
+}
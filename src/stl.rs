@@ -0,0 +1,132 @@
+use crate::{mesh::TriangleMesh, vec3::Vec3};
+use std::{
+    fs::File,
+    io::{self, Error, ErrorKind, Read},
+    path::Path,
+};
+
+// Reader for STL files, in the binary and the ASCII variant. STL stores unconnected triangles,
+// so every triangle gets its own three vertices, and the facet normals are ignored in favour
+// of the winding order.
+
+const HEADER_SIZE: usize = 80;
+const TRIANGLE_SIZE: usize = 50;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn load(path: &Path) -> io::Result<TriangleMesh> {
+    read(File::open(path)?)
+}
+
+pub fn read<R: Read>(mut reader: R) -> io::Result<TriangleMesh> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    // binary files may also start with "solid", so trust the size implied by the count first
+    if data.len() >= HEADER_SIZE + 4 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == HEADER_SIZE + 4 + count * TRIANGLE_SIZE {
+            return Ok(read_binary(&data[HEADER_SIZE + 4..], count));
+        }
+    }
+    if data.starts_with(b"solid") {
+        let text = std::str::from_utf8(&data).map_err(|_| invalid_data("ASCII STL is not text"))?;
+        read_ascii(text)
+    } else {
+        Err(invalid_data("not an STL file"))
+    }
+}
+
+fn read_binary(data: &[u8], count: usize) -> TriangleMesh {
+    let mut mesh = TriangleMesh::default();
+    let float = |offset: usize| {
+        f32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    };
+    for triangle in 0..count {
+        // skip the facet normal
+        let start = triangle * TRIANGLE_SIZE + 12;
+        let index = mesh.positions.len() as u32;
+        for vertex in 0..3 {
+            let offset = start + 12 * vertex;
+            mesh.positions.push(Vec3::new(
+                float(offset),
+                float(offset + 4),
+                float(offset + 8),
+            ));
+        }
+        mesh.triangles.push([index, index + 1, index + 2]);
+    }
+    mesh
+}
+
+fn read_ascii(text: &str) -> io::Result<TriangleMesh> {
+    let mut mesh = TriangleMesh::default();
+    let mut vertices = Vec::new();
+    for line in text.lines() {
+        let mut words = line.split_ascii_whitespace();
+        if words.next() != Some("vertex") {
+            continue;
+        }
+        let mut next = || -> io::Result<f32> {
+            let token = words
+                .next()
+                .ok_or_else(|| invalid_data("STL vertex needs three coordinates"))?;
+            token
+                .parse()
+                .map_err(|_| invalid_data(&format!("bad STL coordinate '{token}'")))
+        };
+        vertices.push(Vec3::new(next()?, next()?, next()?));
+    }
+    if vertices.len() % 3 != 0 {
+        return Err(invalid_data("STL facet without three vertices"));
+    }
+    for (i, chunk) in vertices.chunks(3).enumerate() {
+        let index = 3 * i as u32;
+        mesh.positions.extend_from_slice(chunk);
+        mesh.triangles.push([index, index + 1, index + 2]);
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod test {
+    use super::read;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn ascii_and_binary() {
+        let ascii = "solid test
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 1 0
+  endloop
+endfacet
+endsolid test
+";
+        let mesh = read(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.positions[2], Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+
+        // a binary header which starts like an ASCII file
+        let mut binary = b"solid but binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend_from_slice(&1u32.to_le_bytes());
+        for x in [
+            0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 2.0, 0.0,
+        ] {
+            binary.extend_from_slice(&x.to_le_bytes());
+        }
+        binary.extend_from_slice(&[0, 0]);
+        let mesh = read(binary.as_slice()).unwrap();
+        assert_eq!(mesh.positions[1], Vec3::new(2.0, 0.0, 0.0));
+        assert_eq!(mesh.triangles.len(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::vec3::Vec3;
+
+/// Converts an 8 bit sRGB encoded channel to linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// An image mapped onto surfaces by their (u, v) coordinates. (0, 0) is the top left corner of
+/// the image, as in glTF, and the image repeats outside of [0, 1]. Texels are stored linear.
+#[derive(Clone, Debug)]
+pub struct Texture {
+    width: usize,
+    height: usize,
+    texels: Vec<Vec3>,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, texels: Vec<Vec3>) -> Texture {
+        assert_eq!(texels.len(), width * height, "wrong texture size");
+        assert!(width > 0 && height > 0, "empty texture");
+        Texture {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    fn texel(&self, x: usize, y: usize) -> Vec3 {
+        self.texels[(y % self.height) * self.width + x % self.width]
+    }
+
+    /// Bilinearly filtered lookup.
+    pub fn sample(&self, u: f32, v: f32) -> Vec3 {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let x = (u * width - 0.5).rem_euclid(width);
+        let y = (v * height - 0.5).rem_euclid(height);
+        let (fx, fy) = (x.fract(), y.fract());
+        let (x0, y0) = (x as usize, y as usize);
+        let top = (1.0 - fx) * self.texel(x0, y0) + fx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * self.texel(x0, y0 + 1) + fx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Texture;
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn filters_and_repeats() {
+        let black = Vec3::zeros();
+        let white = Vec3::ones();
+        let texture = Texture::new(2, 1, vec![black, white]);
+        assert_eq!(texture.sample(0.25, 0.5), black);
+        assert_eq!(texture.sample(1.75, 0.5), white);
+        // halfway between the texel centres
+        assert!((texture.sample(0.5, 0.5).x - 0.5).abs() < 1e-6);
+        // wrapping around the right edge blends back into the first texel
+        assert!((texture.sample(1.0, 0.5).x - 0.5).abs() < 1e-6);
+    }
+}
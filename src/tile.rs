@@ -0,0 +1,145 @@
+/// A rectangle of pixels, counting rows from the top of the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// The part of this region inside `other`, which may be empty.
+    pub fn intersect(&self, other: Region) -> Region {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Region::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+/// The order in which tiles are rendered.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TileOrder {
+    /// row by row from the top left
+    Scanline,
+    /// outwards from the centre, where the subject usually is
+    Spiral,
+    /// along a Hilbert curve, which keeps consecutive tiles close together
+    Hilbert,
+}
+
+// The distance of (x, y) along a Hilbert curve filling a square of side `n`, a power of two.
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = usize::from(x & s > 0);
+        let ry = usize::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        // turn the quadrant so that the curve continues from the last one
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// Splits `region` into tiles of at most `tile_size` pixels square, in the given order. Tiles
+/// at the right and bottom edges may be smaller.
+pub fn tiles(region: Region, tile_size: usize, order: TileOrder) -> Vec<Region> {
+    let size = tile_size.max(1);
+    let columns = region.width.div_ceil(size);
+    let rows = region.height.div_ceil(size);
+    let mut cells: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // ring by ring around the centre, clockwise from the left within each ring
+            let key = |&(column, row): &(usize, usize)| {
+                let dx = column as f32 + 0.5 - 0.5 * columns as f32;
+                let dy = row as f32 + 0.5 - 0.5 * rows as f32;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| {
+                let (a, b) = (key(a), key(b));
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+            });
+        }
+        TileOrder::Hilbert => {
+            let n = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(n, column, row));
+        }
+    }
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let tile = Region::new(region.x + column * size, region.y + row * size, size, size);
+            tile.intersect(region)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tiles, Region, TileOrder};
+
+    #[test]
+    fn tiles_cover_the_region_once() {
+        let region = Region::new(3, 5, 70, 45);
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = tiles(region, 16, order);
+            assert_eq!(tiles.len(), 5 * 3);
+            let area: usize = tiles.iter().map(|t| t.width * t.height).sum();
+            assert_eq!(area, 70 * 45);
+            for y in 0..60 {
+                for x in 0..80 {
+                    let covering = tiles.iter().filter(|t| t.contains(x, y)).count();
+                    assert_eq!(covering, usize::from(region.contains(x, y)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tile_orders() {
+        let region = Region::new(0, 0, 80, 80);
+        let scanline = tiles(region, 16, TileOrder::Scanline);
+        assert_eq!(scanline[1], Region::new(16, 0, 16, 16));
+
+        let spiral = tiles(region, 16, TileOrder::Spiral);
+        assert_eq!(spiral[0], Region::new(32, 32, 16, 16));
+        // the ring around the centre tile comes next
+        assert!(spiral[1..9]
+            .iter()
+            .all(|t| t.x.abs_diff(32) <= 16 && t.y.abs_diff(32) <= 16));
+
+        // consecutive tiles along the Hilbert curve share an edge
+        let hilbert = tiles(Region::new(0, 0, 64, 64), 16, TileOrder::Hilbert);
+        assert_eq!(hilbert[0], Region::new(0, 0, 16, 16));
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(distance, 16);
+        }
+    }
+}
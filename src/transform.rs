@@ -0,0 +1,602 @@
+use crate::{aabb::Aabb, vec3::Vec3};
+use std::ops::Mul;
+
+/// A 4x4 matrix, stored by rows. Points are treated as column vectors, so `a * b` applies `b`
+/// first.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4::new(m)
+    }
+
+    /// Inverts the matrix by Gauss-Jordan elimination with partial pivoting. None if the matrix
+    /// is singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap_or(col);
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[col][j];
+                    inv[row][j] -= factor * inv[col][j];
+                }
+            }
+        }
+        Some(Mat4::new(inv))
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            (1.0 / w) * Vec3::new(x, y, z)
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4::new(m)
+    }
+}
+
+/// An affine transform together with its inverse, which is needed to bring rays into object space
+/// and to transform normals.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+    // the inverse transpose, which transforms normals
+    normal_matrix: Mat4,
+}
+
+impl Transform {
+    /// Panics if the matrix can't be inverted, e.g. when scaling by zero.
+    pub fn new(matrix: Mat4) -> Transform {
+        let inverse = matrix.inverse().expect("transform matrix is singular");
+        Transform::from_matrices(matrix, inverse)
+    }
+
+    /// A transform from a matrix and its inverse, which the caller has already worked out.
+    pub(crate) fn from_matrices(matrix: Mat4, inverse: Mat4) -> Transform {
+        Transform {
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+        }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::from_matrices(Mat4::identity(), Mat4::identity())
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        let mut matrix = Mat4::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        let mut inverse = Mat4::identity();
+        inverse.m[0][3] = -offset.x;
+        inverse.m[1][3] = -offset.y;
+        inverse.m[2][3] = -offset.z;
+        Transform::from_matrices(matrix, inverse)
+    }
+
+    pub fn scale(factors: Vec3) -> Transform {
+        let mut matrix = Mat4::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        Transform::new(matrix)
+    }
+
+    pub fn uniform_scale(factor: f32) -> Transform {
+        Transform::scale(Vec3::new(factor, factor, factor))
+    }
+
+    /// A counterclockwise rotation by `degrees` around `axis`, looking down the axis.
+    pub fn rotate(axis: Vec3, degrees: f32) -> Transform {
+        let a = axis.make_unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let matrix = Mat4::new([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                0.0,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                0.0,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // rotations are orthogonal
+        Transform::from_matrices(matrix, matrix.transpose())
+    }
+
+    pub fn inverted(&self) -> Transform {
+        Transform::from_matrices(self.inverse, self.matrix)
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals transform with the inverse transpose, which keeps them perpendicular to surfaces
+    /// under non-uniform scaling. The result is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.normal_matrix.transform_vector(n)
+    }
+
+    /// The box bounding all eight transformed corners of `bounds`.
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        transformed_bounds(&self.matrix, bounds)
+    }
+}
+
+// The eight corners of a box.
+fn corners(bounds: &Aabb) -> [Vec3; 8] {
+    let pick = |i: usize, bit: usize, min: f32, max: f32| if i & bit == 0 { min } else { max };
+    let mut corners = [Vec3::zeros(); 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        *corner = Vec3::new(
+            pick(i, 1, bounds.min.x, bounds.max.x),
+            pick(i, 2, bounds.min.y, bounds.max.y),
+            pick(i, 4, bounds.min.z, bounds.max.z),
+        );
+    }
+    corners
+}
+
+// The box bounding the corners of `bounds` transformed by `matrix`.
+fn transformed_bounds(matrix: &Mat4, bounds: &Aabb) -> Aabb {
+    let corners = corners(bounds);
+    let first = matrix.transform_point(corners[0]);
+    corners[1..]
+        .iter()
+        .fold(Aabb::new(first, first), |acc, &c| {
+            let p = matrix.transform_point(c);
+            acc.union(&Aabb::new(p, p))
+        })
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    // `a * b` applies `b` first, then `a`.
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform::from_matrices(self.matrix * rhs.matrix, rhs.inverse * self.inverse)
+    }
+}
+
+/// A unit quaternion representing a rotation, used to interpolate between keyframes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quaternion {
+    pub v: Vec3,
+    pub w: f32,
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            v: Vec3::zeros(),
+            w: 1.0,
+        }
+    }
+
+    /// A counterclockwise rotation by `degrees` around `axis`, like `Transform::rotate`.
+    pub fn rotation(axis: Vec3, degrees: f32) -> Quaternion {
+        let (sin, cos) = (0.5 * degrees.to_radians()).sin_cos();
+        Quaternion {
+            v: sin * axis.make_unit_vector(),
+            w: cos,
+        }
+    }
+
+    fn dot(self, other: Quaternion) -> f32 {
+        self.v.dot(other.v) + self.w * other.w
+    }
+
+    fn scaled(self, s: f32) -> Quaternion {
+        Quaternion {
+            v: s * self.v,
+            w: s * self.w,
+        }
+    }
+
+    fn add(self, other: Quaternion) -> Quaternion {
+        Quaternion {
+            v: self.v + other.v,
+            w: self.w + other.w,
+        }
+    }
+
+    fn normalized(self) -> Quaternion {
+        self.scaled(1.0 / self.dot(self).sqrt())
+    }
+
+    /// Spherical linear interpolation along the shorter arc.
+    pub fn slerp(self, other: Quaternion, t: f32) -> Quaternion {
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            other.scaled(-1.0)
+        } else {
+            other
+        };
+        if cos_theta > 0.9995 {
+            // nearly parallel, where linear interpolation is accurate and stable
+            return self.scaled(1.0 - t).add(other.scaled(t)).normalized();
+        }
+        let theta = cos_theta.clamp(-1.0, 1.0).acos();
+        let sin_theta = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin_theta;
+        let b = (t * theta).sin() / sin_theta;
+        self.scaled(a).add(other.scaled(b)).normalized()
+    }
+
+    pub fn to_transform(self) -> Transform {
+        let matrix = self.to_matrix();
+        Transform::from_matrices(matrix, matrix.transpose())
+    }
+
+    fn to_matrix(self) -> Mat4 {
+        let Vec3 { x, y, z } = self.v;
+        let w = self.w;
+        Mat4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+/// A pose at an instant in time: scaling, then rotation, then translation. A scale of zero along
+/// any axis flattens the object out of sight.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, translation: Vec3, rotation: Quaternion, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    // The matrix of the pose, which is singular for a zero scale.
+    fn matrix(&self) -> Mat4 {
+        let mut m = self.rotation.to_matrix().m;
+        let (s, t) = (self.scale, self.translation);
+        for (i, row) in m.iter_mut().take(3).enumerate() {
+            row[0] *= s.x;
+            row[1] *= s.y;
+            row[2] *= s.z;
+            row[3] = t[i];
+        }
+        Mat4::new(m)
+    }
+
+    /// The transform of the pose, None if it has a zero scale and so no inverse. The inverse is
+    /// put together from the parts rather than by inverting the matrix.
+    pub fn transform(&self) -> Option<Transform> {
+        let (s, t) = (self.scale, self.translation);
+        if s.x == 0.0 || s.y == 0.0 || s.z == 0.0 {
+            return None;
+        }
+        // S^-1 R^T T^-1
+        let rotation = self.rotation.to_matrix().m;
+        let inv_scale = [1.0 / s.x, 1.0 / s.y, 1.0 / s.z];
+        let mut inverse = Mat4::identity().m;
+        for (i, row) in inverse.iter_mut().take(3).enumerate() {
+            for (j, value) in row.iter_mut().take(3).enumerate() {
+                *value = inv_scale[i] * rotation[j][i];
+            }
+            row[3] = -(row[0] * t.x + row[1] * t.y + row[2] * t.z);
+        }
+        Some(Transform::from_matrices(self.matrix(), Mat4::new(inverse)))
+    }
+}
+
+// Number of steps per keyframe interval when bounding the motion.
+const MOTION_BOUND_STEPS: usize = 32;
+
+/// A transform which moves over time by interpolating between keyframes: linearly for
+/// translation and scale, and by slerp for rotation. Two keyframes give linear motion. Before the
+/// first and after the last keyframe the transform holds still.
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    /// Panics if there are no keyframes.
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(
+            !keyframes.is_empty(),
+            "an animated transform needs keyframes"
+        );
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { keyframes }
+    }
+
+    /// The pose at `time`.
+    pub fn pose(&self, time: f32) -> Keyframe {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return Keyframe { time, ..*first };
+        }
+        if time >= last.time {
+            return Keyframe { time, ..*last };
+        }
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+        Keyframe::new(
+            time,
+            a.translation + t * (b.translation - a.translation),
+            a.rotation.slerp(b.rotation, t),
+            a.scale + t * (b.scale - a.scale),
+        )
+    }
+
+    /// The transform at `time`, None at instants where the scale is zero.
+    pub fn at(&self, time: f32) -> Option<Transform> {
+        self.pose(time).transform()
+    }
+
+    /// A box bounding `bounds` over the whole motion. Each keyframe interval is stepped through,
+    /// and the result is padded by the largest distance a corner moves in one step, which covers
+    /// the curved paths of rotating corners between the steps.
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let corners = corners(bounds);
+        let matrix = |time: f32| self.pose(time).matrix();
+        let mut result = transformed_bounds(&matrix(self.keyframes[0].time), bounds);
+        let mut padding = 0.0f32;
+        for pair in self.keyframes.windows(2) {
+            let (t0, t1) = (pair[0].time, pair[1].time);
+            let mut previous = matrix(t0);
+            for step in 1..=MOTION_BOUND_STEPS {
+                let current = matrix(t0 + (t1 - t0) * step as f32 / MOTION_BOUND_STEPS as f32);
+                for &corner in &corners {
+                    let moved = (current.transform_point(corner)
+                        - previous.transform_point(corner))
+                    .length();
+                    padding = padding.max(moved);
+                }
+                result = result.union(&transformed_bounds(&current, bounds));
+                previous = current;
+            }
+        }
+        let pad = Vec3::new(padding, padding, padding);
+        Aabb::new(result.min - pad, result.max + pad)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AnimatedTransform, Keyframe, Quaternion, Transform};
+    use crate::{aabb::Aabb, vec3::Vec3};
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5);
+    }
+
+    #[test]
+    fn inverse_roundtrip() {
+        let t = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Transform::scale(Vec3::new(2.0, 0.5, 3.0));
+        let inverse = t.matrix.inverse().unwrap();
+        let p = Vec3::new(0.3, -0.7, 1.1);
+        assert_close(inverse.transform_point(t.point(p)), p);
+        assert_close(t.inverse.transform_point(t.point(p)), p);
+        assert_close(
+            Transform::rotate(Vec3::new(0.0, 1.0, 0.0), 90.0).point(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+    }
+
+    #[test]
+    fn keyframe_interpolation() {
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(
+                1.0,
+                Vec3::new(2.0, 0.0, 0.0),
+                Quaternion::rotation(Vec3::new(0.0, 1.0, 0.0), 90.0),
+                Vec3::ones(),
+            ),
+            Keyframe::new(0.0, Vec3::zeros(), Quaternion::identity(), Vec3::ones()),
+        ]);
+        let p = Vec3::new(1.0, 0.0, 0.0);
+        assert_close(motion.at(-1.0).unwrap().point(p), p);
+        assert_close(motion.at(2.0).unwrap().point(p), Vec3::new(2.0, 0.0, -1.0));
+        let half = 0.5f32.sqrt();
+        assert_close(
+            motion.at(0.5).unwrap().point(p),
+            Vec3::new(1.0 + half, 0.0, -half),
+        );
+        let rotate = Quaternion::rotation(Vec3::new(1.0, 2.0, 3.0), 40.0).to_transform();
+        let q = Vec3::new(0.2, 0.5, -0.4);
+        assert_close(
+            rotate.point(q),
+            Transform::rotate(Vec3::new(1.0, 2.0, 3.0), 40.0).point(q),
+        );
+    }
+
+    #[test]
+    fn keyframe_inverses() {
+        let key = Keyframe::new(
+            0.0,
+            Vec3::new(1.0, -2.0, 3.0),
+            Quaternion::rotation(Vec3::new(1.0, 2.0, -1.0), 70.0),
+            Vec3::new(2.0, 0.5, -3.0),
+        );
+        let t = key.transform().unwrap();
+        let composed = Transform::translate(key.translation)
+            * key.rotation.to_transform()
+            * Transform::scale(key.scale);
+        let inverse = t.matrix.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((t.matrix.m[i][j] - composed.matrix.m[i][j]).abs() < 1e-5);
+                assert!((t.inverse.m[i][j] - inverse.m[i][j]).abs() < 1e-5);
+            }
+        }
+
+        // shrinking to nothing and back out through a mirror image
+        let flat = Keyframe::new(
+            1.0,
+            Vec3::zeros(),
+            Quaternion::identity(),
+            Vec3::new(1.0, 0.0, 1.0),
+        );
+        assert!(flat.transform().is_none());
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(0.0, Vec3::zeros(), Quaternion::identity(), Vec3::ones()),
+            Keyframe::new(2.0, Vec3::zeros(), Quaternion::identity(), -Vec3::ones()),
+        ]);
+        assert!(motion.at(1.0).is_none());
+        assert_close(
+            motion.at(1.5).unwrap().point(Vec3::ones()),
+            -0.5 * Vec3::ones(),
+        );
+        let bounds = motion.bounds(&Aabb::new(Vec3::zeros(), Vec3::ones()));
+        assert!(bounds.contains(-Vec3::ones()) && bounds.contains(Vec3::ones()));
+    }
+
+    #[test]
+    fn motion_bounds_cover_the_path() {
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(0.0, Vec3::zeros(), Quaternion::identity(), Vec3::ones()),
+            Keyframe::new(
+                1.0,
+                Vec3::zeros(),
+                Quaternion::rotation(Vec3::new(0.0, 1.0, 0.0), 180.0),
+                Vec3::ones(),
+            ),
+        ]);
+        let bounds = motion.bounds(&Aabb::new(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        ));
+        for i in 0..=100 {
+            assert!(bounds.contains(
+                motion
+                    .at(i as f32 / 100.0)
+                    .unwrap()
+                    .point(Vec3::new(1.0, 0.0, 0.0))
+            ));
+        }
+    }
+
+    #[test]
+    fn normals_under_non_uniform_scale() {
+        let t = Transform::rotate(Vec3::new(0.0, 0.0, 1.0), 20.0)
+            * Transform::scale(Vec3::new(4.0, 1.0, 1.0));
+        // a tangent and the normal of the plane x + y = 0
+        let tangent = t.vector(Vec3::new(1.0, -1.0, 0.0));
+        let n = t.normal(Vec3::new(1.0, 1.0, 0.0));
+        assert!(tangent.dot(n).abs() < 1e-5);
+    }
+}
@@ -1,5 +1,6 @@
+use crate::sampling::Pcg32;
 use rand::prelude::*;
-use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+use std::ops::{Add, AddAssign, Index, Mul, Neg, Sub};
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vec3 {
@@ -61,9 +62,48 @@ impl Vec3 {
     pub fn reflect(&self, n: Vec3) -> Vec3 {
         *self - 2.0 * self.dot(n) * n
     }
+
+    /// The index of the largest component.
+    pub fn largest_axis(&self) -> usize {
+        if self.x >= self.y && self.x >= self.z {
+            0
+        } else if self.y >= self.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn exp(&self) -> Vec3 {
+        Vec3 {
+            x: self.x.exp(),
+            y: self.y.exp(),
+            z: self.z.exp(),
+        }
+    }
+
+    pub fn ln(&self) -> Vec3 {
+        Vec3 {
+            x: self.x.ln(),
+            y: self.y.ln(),
+            z: self.z.ln(),
+        }
+    }
 }
 
-pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
+/// Builds two unit vectors which together with the unit vector `w` form an orthonormal basis.
+pub fn coordinate_system(w: Vec3) -> (Vec3, Vec3) {
+    let a = if w.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let v = w.cross(a).make_unit_vector();
+    let u = w.cross(v);
+    (u, v)
+}
+
+pub fn random_in_unit_sphere(rng: &mut Pcg32) -> Vec3 {
     loop {
         // TODO: since we're using random() in a loop, caching rng should
         // increase performace
@@ -75,7 +115,14 @@ pub fn random_in_unit_sphere(rng: &mut ThreadRng) -> Vec3 {
     }
 }
 
-pub fn random_in_unit_disk(rng: &mut ThreadRng) -> Vec3 {
+pub fn random_unit_vector(rng: &mut Pcg32) -> Vec3 {
+    let z = 2.0 * rng.gen::<f32>() - 1.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * rng.gen::<f32>();
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn random_in_unit_disk(rng: &mut Pcg32) -> Vec3 {
     loop {
         let p = Vec3::new(
             2.0 * rng.gen::<f32>() - 1.0,
@@ -108,6 +155,19 @@ impl AddAssign for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f32;
+
+    fn index(&self, i: usize) -> &f32 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range"),
+        }
+    }
+}
+
 impl Mul<Vec3> for f32 {
     type Output = Vec3;
 
@@ -288,6 +348,13 @@ mod test {
         assert_eq!(lhs * rhs, Vec3::new(2.0, 3.0, 4.0));
     }
 
+    #[test]
+    fn exp_ln() {
+        let v = Vec3::new(0.25, 0.5, 1.0);
+        assert_eq!(Vec3::zeros().exp(), Vec3::ones());
+        assert!((v.ln().exp() - v).length() < 1e-6);
+    }
+
     #[test]
     fn neg() {
         assert_eq!(
@@ -0,0 +1,320 @@
+use crate::{aabb::Aabb, vec3::Vec3};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+};
+
+/// A dense voxel grid, e.g. exported from a fluid simulation. Voxel values are sampled at cell
+/// centres and stored with x varying fastest. Density is always present, temperature (in Kelvin)
+/// and emission are optional.
+#[derive(Clone)]
+pub struct VoxelGrid {
+    pub bounds: Aabb,
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub density: Vec<f32>,
+    pub temperature: Option<Vec<f32>>,
+    pub emission: Option<Vec<f32>>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads `count` values, growing the buffer with the data actually there rather than trusting
+// the count up front.
+fn read_floats<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<f32>> {
+    let size = count
+        .checked_mul(4)
+        .ok_or_else(|| invalid_data("voxel grid is too large"))?;
+    let mut bytes = Vec::new();
+    reader.take(size as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != size {
+        return Err(invalid_data("voxel data is cut short"));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn write_floats<W: Write>(writer: &mut W, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn parse_numbers<T: std::str::FromStr>(
+    line: &str,
+    keyword: &str,
+    count: usize,
+) -> io::Result<Vec<T>> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some(keyword) {
+        return Err(invalid_data(&format!("expected '{keyword}'")));
+    }
+    let numbers = tokens
+        .map(|t| {
+            t.parse::<T>()
+                .map_err(|_| invalid_data(&format!("bad number in '{line}'")))
+        })
+        .collect::<io::Result<Vec<T>>>()?;
+    if numbers.len() == count {
+        Ok(numbers)
+    } else {
+        Err(invalid_data(&format!(
+            "expected {count} values after '{keyword}'"
+        )))
+    }
+}
+
+impl VoxelGrid {
+    pub fn new(bounds: Aabb, nx: usize, ny: usize, nz: usize, density: Vec<f32>) -> VoxelGrid {
+        assert_eq!(
+            density.len(),
+            nx * ny * nz,
+            "density has the wrong voxel count"
+        );
+        VoxelGrid {
+            bounds,
+            nx,
+            ny,
+            nz,
+            density,
+            temperature: None,
+            emission: None,
+        }
+    }
+
+    /// Loads a headerless file of little-endian f32 densities.
+    pub fn load_raw(
+        path: &Path,
+        bounds: Aabb,
+        nx: usize,
+        ny: usize,
+        nz: usize,
+    ) -> io::Result<VoxelGrid> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|n| n.checked_mul(nz))
+            .ok_or_else(|| invalid_data("voxel grid is too large"))?;
+        let density = read_floats(&mut reader, count)?;
+        Ok(VoxelGrid::new(bounds, nx, ny, nz, density))
+    }
+
+    /// Loads a dense grid file. The file starts with a text header
+    ///
+    /// ```text
+    /// VOXELGRID 1
+    /// dims 64 64 64
+    /// bounds -1 0 -1 1 2 1
+    /// channels density temperature emission
+    /// end
+    /// ```
+    ///
+    /// followed by each listed channel in turn as little-endian f32 voxel values.
+    pub fn load(path: &Path) -> io::Result<VoxelGrid> {
+        VoxelGrid::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<VoxelGrid> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of voxel grid header"));
+            }
+            let line = line.trim().to_string();
+            if line == "end" {
+                break;
+            }
+            lines.push(line);
+        }
+        if lines.len() != 4 || lines[0] != "VOXELGRID 1" {
+            return Err(invalid_data("not a version 1 voxel grid file"));
+        }
+        let dims = parse_numbers::<usize>(&lines[1], "dims", 3)?;
+        let b = parse_numbers::<f32>(&lines[2], "bounds", 6)?;
+        let bounds = Aabb::new(Vec3::new(b[0], b[1], b[2]), Vec3::new(b[3], b[4], b[5]));
+        if dims.contains(&0) {
+            return Err(invalid_data("voxel grid has no voxels"));
+        }
+        let count = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]))
+            .ok_or_else(|| invalid_data("voxel grid is too large"))?;
+
+        let (mut density, mut temperature, mut emission) = (None, None, None);
+        for channel in lines[3].split_whitespace().skip(1) {
+            let values = read_floats(reader, count)?;
+            match channel {
+                "density" => density = Some(values),
+                "temperature" => temperature = Some(values),
+                "emission" => emission = Some(values),
+                _ => return Err(invalid_data(&format!("unknown channel '{channel}'"))),
+            }
+        }
+        let density = density.ok_or_else(|| invalid_data("voxel grid has no density channel"))?;
+        let mut grid = VoxelGrid::new(bounds, dims[0], dims[1], dims[2], density);
+        grid.temperature = temperature;
+        grid.emission = emission;
+        Ok(grid)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut channels = vec![("density", &self.density)];
+        if let Some(temperature) = &self.temperature {
+            channels.push(("temperature", temperature));
+        }
+        if let Some(emission) = &self.emission {
+            channels.push(("emission", emission));
+        }
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        writeln!(writer, "VOXELGRID 1")?;
+        writeln!(writer, "dims {} {} {}", self.nx, self.ny, self.nz)?;
+        writeln!(
+            writer,
+            "bounds {} {} {} {} {} {}",
+            min.x, min.y, min.z, max.x, max.y, max.z
+        )?;
+        let names: Vec<&str> = channels.iter().map(|(name, _)| *name).collect();
+        writeln!(writer, "channels {}", names.join(" "))?;
+        writeln!(writer, "end")?;
+        for (_, values) in channels {
+            write_floats(writer, values)?;
+        }
+        Ok(())
+    }
+
+    pub fn max_density(&self) -> f32 {
+        self.density.iter().fold(0.0, |acc, &d| acc.max(d))
+    }
+
+    fn voxel(&self, values: &[f32], x: usize, y: usize, z: usize) -> f32 {
+        values[(z * self.ny + y) * self.nx + x]
+    }
+
+    /// Trilinearly interpolates the channel at the world space point `p`. Points outside the
+    /// grid bounds are zero.
+    pub fn lookup(&self, values: &[f32], p: Vec3) -> f32 {
+        if !self.bounds.contains(p) {
+            return 0.0;
+        }
+        let size = self.bounds.size();
+        let rel = p - self.bounds.min;
+        // continuous voxel coordinates, with voxel centres at integer positions
+        let coords = [
+            (rel.x / size.x * self.nx as f32 - 0.5, self.nx),
+            (rel.y / size.y * self.ny as f32 - 0.5, self.ny),
+            (rel.z / size.z * self.nz as f32 - 0.5, self.nz),
+        ];
+        let mut base = [0usize; 3];
+        let mut frac = [0.0f32; 3];
+        let mut next = [0usize; 3];
+        for (axis, (c, n)) in coords.iter().enumerate() {
+            let c = c.max(0.0).min((n - 1) as f32);
+            let i = (c.floor() as usize).min(n - 1);
+            base[axis] = i;
+            next[axis] = (i + 1).min(n - 1);
+            frac[axis] = c - i as f32;
+        }
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+        let corner = |dx: bool, dy: bool, dz: bool| {
+            self.voxel(
+                values,
+                if dx { next[0] } else { base[0] },
+                if dy { next[1] } else { base[1] },
+                if dz { next[2] } else { base[2] },
+            )
+        };
+        let c00 = lerp(
+            corner(false, false, false),
+            corner(true, false, false),
+            frac[0],
+        );
+        let c10 = lerp(
+            corner(false, true, false),
+            corner(true, true, false),
+            frac[0],
+        );
+        let c01 = lerp(
+            corner(false, false, true),
+            corner(true, false, true),
+            frac[0],
+        );
+        let c11 = lerp(corner(false, true, true), corner(true, true, true), frac[0]);
+        lerp(lerp(c00, c10, frac[1]), lerp(c01, c11, frac[1]), frac[2])
+    }
+
+    pub fn density_at(&self, p: Vec3) -> f32 {
+        self.lookup(&self.density, p)
+    }
+
+    pub fn temperature_at(&self, p: Vec3) -> f32 {
+        self.temperature
+            .as_ref()
+            .map_or(0.0, |values| self.lookup(values, p))
+    }
+
+    pub fn emission_at(&self, p: Vec3) -> f32 {
+        self.emission
+            .as_ref()
+            .map_or(0.0, |values| self.lookup(values, p))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VoxelGrid;
+    use crate::{aabb::Aabb, vec3::Vec3};
+
+    fn ramp() -> VoxelGrid {
+        // density increases along x from 0 to 1
+        let bounds = Aabb::new(Vec3::zeros(), Vec3::new(2.0, 1.0, 1.0));
+        VoxelGrid::new(bounds, 2, 1, 1, vec![0.0, 1.0])
+    }
+
+    #[test]
+    fn trilinear_lookup() {
+        let grid = ramp();
+        assert_eq!(grid.density_at(Vec3::new(0.5, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density_at(Vec3::new(1.0, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density_at(Vec3::new(1.5, 0.2, 0.9)), 1.0);
+        // clamped towards the boundary, zero outside
+        assert_eq!(grid.density_at(Vec3::new(1.9, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density_at(Vec3::new(2.5, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let mut grid = ramp();
+        grid.temperature = Some(vec![1000.0, 1500.0]);
+        let mut bytes = Vec::new();
+        grid.write(&mut bytes).unwrap();
+        let read = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.bounds, grid.bounds);
+        assert_eq!((read.nx, read.ny, read.nz), (2, 1, 1));
+        assert_eq!(read.density, grid.density);
+        assert_eq!(read.temperature, grid.temperature);
+        assert!(read.emission.is_none());
+    }
+
+    #[test]
+    fn rejects_bad_dims() {
+        let header = |dims: &str| {
+            format!("VOXELGRID 1\ndims {dims}\nbounds 0 0 0 1 1 1\nchannels density\nend\n")
+        };
+        for dims in &["0 0 0", "4 0 4", "4294967296 4294967296 2"] {
+            let text = header(dims);
+            assert!(VoxelGrid::read(&mut text.as_bytes()).is_err());
+        }
+        // a huge grid without the data to back it up fails without allocating it
+        let text = header("100000 100000 1000");
+        let error = VoxelGrid::read(&mut text.as_bytes()).err().unwrap();
+        assert!(error.to_string().contains("cut short"));
+    }
+}
@@ -0,0 +1,226 @@
+use rust_ray_trace::{
+    animation::{frame_path, render_sequence},
+    panoramic::render_cube_map,
+    pbrt, render, render_resumable, render_tiles, Camera, CameraAnimation, Checkpoint, CubeFace,
+    EquirectangularCamera, FisheyeCamera, FisheyeMapping, Framebuffer, Hitable, Material, Region,
+    RenderSettings, Sphere, TileOrder, Vec3, World,
+};
+use std::path::Path;
+
+fn camera(settings: &RenderSettings) -> Camera {
+    Camera::new(
+        Vec3::zeros(),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        60.0,
+        settings.aspect(),
+        0.0,
+        1.0,
+    )
+}
+
+#[test]
+fn empty_world_shows_the_background() {
+    let settings = RenderSettings::new(8, 8, 1);
+    let image = render(&settings, &camera(&settings), &World::new(Vec::new()));
+    assert_eq!((image.width, image.height), (8, 8));
+    assert_eq!(image.pixels.len(), 64);
+    // the default background fades from white at the horizon to blue overhead
+    let top = image.pixel(4, 0);
+    let bottom = image.pixel(4, 7);
+    assert!(top.z > top.x && top.x < bottom.x);
+}
+
+#[test]
+fn sphere_in_front_of_the_camera() {
+    let settings = RenderSettings::new(16, 16, 16).with_max_depth(4);
+    let red = Material::lambertian(Vec3::new(0.9, 0.1, 0.1));
+    let world = World::new(vec![Hitable::Sphere(Sphere::new(
+        Vec3::new(0.0, 0.0, -3.0),
+        1.0,
+        red,
+    ))]);
+    let image = render(&settings, &camera(&settings), &world);
+    let centre = image.pixel(8, 8);
+    let corner = image.pixel(0, 0);
+    assert!(centre.x > 2.0 * centre.y);
+    assert!(corner.z > corner.x);
+    assert!(image
+        .pixels
+        .iter()
+        .all(|p| p.x.is_finite() && p.y.is_finite() && p.z.is_finite()));
+}
+
+#[test]
+fn framebuffer_encoding() {
+    let mut image = Framebuffer::new(2, 1);
+    image.pixels[0] = Vec3::new(0.25, 1.0, 4.0);
+    assert_eq!(image.to_rgb8(), vec![[127, 255, 255], [0, 0, 0]]);
+    assert_eq!(image.to_bgra(), vec![0xff7f_ffff, 0xff00_0000]);
+}
+
+#[test]
+fn pbrt_scene_settings_drive_the_render() {
+    let text = r#"
+LookAt 0 0 -4  0 0 0  0 1 0
+Camera "perspective" "float fov" 40
+Film "image" "integer xresolution" 12 "integer yresolution" 6
+Sampler "random" "integer pixelsamples" 2
+WorldBegin
+LightSource "infinite" "rgb L" [0.5 0.5 0.5]
+Shape "sphere"
+WorldEnd
+"#;
+    let scene = pbrt::parse(text, Path::new(".")).unwrap();
+    assert!(scene.warnings.is_empty());
+    let settings = RenderSettings::new(scene.width, scene.height, scene.samples);
+    let image = render(&settings, &scene.camera, &scene.world);
+    assert_eq!(image.pixels.len(), 72);
+    // the matte sphere reflects at most the uniform environment around it
+    let centre = image.pixel(6, 3);
+    assert!(centre.x > 0.0 && centre.x < 0.5);
+    assert!((image.pixel(0, 0).x - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn panoramic_cameras() {
+    let world = World::new(Vec::new());
+    let up = Vec3::new(0.0, 1.0, 0.0);
+
+    // the whole sky in one image, with the zenith along the top row
+    let settings = RenderSettings::new(16, 8, 1);
+    let camera = EquirectangularCamera::new(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0), up);
+    let image = render(&settings, &camera, &world);
+    assert!(image.pixel(3, 0).x < image.pixel(11, 7).x);
+
+    // nothing is seen outside of the image circle
+    let fisheye = FisheyeCamera::new(
+        Vec3::zeros(),
+        Vec3::new(0.0, 0.0, -1.0),
+        up,
+        180.0,
+        FisheyeMapping::Equisolid,
+        settings.aspect(),
+    );
+    let image = render(&settings, &fisheye, &world);
+    assert_eq!(image.pixel(0, 4), Vec3::zeros());
+    assert!(image.pixel(8, 4).x > 0.0);
+
+    let faces = render_cube_map(&RenderSettings::new(4, 4, 1), Vec3::zeros(), &world);
+    assert_eq!(faces.len(), 6);
+    assert_eq!(faces[2].0, CubeFace::PositiveY);
+}
+
+#[test]
+fn animation_frames_are_written_to_files() {
+    let animation = CameraAnimation::parse(
+        "0 0 0 0  0 0 -1  60 1 0
+         4 0 0 0  0 1 -1  60 1 0",
+    )
+    .unwrap();
+    let directory = std::env::temp_dir().join(format!("frames-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let pattern = directory.join("frame_##.ppm");
+    let pattern = pattern.to_str().unwrap();
+    let mut written = Vec::new();
+    render_sequence(
+        &RenderSettings::new(4, 2, 1),
+        &animation,
+        &World::new(Vec::new()),
+        2..=3,
+        pattern,
+        |frame, path| written.push((frame, path.to_path_buf())),
+    )
+    .unwrap();
+    assert_eq!(
+        written,
+        vec![(2, frame_path(pattern, 2)), (3, frame_path(pattern, 3))]
+    );
+    let data = std::fs::read(directory.join("frame_03.ppm")).unwrap();
+    assert!(data.starts_with(b"P6\n4 2\n255\n"));
+    assert_eq!(data.len(), 11 + 4 * 2 * 3);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn tiles_and_crop_regions() {
+    let settings = RenderSettings::new(20, 12, 1)
+        .with_tiles(8, TileOrder::Hilbert)
+        .with_crop(Region::new(4, 2, 30, 6));
+    let world = World::new(Vec::new());
+    let done = std::sync::Mutex::new(Vec::new());
+    let image = render_tiles(&settings, &camera(&settings), &world, |tile, pixels| {
+        assert_eq!(pixels.len(), tile.width * tile.height);
+        done.lock().unwrap().push(tile);
+    });
+    // the crop region is clipped to the image, and only it is rendered
+    let crop = settings.region();
+    assert_eq!(crop, Region::new(4, 2, 16, 6));
+    let done = done.into_inner().unwrap();
+    assert_eq!(
+        done.iter().map(|t| t.width * t.height).sum::<usize>(),
+        16 * 6
+    );
+    for y in 0..12 {
+        for x in 0..20 {
+            assert_eq!(image.pixel(x, y) != Vec3::zeros(), crop.contains(x, y));
+        }
+    }
+}
+
+#[test]
+fn resumed_renders_match_uninterrupted_ones() {
+    let settings = RenderSettings::new(12, 8, 8)
+        .with_max_depth(4)
+        .with_tiles(5, TileOrder::Scanline)
+        .with_seed(3);
+    // depth of field and a diffuse sphere, so that every sample uses many random numbers
+    let camera = Camera::new(
+        Vec3::zeros(),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        60.0,
+        settings.aspect(),
+        0.1,
+        3.0,
+    );
+    let world = World::new(vec![Hitable::Sphere(Sphere::new(
+        Vec3::new(0.0, 0.0, -3.0),
+        1.0,
+        Material::lambertian(Vec3::new(0.5, 0.5, 0.5)),
+    ))]);
+    let uninterrupted = render(&settings, &camera, &world);
+    assert_ne!(
+        render(&settings.with_seed(4), &camera, &world),
+        uninterrupted
+    );
+
+    // the render is cut short after its first pass has been saved
+    let path = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+    let mut checkpoint = Checkpoint::new(&settings);
+    let interrupted = render_resumable(&settings, &camera, &world, &mut checkpoint, 3, |c| {
+        c.save(&path)?;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "reboot",
+        ))
+    });
+    assert!(interrupted.is_err());
+
+    // resumed with fewer samples first, and then more added
+    let mut checkpoint = Checkpoint::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(checkpoint.samples(0, 0), 3);
+    let fewer = RenderSettings {
+        samples: 5,
+        ..settings
+    };
+    let image = render_resumable(&fewer, &camera, &world, &mut checkpoint, 1, |_| Ok(())).unwrap();
+    assert_eq!(image, render(&fewer, &camera, &world));
+    let image =
+        render_resumable(&settings, &camera, &world, &mut checkpoint, 2, |_| Ok(())).unwrap();
+    assert_eq!(image, uninterrupted);
+
+    let other = RenderSettings::new(6, 4, 8);
+    assert!(render_resumable(&other, &camera, &world, &mut checkpoint, 2, |_| Ok(())).is_err());
+}